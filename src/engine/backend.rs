@@ -10,12 +10,24 @@ use std::sync::Arc;
 use super::driver_traits::*;
 
 use super::metronome::AudioMetronome;
-use super::midiclock::MidiClock;
+use super::output_bus::{OutputBus, apply_gain};
+use super::ramp::LinearRamp;
+use super::midiclock::{MidiClock, PendingTransportStart, queue_clock_ticks};
+use super::midiclock_slave::{MidiClockSlave, TransportChange, LoopLengthSuggestion};
 use super::midi_registry::MidiNoteRegistry;
 
 use assert_no_alloc::assert_no_alloc;
 use crate::realtime_send_queue;
 
+/// Deviations from an external song position (MIDI clock or JACK transport), in frames,
+/// that are tolerated
+const SYNC_DEAD_BAND: i64 = 64;
+/// Resolution of the BBT position published as JACK timebase master
+const TICKS_PER_BEAT: f64 = 1920.0;
+/// Capacity of the queue to the destructor thread. The command queue holds 16 messages, each
+/// causing at most one destruction request, so this lets the destructor lag 16 periods behind.
+const DESTRUCTOR_QUEUE_LENGTH: usize = 256;
+
 fn for_first<T: intrusive_collections::Adapter, R>(
 	list: &mut LinkedList<T>,
 	func: impl Fn (&<<T as intrusive_collections::Adapter>::PointerOps as intrusive_collections::PointerOps>::Value)->Option<R>
@@ -32,6 +44,23 @@ where T::LinkOps: intrusive_collections::linked_list::LinkedListOps,
 	Err(())
 }
 
+/** Unlinks the first node for which `func` returns true from the list and returns it. */
+fn remove_first<T: intrusive_collections::Adapter>(
+	list: &mut LinkedList<T>,
+	func: impl Fn (&<<T as intrusive_collections::Adapter>::PointerOps as intrusive_collections::PointerOps>::Value)->bool
+) -> Option<<<T as intrusive_collections::Adapter>::PointerOps as intrusive_collections::PointerOps>::Pointer>
+where T::LinkOps: intrusive_collections::linked_list::LinkedListOps,
+{
+	let mut cursor = list.front_mut();
+	while let Some(node) = cursor.get() {
+		if func(node) {
+			return cursor.remove();
+		}
+		cursor.move_next();
+	}
+	None
+}
+
 macro_rules! for_take {
 	($list:expr, $id:expr, $take:ident -> $code:block) => {{
 		let id = $id;
@@ -48,13 +77,15 @@ macro_rules! for_take {
 }
 
 pub struct AudioDeviceData {
-	echo: bool
+	echo: bool,
+	gain: LinearRamp,
 }
 
 impl AudioDeviceData {
 	pub fn new() -> AudioDeviceData {
 		AudioDeviceData {
-			echo: false
+			echo: false,
+			gain: LinearRamp::new(1.0),
 		}
 	}
 }
@@ -62,6 +93,11 @@ impl AudioDeviceData {
 pub struct MidiDeviceData {
 	start_transport_pending: bool,
 	stop_transport_pending: bool,
+	/// Start (0xFA) or Continue (0xFB) and the Song Position Pointer preceding it, to be sent
+	/// when the song reaches the next sixteenth note.
+	transport_message_pending: Option<PendingTransportStart>,
+	/// Offset of the clock ticks sent to this device, or None if it does not receive clock.
+	clock_offset: Option<i32>,
 	registry: MidiNoteRegistry,
 }
 
@@ -70,6 +106,8 @@ impl MidiDeviceData {
 		MidiDeviceData {
 			start_transport_pending: false,
 			stop_transport_pending: false,
+			transport_message_pending: None,
+			clock_offset: None,
 			registry: MidiNoteRegistry::new(),
 		}
 	}
@@ -80,15 +118,46 @@ pub struct AudioThreadState<Driver: DriverTrait>
 	devices: Vec<Option<(Driver::AudioDev, AudioDeviceData)>>,
 	mididevices: Vec<Option<(Driver::MidiDev, MidiDeviceData)>>,
 	metronome: AudioMetronome<Driver::AudioDev>,
+	/// Carries the unmuted takes, e.g. to the main speakers.
+	master_bus: OutputBus<Driver::AudioDev>,
+	/// Carries the metronome, echoed inputs and cued takes, e.g. to the performer's headphones.
+	monitor_bus: OutputBus<Driver::AudioDev>,
+	/// Level of the metronome within the monitor bus.
+	metronome_gain: LinearRamp,
 	midiclock: MidiClock<Driver::MidiDev>,
+	/// MIDI device whose clock and transport messages the song follows. While set, the
+	/// internal MIDI clock is not sent.
+	clock_source: Option<usize>,
+	clock_slave: MidiClockSlave,
+	transport: Driver::Transport,
+	jack_transport_mode: JackTransportMode,
+	jack_loop_length_suggestion: LoopLengthSuggestion,
 	audiotakes: LinkedList<AudioTakeAdapter>,
 	miditakes: LinkedList<MidiTakeAdapter>,
+	/// Takes that have been removed from playback but may be restored later
+	retired_audiotakes: LinkedList<AudioTakeAdapter>,
+	retired_miditakes: LinkedList<MidiTakeAdapter>,
 	command_channel: ringbuf::Consumer<Message<Driver::AudioDev, Driver::MidiDev>>,
 	sample_rate: u32,
 	transport_position: u32, // does not wrap 
 	song_position: u32, // wraps
 	song_length: u32,
+	/// Number of times the song has wrapped since the last rewind. Used for the Song Position
+	/// Pointer, so that devices with patterns longer than the loop stay in step.
+	loop_count: u32,
+	/// Whether the song has wrapped since the last drift correction. Small deviations from an
+	/// external song position are only corrected once per loop, right after the loop start.
+	drift_correction_due: bool,
+	/// While stopped, neither the song nor the transport position advance and no takes are
+	/// played or recorded.
+	playing: bool,
+	/// While set, waiting takes start recording right away instead of at the loop start, and
+	/// the metronome and the MIDI clock are silent. Used to define the loop length by the
+	/// first recording.
+	free_running: bool,
 	n_beats: u32,
+	/// Rescaled takes waiting to be swapped in at the next loop boundary
+	pending_song_length_change: Option<Box<SongLengthChange>>,
 	shared: Arc<SharedThreadState>,
 	event_channel: realtime_send_queue::Producer<Event>,
 	destructor_thread_handle: std::thread::JoinHandle<()>,
@@ -107,17 +176,26 @@ impl<Driver: DriverTrait> Drop for AudioThreadState<Driver>
 impl<Driver: DriverTrait> AudioThreadState<Driver>
 {
 	// FIXME this function signature sucks
-	pub fn new(sample_rate: u32, audiodevices: Vec<Driver::AudioDev>, mididevices: Vec<Driver::MidiDev>, metronome: AudioMetronome<Driver::AudioDev>, midiclock: MidiClock<Driver::MidiDev>, command_channel: ringbuf::Consumer<Message<Driver::AudioDev, Driver::MidiDev>>, song_length: u32, shared: Arc<SharedThreadState>, event_channel: realtime_send_queue::Producer<Event>) -> AudioThreadState<Driver>
+	pub fn new(sample_rate: u32, audiodevices: Vec<Driver::AudioDev>, mididevices: Vec<Driver::MidiDev>, metronome: AudioMetronome<Driver::AudioDev>, master_bus: OutputBus<Driver::AudioDev>, monitor_bus: OutputBus<Driver::AudioDev>, midiclock: MidiClock<Driver::MidiDev>, transport: Driver::Transport, command_channel: ringbuf::Consumer<Message<Driver::AudioDev, Driver::MidiDev>>, song_length: u32, shared: Arc<SharedThreadState>, event_channel: realtime_send_queue::Producer<Event>) -> AudioThreadState<Driver>
 	{
-		let (destruction_sender, mut destruction_receiver) = ringbuf::RingBuffer::new(32).split();
+		let (destruction_sender, mut destruction_receiver) = ringbuf::RingBuffer::new(DESTRUCTOR_QUEUE_LENGTH).split();
+		let destructor_shared = shared.clone();
+		let device_destroyed = move || {
+			*destructor_shared.destroyed_devices.lock().unwrap() += 1;
+			destructor_shared.device_destroyed.notify_all();
+		};
 		let destructor_thread_handle = std::thread::spawn(move || {
 			loop {
 				std::thread::park();
 				println!("Handling deconstruction request");
 				while let Some(request) = destruction_receiver.pop() {
 					match request {
-						DestructionRequest::AudioDevice(dev) => std::mem::drop(dev),
-						DestructionRequest::MidiDevice(dev) => std::mem::drop(dev),
+						DestructionRequest::AudioDevice(dev) => { std::mem::drop(dev); device_destroyed(); }
+						DestructionRequest::MidiDevice(dev) => { std::mem::drop(dev); device_destroyed(); }
+						DestructionRequest::AudioTake(take) => std::mem::drop(take),
+						DestructionRequest::MidiTake(take) => std::mem::drop(take),
+						DestructionRequest::SongLengthChange(change) => std::mem::drop(change),
+						DestructionRequest::MetronomeSound(sound) => std::mem::drop(sound),
 						DestructionRequest::End => {println!("destructor thread exiting..."); break;}
 					}
 				}
@@ -128,15 +206,30 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 			devices: pad_option_vec(audiodevices.into_iter().map(|d| (d, AudioDeviceData::new())), 32),
 			mididevices: pad_option_vec(mididevices.into_iter().map(|d| (d, MidiDeviceData::new())), 32),
 			metronome,
+			master_bus,
+			monitor_bus,
+			metronome_gain: LinearRamp::new(1.0),
 			midiclock,
+			clock_source: None,
+			clock_slave: MidiClockSlave::new(sample_rate),
+			transport,
+			jack_transport_mode: JackTransportMode::Ignore,
+			jack_loop_length_suggestion: LoopLengthSuggestion::new(sample_rate),
 			audiotakes: LinkedList::new(AudioTakeAdapter::new()),
 			miditakes: LinkedList::new(MidiTakeAdapter::new()),
+			retired_audiotakes: LinkedList::new(AudioTakeAdapter::new()),
+			retired_miditakes: LinkedList::new(MidiTakeAdapter::new()),
 			command_channel,
 			sample_rate,
 			transport_position: 0,
 			song_position: 0,
 			song_length,
+			loop_count: 0,
+			drift_correction_due: false,
+			playing: true,
+			free_running: false,
 			n_beats: 4,
+			pending_song_length_change: None,
 			shared,
 			event_channel,
 			destructor_thread_handle,
@@ -148,34 +241,85 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 		assert_no_alloc(||{
 			assert!(scope.n_frames() < self.song_length);
 
-			self.metronome.process(self.song_position, self.song_length, self.n_beats, self.sample_rate, scope);
-			self.midiclock.process(self.song_position, self.song_length, self.n_beats, scope);
-
+			// transport changes take effect right at the beginning of the period
 			self.process_command_channel();
+			self.process_clock_slave(scope);
+			let jack_position = self.process_jack_transport(scope.n_frames());
+
+			if self.playing && !self.free_running && self.metronome.is_active(self.is_counting_in_or_recording()) {
+				self.metronome.process(self.song_position, self.song_length, self.n_beats, self.sample_rate, scope);
+			}
+			else {
+				play_silence(scope, self.metronome.device_mut(), 0..scope.n_frames());
+			}
+			if self.playing && !self.free_running && self.clock_source.is_none() {
+				self.midiclock.process(self.song_position, self.song_length, self.n_beats, scope);
+			}
+			else {
+				self.midiclock.process_stopped(scope);
+			}
 
 			self.process_audio_playback(scope);
+			self.process_output_buses(scope);
 			self.process_midi_playback(scope);
 
-			self.process_audio_recording(scope);
+			if self.playing {
+				self.process_audio_recording(scope);
+			}
 			self.process_midi_recording(scope);
 
-			self.song_position = self.song_position + scope.n_frames();
-			let song_wraps = self.song_position >= self.song_length;
-			self.song_position %= self.song_length;
-			self.transport_position += scope.n_frames();
+			let mut song_length_changed = false;
+			if self.playing {
+				self.song_position = self.song_position + scope.n_frames();
+				let song_wraps = self.song_position >= self.song_length;
+				self.song_position %= self.song_length;
+				self.transport_position += scope.n_frames();
+				if song_wraps {
+					self.loop_count = self.loop_count.wrapping_add(1);
+					self.drift_correction_due = true;
+				}
+
+				song_length_changed = song_wraps && self.apply_song_length_change();
 
-			if song_wraps {
-				self.event_channel.send_or_complain(Event::Timestamp(self.song_position, self.transport_position));
+				if song_wraps {
+					self.event_channel.send_or_complain(Event::Timestamp(self.song_position, self.transport_position));
+				}
+
+				if !song_length_changed {
+					let target = match jack_position {
+						Some(position) => Some((position + scope.n_frames()) % self.song_length),
+						None if self.clock_source.is_some() => self.clock_slave.song_position(self.song_length, self.n_beats),
+						None => None
+					};
+					if let Some(target) = target {
+						self.follow_position(target);
+					}
+				}
+			}
+			else if let Some(new_length) = self.pending_song_length_change.as_ref().map(|change| change.song_length) {
+				// there is no wrap to wait for, so keep the relative position and apply right away
+				self.song_position = (self.song_position as u64 * new_length as u64 / self.song_length as u64) as u32;
+				song_length_changed = self.apply_song_length_change();
 			}
 
 			self.shared.song_length.store(self.song_length, std::sync::atomic::Ordering::Relaxed);
 			self.shared.song_position.store(self.song_position, std::sync::atomic::Ordering::Relaxed);
 			self.shared.transport_position.store(self.transport_position, std::sync::atomic::Ordering::Relaxed);
+			self.shared.playing.store(self.playing, std::sync::atomic::Ordering::Relaxed);
+			if song_length_changed {
+				self.shared.song_length_change_pending.store(false, std::sync::atomic::Ordering::Release);
+			}
 		});
 	}
 
 	fn process_command_channel(&mut self) {
 		loop {
+			// a command submits at most one destruction request, and one slot is kept for the song
+			// length change at the end of the period. If the destructor thread lags behind, the
+			// remaining commands stay queued until the next period.
+			if self.destructor_channel.remaining() < 2 {
+				break;
+			}
 			match self.command_channel.pop() {
 				Some(msg) => {
 					match msg {
@@ -184,6 +328,17 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 							self.song_length = song_length;
 							self.n_beats = n_beats;
 							self.transport_position = 0;
+							self.loop_count = 0;
+						}
+						Message::ChangeSongLength(change) => {
+							// the frontend sends one change at a time. Should a second one arrive
+							// anyway, it is dropped instead of panicking in the audio thread.
+							if self.pending_song_length_change.is_none() {
+								self.pending_song_length_change = Some(change);
+							}
+							else {
+								self.submit_destruction_request(DestructionRequest::SongLengthChange(change));
+							}
 						}
 						Message::UpdateAudioDevice(id, device) => {
 							// FrontendThreadState has verified that audiodev_id isn't currently used by any take
@@ -197,12 +352,7 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 							std::mem::swap(&mut self.devices[id], &mut devtuple);
 							
 							if let Some((old, _)) = devtuple {
-								#[cfg(feature = "debug_print_in_audio_thread")]
-								println!("submitting deconstruction request");
-								if self.destructor_channel.push(DestructionRequest::AudioDevice(old)).is_err() {
-									panic!("Failed to submit deconstruction request");
-								}
-								self.destructor_thread_handle.thread().unpark();
+								self.submit_destruction_request(DestructionRequest::AudioDevice(old));
 							}
 						}
 						Message::UpdateMidiDevice(id, device) => {
@@ -217,29 +367,95 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 							std::mem::swap(&mut self.mididevices[id], &mut devtuple);
 
 							if let Some((old, _)) = devtuple {
-								#[cfg(feature = "debug_print_in_audio_thread")]
-								println!("submitting deconstruction request");
-								if self.destructor_channel.push(DestructionRequest::MidiDevice(old)).is_err() {
-									panic!("Failed to submit deconstruction request");
-								}
-								self.destructor_thread_handle.thread().unpark();
+								self.submit_destruction_request(DestructionRequest::MidiDevice(old));
 							}
 						}
 						Message::SetAudioEcho(id, echo) => {
 							self.devices[id].as_mut().unwrap().1.echo = echo;
 						}
+						Message::SetAudioDeviceGain(id, gain) => {
+							let ramp_length = self.gain_ramp_length();
+							self.devices[id].as_mut().unwrap().1.gain.set_target(gain, ramp_length);
+						}
+						Message::SetBusGains(master_gain, monitor_gain, metronome_gain) => {
+							let ramp_length = self.gain_ramp_length();
+							self.master_bus.set_gain(master_gain);
+							self.monitor_bus.set_gain(monitor_gain);
+							self.metronome_gain.set_target(metronome_gain, ramp_length);
+						}
+						Message::SetMetronome(settings) => {
+							self.metronome.set_settings(settings);
+						}
+						Message::SetMetronomeSound(which, sound) => {
+							if let Some(old) = self.metronome.replace_sound(which, sound.map(|sound| sound.0)) {
+								self.submit_destruction_request(DestructionRequest::MetronomeSound(old));
+							}
+						}
 						Message::RestartMidiTransport(id) => {
 							self.mididevices[id].as_mut().unwrap().1.start_transport_pending = true;
 							self.mididevices[id].as_mut().unwrap().1.stop_transport_pending = true;
 						}
+						Message::StartTransport(rewind) => {
+							if rewind && !self.playing {
+								self.rewind();
+							}
+							self.start_transport();
+						}
+						Message::StopTransport(rewind) => {
+							self.stop_transport();
+							if rewind {
+								self.rewind();
+							}
+						}
+						Message::SetMidiClock(enabled, offset) => {
+							self.midiclock.configure(enabled, offset);
+						}
+						Message::SetMidiDeviceClock(id, offset) => {
+							self.mididevices[id].as_mut().unwrap().1.clock_offset = offset;
+						}
+						Message::SetFreeRunning(free_running) => {
+							if free_running && !self.free_running && self.playing {
+								self.send_transport_stop();
+							}
+							self.free_running = free_running;
+						}
+						Message::EndFreeRunning(song_length, n_beats, take_id) => {
+							self.end_free_running(song_length, n_beats, take_id);
+						}
+						Message::SetClockSource(id) => {
+							self.clock_source = id;
+							self.clock_slave = MidiClockSlave::new(self.sample_rate);
+						}
+						Message::SetJackTransportMode(mode) => {
+							self.jack_transport_mode = mode;
+							self.jack_loop_length_suggestion = LoopLengthSuggestion::new(self.sample_rate);
+						}
 						Message::NewAudioTake(take) => {
 							#[cfg(feature = "debug_print_in_audio_thread")]
 							println!("\ngot take");
+							{
+								let mut t = take.take.borrow_mut();
+								if t.record_state == RecordState::Finished {
+									// takes that are already finished start playing in sync with the song
+									let length = t.length.unwrap();
+									let latency = self.devices[t.audiodev_id].as_ref().unwrap().0.playback_latency();
+									t.seek((self.song_position + latency) % length);
+								}
+							}
 							self.audiotakes.push_back(take);
 						}
 						Message::NewMidiTake(take) => {
 							#[cfg(feature = "debug_print_in_audio_thread")]
 							println!("\ngot miditake");
+							{
+								let mut t = take.take.borrow_mut();
+								if t.record_state == RecordState::Finished {
+									// takes that are already finished start playing in sync with the song
+									let length = t.length.unwrap();
+									let latency = self.mididevices[t.mididev_id].as_ref().unwrap().0.playback_latency();
+									t.seek((self.song_position + latency) % length);
+								}
+							}
 							self.miditakes.push_back(take);
 						}
 						Message::FinishAudioTake(id, length) => {
@@ -262,19 +478,150 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 								Some(())
 							}).expect("could not find take to mute");
 						}
+						Message::UnfinishAudioTake(id) => {
+							let devices = &self.devices;
+							for_take!(&mut self.audiotakes, id, t -> {
+								// once finished, the take has stopped recording and cannot be continued
+								if t.record_state == RecordState::Recording {
+									let dev = &devices[t.audiodev_id].as_ref().unwrap().0;
+									let latency = dev.playback_latency() + dev.capture_latency();
+									t.length = None;
+									// undo a seek done by FinishAudioTake
+									let position = t.recorded_length + latency;
+									t.seek(position);
+								}
+								Some(())
+							}).expect("could not find take to unfinish");
+						}
+						Message::UnfinishMidiTake(id) => {
+							let mididevices = &self.mididevices;
+							for_take!(&mut self.miditakes, id, t -> {
+								if t.record_state == RecordState::Recording {
+									let dev = &mididevices[t.mididev_id].as_ref().unwrap().0;
+									let latency = dev.playback_latency() + dev.capture_latency();
+									t.length = None;
+									let position = t.recorded_length + latency;
+									t.seek(position);
+								}
+								Some(())
+							}).expect("could not find take to unfinish");
+						}
 						Message::SetAudioMute(id, unmuted) => {
 							for_take!(&mut self.audiotakes, id, t -> {
 								t.unmuted = unmuted;
+								t.scheduled_unmute = None;
 								Some(())
 							}).expect("could not find take to mute");
 						}
 						Message::SetMidiMute(id, unmuted) => {
 							for_take!(&mut self.miditakes, id, t -> {
 								t.unmuted = unmuted;
+								t.scheduled_unmute = None;
+								Some(())
+							}).expect("could not find take to mute");
+						}
+						Message::ScheduleAudioMute(id, unmuted) => {
+							for_take!(&mut self.audiotakes, id, t -> {
+								t.scheduled_unmute = unmuted;
 								Some(())
 							}).expect("could not find take to mute");
 						}
-						_ => { unimplemented!() }
+						Message::ScheduleMidiMute(id, unmuted) => {
+							for_take!(&mut self.miditakes, id, t -> {
+								t.scheduled_unmute = unmuted;
+								Some(())
+							}).expect("could not find take to mute");
+						}
+						Message::SetAudioGain(id, gain, pan) => {
+							for_take!(&mut self.audiotakes, id, t -> {
+								t.gain = gain;
+								t.pan = pan;
+								Some(())
+							}).expect("could not find take to change gain");
+						}
+						Message::SetAudioCue(id, cued) => {
+							for_take!(&mut self.audiotakes, id, t -> {
+								t.cued = cued;
+								Some(())
+							}).expect("could not find take to cue");
+						}
+						Message::SetAudioOverdub(id, feedback) => {
+							for_take!(&mut self.audiotakes, id, t -> {
+								t.overdub_feedback = feedback;
+								Some(())
+							}).expect("could not find take to overdub");
+						}
+						Message::DeleteTake(id) => {
+							// take ids are unique across audio and midi takes
+							if let Some(node) = remove_first(&mut self.audiotakes, |node| node.take.borrow().id == id) {
+								#[cfg(feature = "debug_print_in_audio_thread")]
+								println!("\ndeleting audio take");
+								self.submit_destruction_request(DestructionRequest::AudioTake(node));
+							}
+							else if let Some(node) = remove_first(&mut self.miditakes, |node| node.take.borrow().id == id) {
+								#[cfg(feature = "debug_print_in_audio_thread")]
+								println!("\ndeleting midi take");
+								{
+									let t = node.take.borrow();
+									if t.unmuted {
+										let dev = &mut self.mididevices[t.mididev_id].as_mut().unwrap().0;
+										t.note_registry.borrow_mut().send_noteoffs(dev);
+									}
+								}
+								self.submit_destruction_request(DestructionRequest::MidiTake(node));
+							}
+							else if let Some(node) = remove_first(&mut self.retired_audiotakes, |node| node.take.borrow().id == id) {
+								self.submit_destruction_request(DestructionRequest::AudioTake(node));
+							}
+							else if let Some(node) = remove_first(&mut self.retired_miditakes, |node| node.take.borrow().id == id) {
+								self.submit_destruction_request(DestructionRequest::MidiTake(node));
+							}
+							else {
+								self.event_channel.send_or_complain(Event::TakeNotFound(id, TakeOperation::Delete));
+							}
+						}
+						Message::RetireTake(id) => {
+							if let Some(node) = remove_first(&mut self.audiotakes, |node| node.take.borrow().id == id) {
+								self.retired_audiotakes.push_back(node);
+							}
+							else if let Some(node) = remove_first(&mut self.miditakes, |node| node.take.borrow().id == id) {
+								{
+									let t = node.take.borrow();
+									if t.unmuted {
+										let dev = &mut self.mididevices[t.mididev_id].as_mut().unwrap().0;
+										t.note_registry.borrow_mut().send_noteoffs(dev);
+									}
+								}
+								self.retired_miditakes.push_back(node);
+							}
+							else {
+								self.event_channel.send_or_complain(Event::TakeNotFound(id, TakeOperation::Retire));
+							}
+						}
+						Message::RestoreTake(id) => {
+							// restored takes are finished and continue playing in sync with the song
+							if let Some(node) = remove_first(&mut self.retired_audiotakes, |node| node.take.borrow().id == id) {
+								{
+									let mut t = node.take.borrow_mut();
+									let length = t.length.unwrap();
+									let latency = self.devices[t.audiodev_id].as_ref().unwrap().0.playback_latency();
+									t.seek((self.song_position + latency) % length);
+								}
+								self.audiotakes.push_back(node);
+							}
+							else if let Some(node) = remove_first(&mut self.retired_miditakes, |node| node.take.borrow().id == id) {
+								{
+									let mut t = node.take.borrow_mut();
+									let length = t.length.unwrap();
+									let latency = self.mididevices[t.mididev_id].as_ref().unwrap().0.playback_latency();
+									t.seek((self.song_position + latency) % length);
+								}
+								self.miditakes.push_back(node);
+							}
+							else {
+								self.event_channel.send_or_complain(Event::TakeNotFound(id, TakeOperation::Restore));
+							}
+						}
 					}
 				}
 				None => { break; }
@@ -282,35 +629,503 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 		}
 	}
 
+	/// Starts playing and sends Start or Continue, preceded by the Song Position Pointer, to all
+	/// MIDI devices.
+	fn start_transport(&mut self) {
+		if !self.playing {
+			self.playing = true;
+			// while free-running, the MIDI devices are started once the loop length is known
+			if !self.free_running {
+				self.send_transport_start();
+			}
+		}
+	}
+
+	/// The Song Position Pointer can only express sixteenth notes, so Start or Continue are sent
+	/// when the song reaches the next one, compensating for each device's latency and offset.
+	fn send_transport_start(&mut self) {
+		// the number of frames since the last rewind
+		let position = self.loop_count as i64 * self.song_length as i64 + self.song_position as i64;
+		if self.clock_source.is_none() {
+			self.midiclock.send_transport_start(position, self.song_length, self.n_beats);
+		}
+		for d in self.mididevices.iter_mut() {
+			if let Some((dev, data)) = d {
+				let compensated = position + dev.playback_latency() as i64 - data.clock_offset.unwrap_or(0) as i64;
+				data.transport_message_pending = Some(PendingTransportStart::new(compensated, self.song_length, self.n_beats));
+			}
+		}
+	}
+
+	fn send_transport_stop(&mut self) {
+		if self.clock_source.is_none() {
+			self.midiclock.send_transport_stop();
+		}
+		for d in self.mididevices.iter_mut() {
+			if let Some((_, data)) = d {
+				data.stop_transport_pending = true;
+				data.start_transport_pending = false;
+				data.transport_message_pending = None;
+			}
+		}
+	}
+
+	/// Ends free-running with a loop of `song_length` frames, which starts where the take
+	/// `take_id` has started recording. The metronome and the MIDI clock start right away.
+	fn end_free_running(&mut self, song_length: u32, n_beats: u32, take_id: u32) {
+		self.free_running = false;
+		self.song_length = song_length;
+		self.n_beats = n_beats;
+		self.loop_count = 0;
+
+		let devices = &self.devices;
+		let mididevices = &self.mididevices;
+		let recorded = self.audiotakes.iter()
+			.map(|node| node.take.borrow())
+			.find(|t| t.id == take_id)
+			.map(|t| t.recorded_length + devices[t.audiodev_id].as_ref().unwrap().0.capture_latency())
+			.or_else(|| self.miditakes.iter()
+				.map(|node| node.take.borrow())
+				.find(|t| t.id == take_id)
+				.map(|t| t.recorded_length + mididevices[t.mididev_id].as_ref().unwrap().0.capture_latency())
+			);
+		self.song_position = recorded.unwrap_or(0) % song_length;
+
+		if self.playing {
+			self.send_transport_start();
+		}
+	}
+
+	fn stop_transport(&mut self) {
+		if self.playing {
+			self.playing = false;
+			if !self.free_running {
+				self.send_transport_stop();
+			}
+			for node in self.miditakes.iter() {
+				let t = node.take.borrow();
+				if t.unmuted {
+					let dev = &mut self.mididevices[t.mididev_id].as_mut().unwrap().0;
+					t.note_registry.borrow_mut().send_noteoffs(dev);
+				}
+			}
+		}
+	}
+
+	/// Feeds the clock and transport messages received from the clock source into the clock
+	/// slave and starts or stops the transport as requested.
+	fn process_clock_slave(&mut self, scope: &Driver::ProcessScope) {
+		let mididevices = &self.mididevices;
+		let dev = match self.clock_source.and_then(|id| mididevices[id].as_ref()) {
+			Some((dev, _)) => dev,
+			None => return
+		};
+		let events = dev.incoming_events(scope).filter_map(|event| match event.bytes() {
+			[status] => Some((event.time(), *status)),
+			_ => None
+		});
+		let change = self.clock_slave.process(events, scope.n_frames(), self.song_length, self.n_beats, dev.capture_latency());
+
+		match change {
+			Some(TransportChange::Start { at, position }) => {
+				// the song reaches `position` exactly at frame `at`
+				self.locate((position + self.song_length - at) % self.song_length);
+				self.start_transport();
+				self.event_channel.send_or_complain(Event::TransportChanged(true));
+			}
+			Some(TransportChange::Stop) => {
+				if self.playing {
+					self.stop_transport();
+					self.event_channel.send_or_complain(Event::TransportChanged(false));
+				}
+			}
+			None => {}
+		}
+
+		if let Some(loop_length) = self.clock_slave.loop_length_change(self.song_length, self.n_beats, scope.n_frames()) {
+			self.event_channel.send_or_complain(Event::SuggestedLoopLength(loop_length));
+		}
+	}
+
+	/// Follows the JACK transport's rolling state, position and tempo, or publishes the song
+	/// position as timebase master. Returns the song position implied by the JACK transport
+	/// for the beginning of the period if it is followed and rolling.
+	fn process_jack_transport(&mut self, n_frames: u32) -> Option<u32> {
+		match self.jack_transport_mode {
+			JackTransportMode::Ignore => None,
+			JackTransportMode::TimebaseMaster => {
+				let position = self.bbt_position();
+				self.transport.publish(position);
+				None
+			}
+			JackTransportMode::Follow => {
+				let info = self.transport.query();
+				let target = match info.bbt {
+					Some(bbt) => {
+						// bar and beat are counted from 1, but other clients may publish anything
+						let beats = bbt.bar.saturating_sub(1) as f64 * bbt.beats_per_bar as f64 + bbt.beat.saturating_sub(1) as f64 + bbt.tick as f64 / bbt.ticks_per_beat;
+						let beat_length = self.song_length as f64 / self.n_beats as f64;
+						((beats % self.n_beats as f64) * beat_length) as u32 % self.song_length
+					}
+					None => info.frame % self.song_length
+				};
+
+				if let Some(bbt) = info.bbt.filter(|bbt| bbt.beats_per_minute.is_finite() && bbt.beats_per_minute > 0.0) {
+					let loop_length = (self.n_beats as f64 * 60.0 / bbt.beats_per_minute * self.sample_rate as f64).round() as u32;
+					if let Some(loop_length) = self.jack_loop_length_suggestion.check(loop_length, self.song_length, n_frames) {
+						self.event_channel.send_or_complain(Event::SuggestedLoopLength(loop_length));
+					}
+				}
+
+				if info.rolling && !self.playing {
+					if !self.is_recording() {
+						self.locate(target);
+					}
+					self.start_transport();
+					self.event_channel.send_or_complain(Event::TransportChanged(true));
+				}
+				else if !info.rolling && self.playing {
+					self.stop_transport();
+					self.event_channel.send_or_complain(Event::TransportChanged(false));
+				}
+				else if !info.rolling && target != self.song_position && !self.is_recording() {
+					self.locate(target);
+				}
+
+				if info.rolling { Some(target) } else { None }
+			}
+		}
+	}
+
+	/// Returns the song position in JACK's BBT format, with one bar per loop.
+	fn bbt_position(&self) -> BbtPosition {
+		let beat_length = self.song_length as f64 / self.n_beats as f64;
+		let beats = self.song_position as f64 / beat_length;
+		BbtPosition {
+			bar: self.transport_position / self.song_length + 1,
+			beat: beats as u32 + 1,
+			tick: (beats.fract() * TICKS_PER_BEAT) as u32,
+			beats_per_bar: self.n_beats as f32,
+			ticks_per_beat: TICKS_PER_BEAT,
+			beats_per_minute: 60.0 * self.sample_rate as f64 / beat_length
+		}
+	}
+
+	/// Moves the song position towards `target`, which is dictated by the clock source or the
+	/// JACK transport. Large deviations (e.g. after a tempo change) are corrected at once. Small
+	/// ones are corrected once per loop, shortly after the loop start, by moving all takes along
+	/// with the song, including those that are being recorded. Audio takes are crossfaded to
+	/// their new position.
+	fn follow_position(&mut self, target: u32) {
+		let song_length = self.song_length as i64;
+		let mut error = target as i64 - self.song_position as i64;
+		if error > song_length / 2 {
+			error -= song_length;
+		}
+		else if error < -song_length / 2 {
+			error += song_length;
+		}
+
+		if error.abs() > song_length / (24 * self.n_beats) as i64 {
+			self.locate(target);
+		}
+		else if self.drift_correction_due {
+			let position = self.song_position as i64 + error;
+			if error.abs() <= SYNC_DEAD_BAND {
+				self.drift_correction_due = false;
+			}
+			// the loop boundary must be passed exactly once
+			else if 0 <= position && position < song_length {
+				self.shift_song_position(error as i32);
+				self.drift_correction_due = false;
+			}
+		}
+	}
+
+	fn is_recording(&self) -> bool {
+		self.audiotakes.iter().any(|node| node.take.borrow().record_state == RecordState::Recording)
+			|| self.miditakes.iter().any(|node| node.take.borrow().record_state == RecordState::Recording)
+	}
+
+	/// Returns whether any take is recording or waiting for the loop start to begin recording.
+	fn is_counting_in_or_recording(&self) -> bool {
+		self.audiotakes.iter().any(|node| node.take.borrow().record_state != RecordState::Finished)
+			|| self.miditakes.iter().any(|node| node.take.borrow().record_state != RecordState::Finished)
+	}
+
+	/// Moves the song position to `position`. Finished takes are moved by the same amount, so
+	/// they stay in sync with the song.
+	fn locate(&mut self, position: u32) {
+		let mut offset = position as i64 - self.song_position as i64;
+		if offset < 0 {
+			offset += self.song_length as i64;
+		}
+		self.song_position = position;
+
+		for node in self.audiotakes.iter() {
+			let mut t = node.take.borrow_mut();
+			if t.record_state == RecordState::Finished {
+				let length = t.length.unwrap() as i64;
+				let target = ((t.playback_position as i64 + offset) % length) as u32;
+				t.seek(target);
+			}
+		}
+		for node in self.miditakes.iter() {
+			let mut t = node.take.borrow_mut();
+			if t.record_state == RecordState::Finished {
+				let length = t.length.unwrap() as i64;
+				let target = ((t.playback_position as i64 + offset) % length) as u32;
+				t.seek(target);
+			}
+		}
+	}
+
+	/// Moves the song position by `offset`, which must not pass the loop boundary. Unlike
+	/// `locate`, the takes that are being recorded move along with the song, and audio takes
+	/// are crossfaded to their new position.
+	fn shift_song_position(&mut self, offset: i32) {
+		self.song_position = (self.song_position as i64 + offset as i64) as u32;
+
+		for node in self.audiotakes.iter() {
+			let mut t = node.take.borrow_mut();
+			match t.record_state {
+				RecordState::Finished => {
+					let length = t.length.unwrap() as i64;
+					let target = (t.playback_position as i64 + offset as i64).rem_euclid(length) as u32;
+					t.seek_crossfaded(target);
+				}
+				RecordState::Recording => {
+					// the take starts playing when `playback_position` reaches its length
+					let position = t.playback_position as i64 + offset as i64;
+					t.playback_position = match t.length {
+						Some(length) => position.rem_euclid(length as i64) as u32,
+						None => position.max(0) as u32
+					};
+				}
+				RecordState::Waiting => {}
+			}
+		}
+		for node in self.miditakes.iter() {
+			let mut t = node.take.borrow_mut();
+			if t.record_state == RecordState::Waiting {
+				continue;
+			}
+			let position = t.playback_position as i64 + offset as i64;
+			match t.length {
+				Some(length) => t.seek(position.rem_euclid(length as i64) as u32),
+				None => t.playback_position = position.max(0) as u32
+			}
+		}
+	}
+
+	/// Moves the song position back to the loop start. Takes that are being recorded would lose
+	/// their alignment with the song, so nothing happens while there are any.
+	fn rewind(&mut self) {
+		if self.is_recording() {
+			return;
+		}
+
+		self.song_position = 0;
+		self.loop_count = 0;
+		for node in self.audiotakes.iter() {
+			let mut t = node.take.borrow_mut();
+			if t.record_state == RecordState::Finished {
+				let length = t.length.unwrap();
+				let latency = self.devices[t.audiodev_id].as_ref().unwrap().0.playback_latency();
+				t.seek(latency % length);
+			}
+		}
+		for node in self.miditakes.iter() {
+			let mut t = node.take.borrow_mut();
+			if t.record_state == RecordState::Finished {
+				let length = t.length.unwrap();
+				let latency = self.mididevices[t.mididev_id].as_ref().unwrap().0.playback_latency();
+				t.seek(latency % length);
+			}
+		}
+	}
+
+	/// Swaps in the takes of a pending song length change. Must be called right after the song
+	/// has wrapped, or while the transport is stopped. Returns whether a change was pending.
+	fn apply_song_length_change(&mut self) -> bool {
+		let mut change = match self.pending_song_length_change.take() {
+			Some(change) => change,
+			None => return false
+		};
+
+		self.song_length = change.song_length;
+		self.n_beats = change.n_beats;
+		std::mem::swap(&mut self.audiotakes, &mut change.audiotakes);
+		std::mem::swap(&mut self.miditakes, &mut change.miditakes);
+		// from now on, `change` holds the old takes.
+
+		let mut cursor = self.audiotakes.front_mut();
+		while let Some(node) = cursor.get() {
+			let id = node.take.borrow().id;
+			let old_state = for_first(&mut change.audiotakes, |old| {
+				let old = old.take.borrow();
+				if old.id == id { Some((old.unmuted, old.scheduled_unmute, old.gain, old.pan, old.cued)) } else { None }
+			});
+			if let Ok((unmuted, scheduled_unmute, gain, pan, cued)) = old_state {
+				// mute, gain and cue changes may have happened since the replacement was created
+				let mut t = node.take.borrow_mut();
+				t.unmuted = unmuted;
+				t.scheduled_unmute = scheduled_unmute;
+				t.gain = gain;
+				t.pan = pan;
+				t.cued = cued;
+				let length = t.length.unwrap();
+				let latency = self.devices[t.audiodev_id].as_ref().unwrap().0.playback_latency();
+				t.seek((self.song_position + latency) % length);
+			}
+			else {
+				// the take has been deleted in the meantime
+				let node = cursor.remove().unwrap();
+				change.audiotakes.push_back(node);
+				continue;
+			}
+			cursor.move_next();
+		}
+
+		for old in change.miditakes.iter() {
+			let t = old.take.borrow();
+			if t.unmuted {
+				let dev = &mut self.mididevices[t.mididev_id].as_mut().unwrap().0;
+				t.note_registry.borrow_mut().send_noteoffs(dev);
+			}
+		}
+
+		let mut cursor = self.miditakes.front_mut();
+		while let Some(node) = cursor.get() {
+			let id = node.take.borrow().id;
+			let old_state = for_first(&mut change.miditakes, |old| {
+				let old = old.take.borrow();
+				if old.id == id { Some((old.unmuted, old.scheduled_unmute)) } else { None }
+			});
+			if let Ok((unmuted, scheduled_unmute)) = old_state {
+				let mut t = node.take.borrow_mut();
+				t.unmuted = unmuted;
+				t.unmuted_old = unmuted;
+				t.scheduled_unmute = scheduled_unmute;
+				let length = t.length.unwrap();
+				let latency = self.mididevices[t.mididev_id].as_ref().unwrap().0.playback_latency();
+				t.seek((self.song_position + latency) % length);
+			}
+			else {
+				let node = cursor.remove().unwrap();
+				change.miditakes.push_back(node);
+				continue;
+			}
+			cursor.move_next();
+		}
+
+		self.submit_destruction_request(DestructionRequest::SongLengthChange(change));
+		true
+	}
+
+	/// Hands `request` over to the destructor thread, so that no memory is freed in the audio thread.
+	fn submit_destruction_request(&mut self, request: DestructionRequest<Driver::AudioDev, Driver::MidiDev>) {
+		#[cfg(feature = "debug_print_in_audio_thread")]
+		println!("submitting deconstruction request");
+		if let Err(request) = self.destructor_channel.push(request) {
+			// should not happen since commands wait for free slots. Leaking the request is
+			// still better than freeing memory or panicking in the audio thread.
+			std::mem::forget(request);
+		}
+		self.destructor_thread_handle.thread().unpark();
+	}
+
 	fn process_audio_playback(&mut self, scope: &Driver::ProcessScope) {
 		for dev in self.devices.iter_mut() {
 			if let Some(d) = dev {
-				if d.1.echo {
-					play_echo(scope, &mut d.0);
-				}
-				else {
-					play_silence(scope,&mut d.0,0..scope.n_frames());
-				}
+				play_silence(scope,&mut d.0,0..scope.n_frames());
 			}
 		}
 
+		// cued takes play into the monitor bus directly
+		self.monitor_bus.clear(scope);
+
+		if !self.playing {
+			return;
+		}
+
 		let mut cursor = self.audiotakes.front();
 		while let Some(node) = cursor.get() {
 			let mut t = node.take.borrow_mut();
 			let dev = self.devices[t.audiodev_id].as_mut().unwrap();
-			t.playback(scope, &mut dev.0, 0..scope.n_frames()); // handles finishing recording and wrapping around.
+
+			let (song_wraps, song_wraps_at) = check_wrap(
+				self.song_position as i32 + dev.0.playback_latency() as i32,
+				self.song_length, scope.n_frames() );
+
+			match t.scheduled_unmute {
+				Some(unmuted) if song_wraps => {
+					t.playback(scope, &mut dev.0, Some(self.monitor_bus.device_mut()), 0..song_wraps_at);
+					t.unmuted = unmuted;
+					t.scheduled_unmute = None;
+					self.event_channel.send_or_complain(Event::AudioTakeMuteChanged(t.audiodev_id, t.id, unmuted));
+					t.playback(scope, &mut dev.0, Some(self.monitor_bus.device_mut()), song_wraps_at..scope.n_frames());
+				}
+				_ => {
+					t.playback(scope, &mut dev.0, Some(self.monitor_bus.device_mut()), 0..scope.n_frames()); // handles finishing recording and wrapping around.
+				}
+			}
 			cursor.move_next();
 		}
 	}
 
+	/// Number of samples over which chain gain changes are smoothed
+	fn gain_ramp_length(&self) -> u32 {
+		self.sample_rate / 200
+	}
+
+	/// Applies the chain gains and sums the chains' takes into the master bus. Echoed inputs
+	/// are then added to their chains and, together with the metronome, to the monitor bus.
+	/// Must run after `process_audio_playback`, which has already added the cued takes to the
+	/// monitor bus.
+	fn process_output_buses(&mut self, scope: &Driver::ProcessScope) {
+		self.master_bus.clear(scope);
+		for dev in self.devices.iter_mut() {
+			if let Some((dev, data)) = dev {
+				apply_gain(scope, dev, &mut data.gain);
+				self.master_bus.add(scope, dev);
+				if data.echo {
+					play_echo(scope, dev);
+					self.monitor_bus.add_capture(scope, dev);
+				}
+			}
+		}
+		self.monitor_bus.add_scaled(scope, self.metronome.device_mut(), &mut self.metronome_gain);
+		self.master_bus.finish(scope);
+		self.monitor_bus.finish(scope);
+	}
+
 	fn process_midi_playback(&mut self, scope: &Driver::ProcessScope) {
 		use crate::midi_message::MidiMessage;
-		let mut cursor = self.miditakes.front();
-		while let Some(node) = cursor.get() {
-			let mut t = node.take.borrow_mut();
-			let dev = &mut self.mididevices[t.mididev_id].as_mut().unwrap().0;
-			t.playback(dev, 0..scope.n_frames()); // handles finishing recording and wrapping around.
-			cursor.move_next();
+		if self.playing {
+			let mut cursor = self.miditakes.front();
+			while let Some(node) = cursor.get() {
+				let mut t = node.take.borrow_mut();
+				let dev = &mut self.mididevices[t.mididev_id].as_mut().unwrap().0;
+
+				let (song_wraps, song_wraps_at) = check_wrap(
+					self.song_position as i32 + dev.playback_latency() as i32,
+					self.song_length, scope.n_frames() );
+
+				match t.scheduled_unmute {
+					Some(unmuted) if song_wraps => {
+						t.playback(dev, 0..song_wraps_at);
+						t.unmuted = unmuted;
+						t.scheduled_unmute = None;
+						self.event_channel.send_or_complain(Event::MidiTakeMuteChanged(t.mididev_id, t.id, unmuted));
+						t.playback(dev, song_wraps_at..scope.n_frames());
+					}
+					_ => {
+						t.playback(dev, 0..scope.n_frames()); // handles finishing recording and wrapping around.
+					}
+				}
+				cursor.move_next();
+			}
 		}
 
 		for d in self.mididevices.iter_mut() {
@@ -323,7 +1138,17 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 					}).ok(); // we can't do anything about lost events
 					data.stop_transport_pending = false;
 				}
-				if data.start_transport_pending {
+				if let Some(start) = data.transport_message_pending.as_mut() {
+					if start.process(dev, scope.n_frames()) {
+						data.transport_message_pending = None;
+					}
+				}
+				if let Some(offset) = data.clock_offset {
+					if self.playing && !self.free_running && self.clock_source.is_none() {
+						queue_clock_ticks(dev, self.song_position, self.song_length, self.n_beats, offset, scope.n_frames());
+					}
+				}
+				if data.start_transport_pending && self.playing {
 					let time_until_action = self.song_length - (self.song_position + dev.capture_latency()) % self.song_length;
 					if time_until_action < scope.n_frames() {
 						dev.queue_event( MidiMessage {
@@ -362,7 +1187,19 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 					}
 				}
 			}
+			else if t.record_state == Finished {
+				// keep recording a bit beyond the end, for crossfading the loop seam
+				if let Some(length) = t.length {
+					if t.recorded_length < length + t.crossfade_length() {
+						t.record(scope, dev, 0..scope.n_frames());
+					}
+				}
+				let latency = dev.playback_latency() + dev.capture_latency();
+				t.overdub(scope, dev, latency);
+			}
 			else if t.record_state == Waiting {
+				// while free-running, takes start recording right away
+				let (song_wraps, song_wraps_at) = if self.free_running { (true, 0) } else { (song_wraps, song_wraps_at) };
 				if song_wraps {
 					#[cfg(feature = "debug_print_in_audio_thread")]
 					println!("\nStarted recording on device {}", t.audiodev_id);
@@ -381,44 +1218,49 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 
 	fn process_midi_recording(&mut self, scope: &Driver::ProcessScope) {
 		use RecordState::*;
-		let mut cursor = self.miditakes.front();
-		while let Some(node) = cursor.get() {
-			let mut t = node.take.borrow_mut();
-			let (dev, dev_data) = &self.mididevices[t.mididev_id].as_ref().unwrap();
-		
-			let (song_wraps, song_wraps_at) = check_wrap(
-				self.song_position as i32 - dev.capture_latency() as i32,
-				self.song_length, scope.n_frames() );
+		if self.playing {
+			let mut cursor = self.miditakes.front();
+			while let Some(node) = cursor.get() {
+				let mut t = node.take.borrow_mut();
+				let (dev, dev_data) = &self.mididevices[t.mididev_id].as_ref().unwrap();
+			
+				let (song_wraps, song_wraps_at) = check_wrap(
+					self.song_position as i32 - dev.capture_latency() as i32,
+					self.song_length, scope.n_frames() );
 
-			if t.record_state == Recording {
-				t.record(scope,dev, 0..scope.n_frames());
+				if t.record_state == Recording {
+					t.record(scope,dev, 0..scope.n_frames());
 
-				if let Some(length) = t.length {
-					if t.recorded_length >= length {
-						#[cfg(feature = "debug_print_in_audio_thread")]
-						println!("\nFinished recording on device {}", t.mididev_id);
-						self.event_channel.send_or_complain(Event::MidiTakeStateChanged(t.mididev_id, t.id, RecordState::Finished, t.started_recording_at + length));
-						t.record_state = Finished;
+					if let Some(length) = t.length {
+						if t.recorded_length >= length {
+							#[cfg(feature = "debug_print_in_audio_thread")]
+							println!("\nFinished recording on device {}", t.mididev_id);
+							self.event_channel.send_or_complain(Event::MidiTakeStateChanged(t.mididev_id, t.id, RecordState::Finished, t.started_recording_at + length));
+							t.record_state = Finished;
+						}
 					}
 				}
-			}
-			else if t.record_state == Waiting {
-				if song_wraps {
-					#[cfg(feature = "debug_print_in_audio_thread")]
-					println!("\nStarted recording on device {}", t.mididev_id);
-					self.event_channel.send_or_complain(Event::MidiTakeStateChanged(t.mididev_id, t.id, RecordState::Recording, self.transport_position + song_wraps_at));
-					t.record_state = Recording;
-					t.started_recording_at = self.transport_position + song_wraps_at;
-					t.start_recording(scope, dev, dev_data.registry.clone(), 0..song_wraps_at);
-					t.recorded_length = 0;
-					t.record(scope, dev, song_wraps_at..scope.n_frames());
-					t.playback_position = scope.n_frames()-song_wraps_at + dev.capture_latency() + dev.playback_latency();
+				else if t.record_state == Waiting {
+					// while free-running, takes start recording right away
+					let (song_wraps, song_wraps_at) = if self.free_running { (true, 0) } else { (song_wraps, song_wraps_at) };
+					if song_wraps {
+						#[cfg(feature = "debug_print_in_audio_thread")]
+						println!("\nStarted recording on device {}", t.mididev_id);
+						self.event_channel.send_or_complain(Event::MidiTakeStateChanged(t.mididev_id, t.id, RecordState::Recording, self.transport_position + song_wraps_at));
+						t.record_state = Recording;
+						t.started_recording_at = self.transport_position + song_wraps_at;
+						t.start_recording(scope, dev, dev_data.registry.clone(), 0..song_wraps_at);
+						t.recorded_length = 0;
+						t.record(scope, dev, song_wraps_at..scope.n_frames());
+						t.playback_position = scope.n_frames()-song_wraps_at + dev.capture_latency() + dev.playback_latency();
+					}
 				}
-			}
 
-			cursor.move_next();
+				cursor.move_next();
+			}
 		}
 
+		// held notes are registered even while stopped, so that they can be captured later on
 		for dev_opt in self.mididevices.iter_mut() {
 			if let Some((dev, data)) = dev_opt {
 				for event in dev.incoming_events(scope) {
@@ -434,9 +1276,12 @@ impl<Driver: DriverTrait> AudioThreadState<Driver>
 	}
 }
 
+/// Adds the device's input to its output.
 fn play_echo<'a, T: AudioDeviceTrait>(scope: &'a T::Scope, device: &'a mut T) {
 	for (output, input) in device.playback_and_capture_buffers(scope) {
-		output.copy_from_slice(input);
+		for (o, i) in output.iter_mut().zip(input.iter()) {
+			*o += *i;
+		}
 	}
 }
 
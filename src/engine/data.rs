@@ -2,10 +2,81 @@
 pub enum Event {
 	AudioTakeStateChanged(usize, u32, RecordState, u32),
 	MidiTakeStateChanged(usize, u32, RecordState, u32 /* timestamp */),
+	AudioTakeMuteChanged(usize, u32, bool /* unmuted */),
+	MidiTakeMuteChanged(usize, u32, bool /* unmuted */),
+	TransportChanged(bool /* playing */),
+	SuggestedLoopLength(u32 /* loop length matching the tempo of the clock source or JACK transport */),
+	/// A take to be retired, restored or deleted was not found in the audio thread, and the
+	/// message was ignored
+	TakeNotFound(u32 /* take id */, TakeOperation),
 	Timestamp(u32, u32),
 	Kill
 }
 
+/// How the song relates to the JACK transport.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum JackTransportMode {
+	Ignore,
+	/// Start, stop and locate the song along with the JACK transport
+	Follow,
+	/// Publish the song's position and tempo to other JACK clients
+	TimebaseMaster
+}
+
+/// What the audio thread was asked to do with a take
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TakeOperation {
+	Retire,
+	Restore,
+	Delete
+}
+
+/// When the metronome clicks while the song is playing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MetronomeMode {
+	Always,
+	/// Only while takes are waiting to be recorded (i.e. during the count-in) or recording
+	Recording
+}
+
+/// Which of the metronome's clicks a sample replaces
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MetronomeSound {
+	/// Played on accented beats
+	Accent,
+	/// Played on all other beats and subdivisions
+	Click
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MetronomeSettings {
+	pub unmuted: bool,
+	pub mode: MetronomeMode,
+	pub volume: f32,
+	/// Level of the clicks on accented beats, relative to the other clicks
+	pub accent: f32,
+	/// Frequency of the clicks in Hz. Accented beats are one octave higher.
+	pub pitch: f32,
+	/// Number of clicks per beat, e.g. 2 for eighths or 3 for triplets
+	pub subdivision: u32,
+	/// Bit n is set if beat n (counted from zero) is accented
+	pub accents: u32
+}
+
+impl Default for MetronomeSettings {
+	fn default() -> MetronomeSettings {
+		MetronomeSettings {
+			unmuted: true,
+			mode: MetronomeMode::Always,
+			volume: 0.3,
+			accent: 1.0,
+			pitch: 440.0,
+			subdivision: 1,
+			accents: 1
+		}
+	}
+}
+
 #[derive(std::cmp::PartialEq, Debug)]
 pub enum RecordState {
 	Waiting,
@@ -10,15 +10,49 @@ pub struct MidiDeviceInfo {
 	pub name: String
 }
 
+/// Musical position in the format of JACK's BBT (bar, beat, tick) information.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BbtPosition {
+	/// Counted from 1
+	pub bar: u32,
+	/// Counted from 1
+	pub beat: u32,
+	pub tick: u32,
+	pub beats_per_bar: f32,
+	pub ticks_per_beat: f64,
+	pub beats_per_minute: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransportInfo {
+	pub rolling: bool,
+	/// Transport position in frames at the beginning of the current period
+	pub frame: u32,
+	pub bbt: Option<BbtPosition>,
+}
+
+/// The driver's transport (e.g. JACK transport), as seen from the audio thread.
+pub trait TransportTrait: Send + 'static {
+	fn query(&self) -> TransportInfo;
+	/// Publishes the position at the beginning of the current period. Has no effect unless
+	/// the driver is timebase master.
+	fn publish(&mut self, position: BbtPosition);
+}
+
 pub trait DriverTrait: Send {
 	type MidiDev : MidiDeviceTrait<Scope = Self::ProcessScope>;
 	type AudioDev : AudioDeviceTrait<Scope = Self::ProcessScope>;
 	type ProcessScope : ProcessScopeTrait;
+	type Transport : TransportTrait;
 	type Error: std::fmt::Debug;
 
 	fn activate(&mut self, audio_thread_state: AudioThreadState<Self>) where Self: Sized;
 	fn new_audio_device(&mut self, n_channels: u32, name: &str) -> Result<Self::AudioDev, Self::Error>;
 	fn new_midi_device(&mut self, name: &str) -> Result<Self::MidiDev, Self::Error>;
+	fn transport(&self) -> Self::Transport;
+	/// Makes the driver publish the positions passed to `TransportTrait::publish` to other
+	/// applications, or stops doing so.
+	fn set_timebase_master(&mut self, master: bool) -> Result<(), Self::Error>;
 
 	fn sample_rate(&self) -> u32;
 }
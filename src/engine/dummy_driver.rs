@@ -236,10 +236,30 @@ impl AudioDeviceTrait for Arc<Mutex<DummyAudioDevice>> {
 	}
 }
 
+/// Simulated JACK transport. The frame advances while rolling; the BBT position is only
+/// changed by the test.
+pub struct DummyTransport {
+	pub info: TransportInfo,
+	pub timebase_master: bool,
+	/// Last position published while being timebase master
+	pub published: Option<BbtPosition>,
+}
+
+impl TransportTrait for Arc<Mutex<DummyTransport>> {
+	fn query(&self) -> TransportInfo { self.lock().unwrap().info }
+	fn publish(&mut self, position: BbtPosition) {
+		let mut transport = self.lock().unwrap();
+		if transport.timebase_master {
+			transport.published = Some(position);
+		}
+	}
+}
+
 pub struct DummyDriverData {
 	pub playback_latency: u32,
 	pub capture_latency: u32,
 	pub sample_rate: u32,
+	pub transport: Arc<Mutex<DummyTransport>>,
 
 	pub audio_devices: std::collections::HashMap<String, Arc<Mutex<DummyAudioDevice>> >,
 	pub midi_devices: std::collections::HashMap<String, Arc<Mutex<DummyMidiDevice>> >,
@@ -254,6 +274,7 @@ impl DriverTrait for DummyDriver {
 	type MidiDev = Arc<Mutex<DummyMidiDevice>>;
 	type AudioDev = Arc<Mutex<DummyAudioDevice>>;
 	type ProcessScope = DummyScope;
+	type Transport = Arc<Mutex<DummyTransport>>;
 	type Error = ();
 
 	fn activate(&mut self, backend: super::backend::AudioThreadState<Self>) {
@@ -274,6 +295,19 @@ impl DriverTrait for DummyDriver {
 		return Ok(arc);
 	}
 
+	fn transport(&self) -> Self::Transport {
+		self.0.lock().unwrap().transport.clone()
+	}
+	fn set_timebase_master(&mut self, master: bool) -> Result<(), Self::Error> {
+		let lock = self.0.lock().unwrap();
+		let mut transport = lock.transport.lock().unwrap();
+		transport.timebase_master = master;
+		if !master {
+			transport.published = None;
+		}
+		Ok(())
+	}
+
 	fn sample_rate(&self) -> u32 {
 		self.0.lock().unwrap().sample_rate
 	}
@@ -285,6 +319,11 @@ impl DummyDriver {
 			playback_latency,
 			capture_latency,
 			sample_rate,
+			transport: Arc::new(Mutex::new(DummyTransport {
+				info: TransportInfo { rolling: false, frame: 0, bbt: None },
+				timebase_master: false,
+				published: None
+			})),
 			audio_devices: std::collections::HashMap::new(),
 			midi_devices: std::collections::HashMap::new(),
 			backend: None,
@@ -297,6 +336,11 @@ impl DummyDriver {
 		inner.scope.next(n_frames);
 		let scope = inner.scope.clone();
 		inner.backend.as_mut().unwrap().process_callback(&scope);
+
+		let mut transport = inner.transport.lock().unwrap();
+		if transport.info.rolling {
+			transport.info.frame += n_frames;
+		}
 	}
 
 	pub fn process_for(&self, n_total_frames: u32, chunksize: u32) {
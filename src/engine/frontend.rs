@@ -1,11 +1,17 @@
 use super::shared::SharedThreadState;
-use super::takes::{MidiTake,MidiTakeNode,AudioTake,AudioTakeNode};
+use super::takes::{MidiTake,MidiTakeNode,MidiTakeAdapter,AudioTake,AudioTakeNode,AudioTakeAdapter,AtomicSample};
 use super::retry_channel::RetryChannelPush;
-use super::messages::Message;
+use super::messages::{Message, Samples, SongLengthChange};
+use super::data::{JackTransportMode, MetronomeSettings, MetronomeSound, RecordState};
 use super::driver_traits::*;
 use std::sync::Arc;
 use std::collections::HashMap;
 use crate::id_generator::IdGenerator;
+use crate::midi_message::MidiMessage;
+use crate::outsourced_allocation_buffer::BufferReader;
+use crate::wav::resample;
+use intrusive_collections::LinkedList;
+use std::sync::atomic::Ordering;
 
 #[cfg(test)]
 const CHUNKSIZE: usize = 44100 * 16;
@@ -13,38 +19,59 @@ const CHUNKSIZE: usize = 44100 * 16;
 #[cfg(not(test))]
 const CHUNKSIZE: usize = 8*1024;
 
+const MAX_METRONOME_SUBDIVISION: u32 = 8;
+
 pub struct GuiAudioTake {
 	pub id: u32,
 	pub audiodev_id: usize,
 	pub unmuted: bool,
-	pub length: Option<u32> // None means "not yet finished"
+	pub scheduled_unmute: Option<bool>,
+	pub length: Option<u32>, // None means "not yet finished"
+	pub crossfade_length: u32,
+	pub gain: f32,
+	pub pan: f32,
+	pub cued: bool,
+	pub overdub_feedback: Option<f32>,
+	/// Read access to the recorded samples, one reader per channel
+	pub samples: Vec<BufferReader<AtomicSample>>,
 }
 
 pub struct GuiMidiTake {
 	pub id: u32,
 	pub mididev_id: usize,
 	pub unmuted: bool,
-	pub length: Option<u32> // None means "not yet finished"
+	pub scheduled_unmute: Option<bool>,
+	pub length: Option<u32>, // None means "not yet finished"
+	/// Whether the take has stopped recording. `length` may be known earlier.
+	pub recording_finished: bool,
+	/// Read access to the recorded events
+	pub events: BufferReader<MidiMessage>,
 }
 
 pub struct GuiAudioDevice {
 	pub info: AudioDeviceInfo,
 	pub takes: HashMap<u32, GuiAudioTake>,
+	/// Takes that do not play, but can be restored. See `retire_audiotake`.
+	pub retired_takes: HashMap<u32, GuiAudioTake>,
 }
 
 impl GuiAudioDevice {
 	pub fn info(&self) -> &AudioDeviceInfo { &self.info }
 	pub fn takes(&self) -> &HashMap<u32, GuiAudioTake> { &self.takes }
+	pub fn retired_takes(&self) -> &HashMap<u32, GuiAudioTake> { &self.retired_takes }
 }
 
 pub struct GuiMidiDevice {
 	pub info: MidiDeviceInfo,
 	pub takes: HashMap<u32, GuiMidiTake>,
+	/// Takes that do not play, but can be restored. See `retire_miditake`.
+	pub retired_takes: HashMap<u32, GuiMidiTake>,
 }
 
 impl GuiMidiDevice {
 	pub fn info(&self) -> &MidiDeviceInfo { &self.info }
 	pub fn takes(&self) -> &HashMap<u32, GuiMidiTake> { &self.takes }
+	pub fn retired_takes(&self) -> &HashMap<u32, GuiMidiTake> { &self.retired_takes }
 }
 
 /** Creates a new trait with the functions specified and an implementation
@@ -71,7 +98,19 @@ pub struct FrontendThreadState<Driver: DriverTrait> {
 	pub mididevices: HashMap<usize, GuiMidiDevice>,
 	pub shared: Arc<SharedThreadState>,
 	pub next_id: IdGenerator,
-	pub driver: Driver
+	pub driver: Driver,
+	pub n_beats: u32,
+	/// Fade length in samples applied to mute changes of newly created audio takes.
+	pub fade_length: u32,
+	/// Loop seam crossfade length in samples for newly created audio takes.
+	pub crossfade_length: u32,
+	/// MIDI device whose clock the song follows.
+	pub clock_source: Option<usize>,
+	pub jack_transport_mode: JackTransportMode,
+	/// Whether the loop length is going to be defined by the first recording.
+	pub free_running: bool,
+	/// Number of devices removed so far, to be compared with `SharedThreadState::destroyed_devices`
+	pub removed_devices: u32,
 }
 
 new_trait_with_impl! {
@@ -85,20 +124,113 @@ impl<Driver: DriverTrait> new pub FrontendTrait for FrontendThreadState<Driver>
 		self.shared.song_length.load(std::sync::atomic::Ordering::Relaxed)
 	}
 
+	// Changes the loop length. Existing takes are rescaled to the new length and swapped in
+	// at the next loop boundary; audio takes are resampled, which changes their pitch.
+	// Fails if a take is still being recorded or if a previous change is still pending.
 	pub fn set_loop_length(&mut self, loop_length_samples: u32, n_beats: u32) -> Result<(),()> {
+		match self.prepare_loop_length_change(loop_length_samples, n_beats)? {
+			Some(rescaling) => self.finish_loop_length_change(rescaling.rescale()),
+			None => Ok(())
+		}
+	}
+
+	// Like `set_loop_length`, but leaves rescaling the takes, which may take a while, to the
+	// caller. If there are takes, the loop length change is pending from now on, and copies of
+	// the takes are returned. They must be rescaled and passed to `finish_loop_length_change`,
+	// or be dropped after calling `cancel_loop_length_change`. Otherwise the change is made
+	// right away.
+	pub fn prepare_loop_length_change(&mut self, loop_length_samples: u32, n_beats: u32) -> Result<Option<TakeRescaling>,()> {
 		// FIXME TODO: reject song lengths that are smaller than the maximum latency.
 
-		// reject changing the loop length if takes exist.
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+
+		// retired takes are not rescaled
+		if self.devices.values().any(|dev| !dev.retired_takes.is_empty()) || self.mididevices.values().any(|dev| !dev.retired_takes.is_empty()) {
+			return Err(());
+		}
+
 		if self.devices.values().map(|dev| dev.takes.len())
 				.chain( self.mididevices.values().map(|dev| dev.takes.len()) )
-				.any(|n| n>0) {
+				.all(|n| n==0) {
+			self.command_channel.send_message(Message::SetSongLength(loop_length_samples, n_beats))?;
+			self.n_beats = n_beats;
+			return Ok(None);
+		}
+
+		let rescaling = self.copy_takes(loop_length_samples, n_beats)?;
+		self.shared.song_length_change_pending.store(true, Ordering::Release);
+		Ok(Some(rescaling))
+	}
+
+	// Swaps in the takes rescaled by `TakeRescaling::rescale` at the next loop boundary. Takes
+	// that have been deleted in the meantime are dropped, mute, gain and cue changes are kept.
+	pub fn finish_loop_length_change(&mut self, change: Box<SongLengthChange>) -> Result<(),()> {
+		let mut audiotakes = Vec::new();
+		for node in change.audiotakes.iter() {
+			let take = node.take.borrow();
+			if let Some(old) = self.devices.get(&take.audiodev_id).and_then(|dev| dev.takes.get(&take.id)) {
+				let mut gui_take = GuiAudioTake::new(&take);
+				gui_take.unmuted = old.unmuted;
+				gui_take.scheduled_unmute = old.scheduled_unmute;
+				gui_take.gain = old.gain;
+				gui_take.pan = old.pan;
+				gui_take.cued = old.cued;
+				audiotakes.push(gui_take);
+			}
+		}
+		let mut miditakes = Vec::new();
+		for node in change.miditakes.iter() {
+			let take = node.take.borrow();
+			if let Some(old) = self.mididevices.get(&take.mididev_id).and_then(|dev| dev.takes.get(&take.id)) {
+				let mut gui_take = GuiMidiTake::new(&take);
+				gui_take.unmuted = old.unmuted;
+				gui_take.scheduled_unmute = old.scheduled_unmute;
+				miditakes.push(gui_take);
+			}
+		}
+
+		let n_beats = change.n_beats;
+		if self.command_channel.send_message(Message::ChangeSongLength(change)).is_err() {
+			self.cancel_loop_length_change();
 			return Err(());
 		}
 
-		self.command_channel.send_message(Message::SetSongLength(loop_length_samples, n_beats))?;
+		for take in audiotakes {
+			self.devices.get_mut(&take.audiodev_id).unwrap().takes.insert(take.id, take);
+		}
+		for take in miditakes {
+			self.mididevices.get_mut(&take.mididev_id).unwrap().takes.insert(take.id, take);
+		}
+		self.n_beats = n_beats;
 		Ok(())
 	}
 
+	// Abandons a loop length change prepared by `prepare_loop_length_change`.
+	pub fn cancel_loop_length_change(&mut self) {
+		self.shared.song_length_change_pending.store(false, Ordering::Release);
+	}
+
+	// Returns whether `set_loop_length` will succeed, apart from the requirement that there
+	// are no retired takes: no change may be pending and all takes must have finished
+	// recording and must not be overdubbed.
+	pub fn can_change_loop_length(&self) -> bool {
+		!self.loop_length_change_pending()
+			&& self.devices.values().flat_map(|dev| dev.takes.values()).all(|take| take.can_be_rescaled())
+			&& self.mididevices.values().flat_map(|dev| dev.takes.values()).all(|take| take.recording_finished)
+	}
+
+	// Returns whether a loop length change requested by `set_loop_length` is still waiting
+	// for the next loop boundary. No takes can be added in the meantime.
+	pub fn loop_length_change_pending(&self) -> bool {
+		self.shared.song_length_change_pending.load(Ordering::Acquire)
+	}
+
+	pub fn n_beats(&self) -> u32 {
+		self.n_beats
+	}
+
 	pub fn song_position(&self) -> u32 {
 		self.shared.song_position.load(std::sync::atomic::Ordering::Relaxed)
 	}
@@ -107,13 +239,129 @@ impl<Driver: DriverTrait> new pub FrontendTrait for FrontendThreadState<Driver>
 		self.shared.transport_position.load(std::sync::atomic::Ordering::Relaxed)
 	}
 
+	pub fn playing(&self) -> bool {
+		self.shared.playing.load(std::sync::atomic::Ordering::Relaxed)
+	}
+
+	// Starts the transport on beat one and sends Start to all MIDI devices. Unless takes are
+	// being recorded, the song position is reset to the loop start first.
+	pub fn start_transport(&mut self) -> Result<(),()> {
+		self.command_channel.send_message(Message::StartTransport(true))
+	}
+
+	// Resumes the transport from the current song position, e.g. after a pause. MIDI devices
+	// receive Start if the song position is at the loop start and Continue otherwise.
+	pub fn resume_transport(&mut self) -> Result<(),()> {
+		self.command_channel.send_message(Message::StartTransport(false))
+	}
+
+	// Stops the transport, silences all takes and sends Stop to all MIDI devices. If `rewind`
+	// is set, the song position is reset to the loop start, unless takes are being recorded.
+	pub fn stop_transport(&mut self, rewind: bool) -> Result<(),()> {
+		self.command_channel.send_message(Message::StopTransport(rewind))
+	}
+
+	// Enables or disables the "clock" MIDI port, which sends clock ticks, Start, Stop, Continue
+	// and Song Position Pointer. The clock ticks are delayed by `offset` frames; negative
+	// values send them earlier.
+	pub fn set_midiclock(&mut self, enabled: bool, offset: i32) -> Result<(),()> {
+		self.command_channel.send_message(Message::SetMidiClock(enabled, offset))
+	}
+
+	// Makes `mididev_id` receive clock ticks, delayed by the given offset in frames, or stops
+	// sending them if `None`. Transport messages are sent to all MIDI devices regardless.
+	pub fn set_mididevice_clock(&mut self, mididev_id: usize, offset: Option<i32>) -> Result<(),()> {
+		if !self.mididevices.contains_key(&mididev_id) {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::SetMidiDeviceClock(mididev_id, offset))
+	}
+
+	pub fn free_running(&self) -> bool {
+		self.free_running
+	}
+
+	// Lets the first recording define the loop length. While free-running, takes start recording
+	// right away instead of at the loop start, and the metronome and the MIDI clock are silent.
+	// Can only be enabled while there are no takes and the song follows neither a MIDI clock
+	// source nor the JACK transport.
+	pub fn set_free_running(&mut self, free_running: bool) -> Result<(),()> {
+		if free_running {
+			let has_takes = self.devices.values().any(|dev| !dev.takes.is_empty() || !dev.retired_takes.is_empty())
+				|| self.mididevices.values().any(|dev| !dev.takes.is_empty() || !dev.retired_takes.is_empty());
+			if has_takes || self.clock_source.is_some() || self.jack_transport_mode == JackTransportMode::Follow {
+				return Err(());
+			}
+		}
+		self.command_channel.send_message(Message::SetFreeRunning(free_running))?;
+		self.free_running = free_running;
+		Ok(())
+	}
+
+	// Ends free-running with a loop of `loop_length_samples` and `n_beats` beats, which starts
+	// where the audio or MIDI take `take_id` has started recording. The takes keep recording
+	// until they are finished with `finish_audiotake` or `finish_miditake`.
+	pub fn end_free_running(&mut self, loop_length_samples: u32, n_beats: u32, take_id: u32) -> Result<(),()> {
+		if !self.free_running || loop_length_samples == 0 || n_beats == 0 {
+			return Err(());
+		}
+		let exists = self.devices.values().any(|dev| dev.takes.contains_key(&take_id))
+			|| self.mididevices.values().any(|dev| dev.takes.contains_key(&take_id));
+		if !exists {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::EndFreeRunning(loop_length_samples, n_beats, take_id))?;
+		self.n_beats = n_beats;
+		self.free_running = false;
+		Ok(())
+	}
+
+	pub fn clock_source(&self) -> Option<usize> {
+		self.clock_source
+	}
+
+	// Makes the song follow the MIDI clock and the Start, Stop and Continue messages received
+	// on `mididev_id`, or stops following if `None`. The internal MIDI clock is not sent while
+	// following. A tempo that does not match the loop length is reported with a
+	// SuggestedLoopLength event.
+	pub fn set_clock_source(&mut self, mididev_id: Option<usize>) -> Result<(),()> {
+		if let Some(id) = mididev_id {
+			if !self.mididevices.contains_key(&id) || self.jack_transport_mode == JackTransportMode::Follow || self.free_running {
+				return Err(());
+			}
+		}
+		self.command_channel.send_message(Message::SetClockSource(mididev_id))?;
+		self.clock_source = mididev_id;
+		Ok(())
+	}
+
+	pub fn jack_transport_mode(&self) -> JackTransportMode {
+		self.jack_transport_mode
+	}
+
+	// Makes the song follow the JACK transport's rolling state, position and tempo, or
+	// publish its position and tempo as JACK timebase master. Following the JACK transport
+	// and a MIDI clock source at the same time is not possible.
+	pub fn set_jack_transport_mode(&mut self, mode: JackTransportMode) -> Result<(),()> {
+		if mode == JackTransportMode::Follow && (self.clock_source.is_some() || self.free_running) {
+			return Err(());
+		}
+		let master = mode == JackTransportMode::TimebaseMaster;
+		if master != (self.jack_transport_mode == JackTransportMode::TimebaseMaster) {
+			self.driver.set_timebase_master(master).map_err(|_| ())?;
+		}
+		self.command_channel.send_message(Message::SetJackTransportMode(mode))?;
+		self.jack_transport_mode = mode;
+		Ok(())
+	}
+
 	pub fn devices(&self) -> &HashMap<usize, GuiAudioDevice> { &self.devices}
 	pub fn mididevices(&self) -> &HashMap<usize, GuiMidiDevice> { &self.mididevices}
 
 	pub fn add_device(&mut self, name: &str, channels: u32) -> Result<usize,()> {
 		if let Some(id) = find_first_free_index(&self.devices, 32) {
 			let dev = self.driver.new_audio_device(channels, name).map_err(|_|())?;
-			let guidev = GuiAudioDevice { info: dev.info(), takes: HashMap::new() };
+			let guidev = GuiAudioDevice { info: dev.info(), takes: HashMap::new(), retired_takes: HashMap::new() };
 			self.command_channel.send_message(Message::UpdateAudioDevice(id, Some(dev)))?;
 			self.devices.insert(id, guidev);
 			Ok(id)
@@ -125,7 +373,7 @@ impl<Driver: DriverTrait> new pub FrontendTrait for FrontendThreadState<Driver>
 	pub fn add_mididevice(&mut self, name: &str) -> Result<usize,()> {
 		if let Some(id) = find_first_free_index(&self.mididevices, 32) {
 			let dev = self.driver.new_midi_device(name).map_err(|_|())?;
-			let guidev = GuiMidiDevice { info: dev.info(), takes: HashMap::new() };
+			let guidev = GuiMidiDevice { info: dev.info(), takes: HashMap::new(), retired_takes: HashMap::new() };
 			self.command_channel.send_message(Message::UpdateMidiDevice(id, Some(dev)))?;
 			self.mididevices.insert(id, guidev);
 			Ok(id)
@@ -135,6 +383,43 @@ impl<Driver: DriverTrait> new pub FrontendTrait for FrontendThreadState<Driver>
 		}
 	}
 
+	pub fn remove_device(&mut self, audiodev_id: usize) -> Result<(),()> {
+		// the audio thread expects that no take is using the device anymore
+		let dev = self.devices.get(&audiodev_id).ok_or(())?;
+		if !dev.takes.is_empty() || !dev.retired_takes.is_empty() {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::UpdateAudioDevice(audiodev_id, None))?;
+		self.devices.remove(&audiodev_id);
+		self.removed_devices += 1;
+		Ok(())
+	}
+	pub fn remove_mididevice(&mut self, mididev_id: usize) -> Result<(),()> {
+		let dev = self.mididevices.get(&mididev_id).ok_or(())?;
+		if !dev.takes.is_empty() || !dev.retired_takes.is_empty() {
+			return Err(());
+		}
+		if self.clock_source == Some(mididev_id) {
+			self.command_channel.send_message(Message::SetClockSource(None))?;
+			self.clock_source = None;
+		}
+		self.command_channel.send_message(Message::UpdateMidiDevice(mididev_id, None))?;
+		self.mididevices.remove(&mididev_id);
+		self.removed_devices += 1;
+		Ok(())
+	}
+
+	// Devices are torn down asynchronously after their removal, so their names may still be
+	// taken for a moment. Blocks until all removed devices have been torn down or `timeout`
+	// has passed, and returns whether they have been torn down.
+	pub fn wait_for_removed_devices(&self, timeout: std::time::Duration) -> bool {
+		let destroyed = self.shared.destroyed_devices.lock().unwrap();
+		let (_, result) = self.shared.device_destroyed
+			.wait_timeout_while(destroyed, timeout, |destroyed| *destroyed < self.removed_devices)
+			.unwrap();
+		!result.timed_out()
+	}
+
 	pub fn restart_midi_transport(&mut self, mididev_id: usize) -> Result<(),()> {
 		self.command_channel.send_message(Message::RestartMidiTransport(mididev_id))?;
 		Ok(())
@@ -145,28 +430,111 @@ impl<Driver: DriverTrait> new pub FrontendTrait for FrontendThreadState<Driver>
 		Ok(())
 	}
 
-	pub fn add_audiotake(&mut self, audiodev_id: usize, unmuted: bool) -> Result<u32,()> {
+	// Sets the linear gain applied to everything the device plays.
+	pub fn set_audiodevice_gain(&mut self, audiodev_id: usize, gain: f32) -> Result<(),()> {
+		if !(gain.is_finite() && gain >= 0.0) || !self.devices.contains_key(&audiodev_id) {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::SetAudioDeviceGain(audiodev_id, gain))?;
+		Ok(())
+	}
+
+	// Sets the gains of the master and monitor buses and the level of the metronome within the latter.
+	pub fn set_bus_gains(&mut self, master_gain: f32, monitor_gain: f32, metronome_gain: f32) -> Result<(),()> {
+		if ![master_gain, monitor_gain, metronome_gain].iter().all(|gain| gain.is_finite() && *gain >= 0.0) {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::SetBusGains(master_gain, monitor_gain, metronome_gain))?;
+		Ok(())
+	}
+
+	// Configures the metronome's click sound and whether it clicks always or only while takes are
+	// waiting to be recorded or recording.
+	pub fn set_metronome(&mut self, settings: MetronomeSettings) -> Result<(),()> {
+		let nyquist = self.driver.sample_rate() as f32 / 2.0;
+		if ![settings.volume, settings.accent].iter().all(|level| level.is_finite() && *level >= 0.0)
+			|| !(settings.pitch > 0.0 && 2.0 * settings.pitch < nyquist)
+			|| !(1..=MAX_METRONOME_SUBDIVISION).contains(&settings.subdivision) {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::SetMetronome(settings))
+	}
+
+	// Replaces the synthesized click on accented beats or on all other beats with the given
+	// mono sample at the engine's sample rate. `None` restores the synthesized click.
+	pub fn set_metronome_sample(&mut self, which: MetronomeSound, sample: Option<Vec<f32>>) -> Result<(),()> {
+		self.command_channel.send_message(Message::SetMetronomeSound(which, sample.map(Samples)))
+	}
+
+	// Sets the mute fade and loop seam crossfade lengths (in samples) for audio takes created
+	// from now on. Zero disables the respective fade. Existing takes keep their lengths.
+	pub fn set_audiotake_fade_lengths(&mut self, fade_length: u32, crossfade_length: u32) {
+		self.fade_length = fade_length;
+		self.crossfade_length = crossfade_length;
+	}
+
+	pub fn audiotake_fade_lengths(&self) -> (u32, u32) {
+		(self.fade_length, self.crossfade_length)
+	}
+
+	// If `length` is known in advance, the take finishes recording by itself without
+	// `finish_audiotake` being called.
+	pub fn add_audiotake(&mut self, audiodev_id: usize, unmuted: bool, length: Option<u32>) -> Result<u32,()> {
+		let n_channels = self.devices.get(&audiodev_id).ok_or(())?.info.n_channels;
+		if length == Some(0) {
+			return Err(());
+		}
+
 		let id = self.next_id.gen();
+		let mut take = AudioTake::new(id, audiodev_id, unmuted, n_channels, CHUNKSIZE);
+		take.length = length;
+		self.submit_audiotake(take)
+	}
 
-		let n_channels = self.devices[&audiodev_id].info.n_channels;
-		let take = AudioTake::new(id, audiodev_id, unmuted, n_channels, CHUNKSIZE);
-		let take_node = Box::new(AudioTakeNode::new(take));
+	pub fn add_finished_audiotake(&mut self, audiodev_id: usize, unmuted: bool, samples: &[Vec<f32>], length: u32) -> Result<u32,()> {
+		let n_channels = self.devices.get(&audiodev_id).ok_or(())?.info.n_channels;
+		if samples.len() != n_channels || samples.iter().any(|channel| channel.len() < length as usize) {
+			return Err(());
+		}
 
-		self.command_channel.send_message(Message::NewAudioTake(take_node))?;
-		self.devices.get_mut(&audiodev_id).unwrap().takes.insert(id, GuiAudioTake{id, audiodev_id, unmuted, length: None});
+		let id = self.next_id.gen();
+		let take = AudioTake::new_finished(id, audiodev_id, unmuted, samples, length, CHUNKSIZE);
+		self.submit_audiotake(take)
+	}
 
-		Ok(id)
+	// If `length` is known in advance, the take finishes recording by itself without
+	// `finish_miditake` being called.
+	pub fn add_miditake(&mut self, mididev_id: usize, unmuted: bool, length: Option<u32>) -> Result<u32,()> {
+		if !self.mididevices.contains_key(&mididev_id) || length == Some(0) {
+			return Err(());
+		}
+
+		let id = self.next_id.gen();
+		let mut take = MidiTake::new(id, mididev_id, unmuted);
+		take.length = length;
+		self.submit_miditake(take)
 	}
 
-	pub fn add_miditake(&mut self, mididev_id: usize, unmuted: bool) -> Result<u32,()> {
+	pub fn add_finished_miditake(&mut self, mididev_id: usize, unmuted: bool, events: &[MidiMessage], length: u32) -> Result<u32,()> {
+		if !self.mididevices.contains_key(&mididev_id) || events.iter().any(|event| event.timestamp >= length) {
+			return Err(());
+		}
+
 		let id = self.next_id.gen();
+		let take = MidiTake::new_finished(id, mididev_id, unmuted, events, length);
+		self.submit_miditake(take)
+	}
 
-		let take = MidiTake::new(id, mididev_id, unmuted);
-		let take_node = Box::new(MidiTakeNode::new(take));
+	pub fn audiotake_samples(&self, audiodev_id: usize, take_id: u32) -> Option<Vec<Vec<f32>>> {
+		let take = self.devices.get(&audiodev_id)?.takes.get(&take_id)?;
+		let n_samples = (take.length? + take.crossfade_length) as usize;
+		Some(take.samples.iter().map(|channel| channel.map_to_vec(n_samples, AtomicSample::get)).collect())
+	}
 
-		self.command_channel.send_message(Message::NewMidiTake(take_node))?;
-		self.mididevices.get_mut(&mididev_id).unwrap().takes.insert(id, GuiMidiTake{id, mididev_id, unmuted, length: None});
-		Ok(id)
+	pub fn miditake_events(&self, mididev_id: usize, take_id: u32) -> Option<Vec<MidiMessage>> {
+		let take = self.mididevices.get(&mididev_id)?.takes.get(&take_id)?;
+		let length = take.length?;
+		Some(take.events.to_vec(usize::MAX).into_iter().filter(|event| event.timestamp < length).collect())
 	}
 
 	pub fn finish_audiotake(&mut self, audiodev_id: usize, take_id: u32, take_length: u32) -> Result<(),()> {
@@ -189,21 +557,410 @@ impl<Driver: DriverTrait> new pub FrontendTrait for FrontendThreadState<Driver>
 		Ok(())
 	}
 
+	// Undoes `finish_audiotake`. Has no effect if the take has already stopped recording,
+	// in which case `audiotake_finished` will be called later on.
+	pub fn unfinish_audiotake(&mut self, audiodev_id: usize, take_id: u32) -> Result<(),()> {
+		let take = self.devices.get_mut(&audiodev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		if take.length.is_none() {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::UnfinishAudioTake(take.id))?;
+		take.length = None;
+		Ok(())
+	}
+
+	pub fn unfinish_miditake(&mut self, mididev_id: usize, take_id: u32) -> Result<(),()> {
+		let take = self.mididevices.get_mut(&mididev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		if take.length.is_none() {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::UnfinishMidiTake(take.id))?;
+		take.length = None;
+		Ok(())
+	}
+
+	// Must be called when the engine reports that a take has finished recording.
+	pub fn audiotake_finished(&mut self, audiodev_id: usize, take_id: u32, length: u32) {
+		if let Some(take) = self.devices.get_mut(&audiodev_id).and_then(|d| d.takes.get_mut(&take_id)) {
+			take.length = Some(length);
+		}
+	}
+	pub fn miditake_finished(&mut self, mididev_id: usize, take_id: u32, length: u32) {
+		if let Some(take) = self.mididevices.get_mut(&mididev_id).and_then(|d| d.takes.get_mut(&take_id)) {
+			take.length = Some(length);
+			take.recording_finished = true;
+		}
+	}
+
+	// Deletes a take, which may also be retired. Takes that are waiting or recording are
+	// cancelled, i.e. they are never played. Their buffers are freed by the destructor thread.
+	pub fn delete_audiotake(&mut self, audiodev_id: usize, take_id: u32) -> Result<(),()> {
+		let dev = self.devices.get_mut(&audiodev_id).ok_or(())?;
+		if !dev.takes.contains_key(&take_id) && !dev.retired_takes.contains_key(&take_id) {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::DeleteTake(take_id))?;
+		dev.takes.remove(&take_id);
+		dev.retired_takes.remove(&take_id);
+		Ok(())
+	}
+
+	pub fn delete_miditake(&mut self, mididev_id: usize, take_id: u32) -> Result<(),()> {
+		let dev = self.mididevices.get_mut(&mididev_id).ok_or(())?;
+		if !dev.takes.contains_key(&take_id) && !dev.retired_takes.contains_key(&take_id) {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::DeleteTake(take_id))?;
+		dev.takes.remove(&take_id);
+		dev.retired_takes.remove(&take_id);
+		Ok(())
+	}
+
+	// Stops playing a finished take, but keeps it so that it can be brought back using
+	// `restore_audiotake`. Retired takes must eventually be deleted.
+	pub fn retire_audiotake(&mut self, audiodev_id: usize, take_id: u32) -> Result<(),()> {
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+		let dev = self.devices.get_mut(&audiodev_id).ok_or(())?;
+		move_take(&mut self.command_channel, &mut dev.takes, &mut dev.retired_takes, take_id, |take| take.length.is_some(), Message::RetireTake(take_id))
+	}
+
+	pub fn retire_miditake(&mut self, mididev_id: usize, take_id: u32) -> Result<(),()> {
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+		let dev = self.mididevices.get_mut(&mididev_id).ok_or(())?;
+		move_take(&mut self.command_channel, &mut dev.takes, &mut dev.retired_takes, take_id, |take| take.length.is_some(), Message::RetireTake(take_id))
+	}
+
+	pub fn restore_audiotake(&mut self, audiodev_id: usize, take_id: u32) -> Result<(),()> {
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+		let dev = self.devices.get_mut(&audiodev_id).ok_or(())?;
+		move_take(&mut self.command_channel, &mut dev.retired_takes, &mut dev.takes, take_id, |_| true, Message::RestoreTake(take_id))
+	}
+
+	pub fn restore_miditake(&mut self, mididev_id: usize, take_id: u32) -> Result<(),()> {
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+		let dev = self.mididevices.get_mut(&mididev_id).ok_or(())?;
+		move_take(&mut self.command_channel, &mut dev.retired_takes, &mut dev.takes, take_id, |_| true, Message::RestoreTake(take_id))
+	}
+
 	pub fn set_audiotake_unmuted(&mut self, audiodev_id: usize, take_id: u32, unmuted: bool) -> Result<(),()> {
 		let take = &mut self.devices.get_mut(&audiodev_id).unwrap().takes.get_mut(&take_id).unwrap(); // TODO propagate error
-		if take.unmuted == unmuted { return Ok(()); }
+		if take.unmuted == unmuted && take.scheduled_unmute.is_none() { return Ok(()); }
 		self.command_channel.send_message(Message::SetAudioMute(take.id, unmuted))?;
 		take.unmuted = unmuted;
+		take.scheduled_unmute = None;
 		Ok(())
 	}
 	pub fn set_miditake_unmuted(&mut self, mididev_id: usize, take_id: u32, unmuted: bool) -> Result<(),()> {
 		let take = &mut self.mididevices.get_mut(&mididev_id).unwrap().takes.get_mut(&take_id).unwrap(); // TODO propagate error
-		if take.unmuted == unmuted { return Ok(()); }
+		if take.unmuted == unmuted && take.scheduled_unmute.is_none() { return Ok(()); }
 		self.command_channel.send_message(Message::SetMidiMute(take.id, unmuted))?;
 		take.unmuted = unmuted;
+		take.scheduled_unmute = None;
+		Ok(())
+	}
+
+	// Schedules the take to be muted or unmuted at the next loop boundary. `None` cancels
+	// a pending schedule.
+	pub fn schedule_audiotake_unmuted(&mut self, audiodev_id: usize, take_id: u32, unmuted: Option<bool>) -> Result<(),()> {
+		let take = self.devices.get_mut(&audiodev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		if take.scheduled_unmute == unmuted { return Ok(()); }
+		self.command_channel.send_message(Message::ScheduleAudioMute(take.id, unmuted))?;
+		take.scheduled_unmute = unmuted;
 		Ok(())
 	}
+	pub fn schedule_miditake_unmuted(&mut self, mididev_id: usize, take_id: u32, unmuted: Option<bool>) -> Result<(),()> {
+		let take = self.mididevices.get_mut(&mididev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		if take.scheduled_unmute == unmuted { return Ok(()); }
+		self.command_channel.send_message(Message::ScheduleMidiMute(take.id, unmuted))?;
+		take.scheduled_unmute = unmuted;
+		Ok(())
+	}
+
+	// Sets the take's linear gain and its balance between the left (-1) and right (1) channels.
+	pub fn set_audiotake_gain(&mut self, audiodev_id: usize, take_id: u32, gain: f32, pan: f32) -> Result<(),()> {
+		if !(gain.is_finite() && gain >= 0.0 && (-1.0..=1.0).contains(&pan)) {
+			return Err(());
+		}
+		let take = self.devices.get_mut(&audiodev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		self.command_channel.send_message(Message::SetAudioGain(take.id, gain, pan))?;
+		take.gain = gain;
+		take.pan = pan;
+		Ok(())
+	}
+
+	// Cued takes are played into the monitor bus even while they are muted.
+	pub fn set_audiotake_cued(&mut self, audiodev_id: usize, take_id: u32, cued: bool) -> Result<(),()> {
+		let take = self.devices.get_mut(&audiodev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		if take.cued == cued { return Ok(()); }
+		self.command_channel.send_message(Message::SetAudioCue(take.id, cued))?;
+		take.cued = cued;
+		Ok(())
+	}
+
+	// While overdubbing, the input is summed into the finished take as it plays, and what was
+	// there before is scaled by `feedback` (between 0 and 1). None stops overdubbing.
+	pub fn set_audiotake_overdub(&mut self, audiodev_id: usize, take_id: u32, feedback: Option<f32>) -> Result<(),()> {
+		if let Some(feedback) = feedback {
+			if !(0.0..=1.0).contains(&feedback) {
+				return Err(());
+			}
+		}
+		// the rescaled copy of a take would lose what is overdubbed
+		if feedback.is_some() && self.loop_length_change_pending() {
+			return Err(());
+		}
+		let take = self.devices.get_mut(&audiodev_id).ok_or(())?.takes.get_mut(&take_id).ok_or(())?;
+		if take.length.is_none() {
+			return Err(());
+		}
+		self.command_channel.send_message(Message::SetAudioOverdub(take.id, feedback))?;
+		take.overdub_feedback = feedback;
+		Ok(())
+	}
+
+	// Must be called when the engine reports that a scheduled mute change has happened.
+	pub fn audiotake_mute_changed(&mut self, audiodev_id: usize, take_id: u32, unmuted: bool) {
+		if let Some(take) = self.devices.get_mut(&audiodev_id).and_then(|d| d.takes.get_mut(&take_id)) {
+			take.unmuted = unmuted;
+			take.scheduled_unmute = None;
+		}
+	}
+	pub fn miditake_mute_changed(&mut self, mididev_id: usize, take_id: u32, unmuted: bool) {
+		if let Some(take) = self.mididevices.get_mut(&mididev_id).and_then(|d| d.takes.get_mut(&take_id)) {
+			take.unmuted = unmuted;
+			take.scheduled_unmute = None;
+		}
+	}
+}
+}
+
+/// Moves the take `take_id` between the playing and the retired takes of a device, after
+/// sending `message` to let the audio thread do the same. Fails if the take is not in `from`
+/// or `movable` does not hold for it.
+fn move_take<T, M: std::fmt::Debug>(command_channel: &mut RetryChannelPush<M>, from: &mut HashMap<u32, T>, to: &mut HashMap<u32, T>, take_id: u32, movable: impl Fn(&T) -> bool, message: M) -> Result<(),()> {
+	if !movable(from.get(&take_id).ok_or(())?) {
+		return Err(());
+	}
+	command_channel.send_message(message)?;
+	let take = from.remove(&take_id).unwrap();
+	to.insert(take_id, take);
+	Ok(())
+}
+
+impl GuiAudioTake {
+	fn new(take: &AudioTake) -> GuiAudioTake {
+		GuiAudioTake {
+			id: take.id,
+			audiodev_id: take.audiodev_id,
+			unmuted: take.unmuted,
+			scheduled_unmute: None,
+			length: take.length,
+			crossfade_length: take.crossfade_length(),
+			gain: take.gain,
+			pan: take.pan,
+			cued: take.cued,
+			overdub_feedback: take.overdub_feedback,
+			samples: take.samples.iter().map(|channel| channel.reader()).collect()
+		}
+	}
+
+	/// Returns whether the take is completely recorded and not being overdubbed, which
+	/// would lose what is overdubbed until a rescaled copy takes effect.
+	fn can_be_rescaled(&self) -> bool {
+		self.overdub_feedback.is_none() &&
+			self.length.map_or(false, |length| self.samples.iter().all(|channel| channel.len() >= length as usize))
+	}
+}
+
+impl GuiMidiTake {
+	fn new(take: &MidiTake) -> GuiMidiTake {
+		GuiMidiTake {
+			id: take.id,
+			mididev_id: take.mididev_id,
+			unmuted: take.unmuted,
+			scheduled_unmute: None,
+			length: take.length,
+			recording_finished: take.record_state == RecordState::Finished,
+			events: take.events.reader()
+		}
+	}
+}
+
+impl<Driver: DriverTrait> FrontendThreadState<Driver> {
+	fn submit_audiotake(&mut self, mut take: AudioTake) -> Result<u32,()> {
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+		take.fade_length = self.fade_length;
+		take.gain_ramp_length = self.gain_ramp_length();
+		take.set_crossfade_length(self.crossfade_length);
+
+		let gui_take = GuiAudioTake::new(&take);
+		let take_node = Box::new(AudioTakeNode::new(take));
+
+		self.command_channel.send_message(Message::NewAudioTake(take_node))?;
+		let id = gui_take.id;
+		self.devices.get_mut(&gui_take.audiodev_id).unwrap().takes.insert(id, gui_take);
+		Ok(id)
+	}
+
+	fn submit_miditake(&mut self, take: MidiTake) -> Result<u32,()> {
+		if self.loop_length_change_pending() {
+			return Err(());
+		}
+		let gui_take = GuiMidiTake::new(&take);
+		let take_node = Box::new(MidiTakeNode::new(take));
+
+		self.command_channel.send_message(Message::NewMidiTake(take_node))?;
+		let id = gui_take.id;
+		self.mididevices.get_mut(&gui_take.mididev_id).unwrap().takes.insert(id, gui_take);
+		Ok(id)
+	}
+
+	/// Number of samples over which take gain and pan changes are smoothed, like the chain
+	/// gains in the audio thread
+	fn gain_ramp_length(&self) -> u32 {
+		self.driver.sample_rate() / 200
+	}
+
+	/// Copies all takes, so that they can be stretched from the current loop length to
+	/// `loop_length` by `TakeRescaling::rescale`. Fails if a take is not completely recorded
+	/// yet or is being overdubbed.
+	fn copy_takes(&self, loop_length: u32, n_beats: u32) -> Result<TakeRescaling,()> {
+		let mut audiotakes = Vec::new();
+		for (&audiodev_id, dev) in self.devices.iter() {
+			for (&take_id, gui_take) in dev.takes.iter() {
+				if !gui_take.can_be_rescaled() {
+					return Err(());
+				}
+				audiotakes.push(AudioTakeCopy {
+					id: take_id,
+					audiodev_id,
+					unmuted: gui_take.unmuted,
+					length: gui_take.length.unwrap(),
+					crossfade_length: gui_take.crossfade_length,
+					gain: gui_take.gain,
+					pan: gui_take.pan,
+					cued: gui_take.cued,
+					samples: self.audiotake_samples(audiodev_id, take_id).ok_or(())?
+				});
+			}
+		}
+
+		let mut miditakes = Vec::new();
+		for (&mididev_id, dev) in self.mididevices.iter() {
+			for (&take_id, gui_take) in dev.takes.iter() {
+				if !gui_take.recording_finished {
+					return Err(());
+				}
+				miditakes.push(MidiTakeCopy {
+					id: take_id,
+					mididev_id,
+					unmuted: gui_take.unmuted,
+					length: gui_take.length.unwrap(),
+					events: self.miditake_events(mididev_id, take_id).ok_or(())?
+				});
+			}
+		}
+
+		Ok(TakeRescaling {
+			old_loop_length: self.loop_length(),
+			loop_length,
+			n_beats,
+			fade_length: self.fade_length,
+			gain_ramp_length: self.gain_ramp_length(),
+			audiotakes,
+			miditakes
+		})
+	}
+}
+
+struct AudioTakeCopy {
+	id: u32,
+	audiodev_id: usize,
+	unmuted: bool,
+	length: u32,
+	crossfade_length: u32,
+	gain: f32,
+	pan: f32,
+	cued: bool,
+	samples: Vec<Vec<f32>>
+}
+
+struct MidiTakeCopy {
+	id: u32,
+	mididev_id: usize,
+	unmuted: bool,
+	length: u32,
+	events: Vec<MidiMessage>
 }
+
+/// Copies of all takes, made by `FrontendTrait::prepare_loop_length_change`. Rescaling them
+/// does not need the engine, so it can be done on a worker thread.
+pub struct TakeRescaling {
+	old_loop_length: u32,
+	loop_length: u32,
+	n_beats: u32,
+	fade_length: u32,
+	gain_ramp_length: u32,
+	audiotakes: Vec<AudioTakeCopy>,
+	miditakes: Vec<MidiTakeCopy>
+}
+
+impl TakeRescaling {
+	/// Stretches the takes to the new loop length. Audio takes are resampled. Not
+	/// real-time-safe!
+	pub fn rescale(self) -> Box<SongLengthChange> {
+		let rescale = |value: u32| std::cmp::max((value as u64 * self.loop_length as u64 / self.old_loop_length as u64) as u32, 1);
+
+		let mut change = Box::new(SongLengthChange {
+			song_length: self.loop_length,
+			n_beats: self.n_beats,
+			audiotakes: LinkedList::new(AudioTakeAdapter::new()),
+			miditakes: LinkedList::new(MidiTakeAdapter::new())
+		});
+
+		for copy in self.audiotakes.iter() {
+			let new_length = rescale(copy.length);
+			let samples: Vec<Vec<f32>> = copy.samples.iter().map(|channel| {
+				let mut channel = resample(channel, copy.length, new_length);
+				channel.resize((new_length + copy.crossfade_length) as usize, 0.0);
+				channel
+			}).collect();
+
+			let mut take = AudioTake::new_finished(copy.id, copy.audiodev_id, copy.unmuted, &samples, new_length, CHUNKSIZE);
+			take.fade_length = self.fade_length;
+			take.gain_ramp_length = self.gain_ramp_length;
+			take.gain = copy.gain;
+			take.pan = copy.pan;
+			take.cued = copy.cued;
+			take.set_crossfade_length(copy.crossfade_length);
+			change.audiotakes.push_back(Box::new(AudioTakeNode::new(take)));
+		}
+
+		for copy in self.miditakes.iter() {
+			let new_length = rescale(copy.length);
+			let events: Vec<MidiMessage> = copy.events.iter()
+				.map(|event| MidiMessage {
+					timestamp: (event.timestamp as u64 * new_length as u64 / copy.length as u64) as u32,
+					..*event
+				})
+				.collect();
+
+			let take = MidiTake::new_finished(copy.id, copy.mididev_id, copy.unmuted, &events, new_length);
+			change.miditakes.push_back(Box::new(MidiTakeNode::new(take)));
+		}
+
+		change
+	}
 }
 
 fn find_first_free_index<T>(map: &HashMap<usize, T>, max: usize) -> Option<usize> {
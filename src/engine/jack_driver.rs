@@ -1,5 +1,8 @@
 use jack;
+use jack_sys;
 use super::driver_traits::*;
+use std::sync::{Arc, Mutex};
+use std::os::raw::{c_int, c_void};
 
 use super::backend::AudioThreadState;
 
@@ -31,7 +34,9 @@ impl JackClientState {
 }
 
 pub struct JackDriver {
-	client: JackClientState
+	client: JackClientState,
+	/// Position published by the audio thread for the timebase callback
+	timebase: Arc<Mutex<Option<BbtPosition>>>
 }
 
 impl JackDriver {
@@ -39,7 +44,8 @@ impl JackDriver {
 		let (client, _status) = jack::Client::new("loopfisch", jack::ClientOptions::NO_START_SERVER).unwrap();
 		println!("JACK running with sampling rate {} Hz, buffer size = {} samples", client.sample_rate(), client.buffer_size());
 		JackDriver {
-			client: JackClientState::NotActivated(client)
+			client: JackClientState::NotActivated(client),
+			timebase: Arc::new(Mutex::new(None))
 		}
 	}
 }
@@ -48,6 +54,7 @@ impl DriverTrait for JackDriver {
 	type MidiDev = MidiDevice;
 	type AudioDev = AudioDevice;
 	type ProcessScope = jack::ProcessScope;
+	type Transport = JackTransport;
 	type Error = jack::Error;
 
 	fn activate(&mut self, audio_thread_state: AudioThreadState<JackDriver>) {
@@ -81,11 +88,89 @@ impl DriverTrait for JackDriver {
 		})
 	}
 
+	fn transport(&self) -> JackTransport {
+		JackTransport {
+			client: self.client.as_jack_client().raw(),
+			timebase: self.timebase.clone()
+		}
+	}
+
+	fn set_timebase_master(&mut self, master: bool) -> Result<(), jack::Error> {
+		let client = self.client.as_jack_client().raw();
+		let result = if master {
+			let arg = Arc::as_ptr(&self.timebase) as *mut c_void;
+			unsafe { jack_sys::jack_set_timebase_callback(client, 0, Some(timebase_callback), arg) }
+		}
+		else {
+			unsafe { jack_sys::jack_release_timebase(client) }
+		};
+		if result == 0 { Ok(()) } else { Err(jack::Error::UnknownError) }
+	}
+
 	fn sample_rate(&self) -> u32 {
 		self.client.as_jack_client().sample_rate() as u32
 	}
 }
 
+/// Called by JACK in the process thread, right after the process callback, while we are
+/// timebase master. `arg` points to `JackDriver::timebase`.
+unsafe extern "C" fn timebase_callback(_state: jack_sys::jack_transport_state_t, _n_frames: jack_sys::jack_nframes_t, pos: *mut jack_sys::jack_position_t, _new_pos: c_int, arg: *mut c_void) {
+	let timebase = &*(arg as *const Mutex<Option<BbtPosition>>);
+	if let Ok(guard) = timebase.try_lock() {
+		if let Some(bbt) = *guard {
+			let pos = &mut *pos;
+			pos.valid = jack_sys::JackPositionBBT;
+			pos.bar = bbt.bar as i32;
+			pos.beat = bbt.beat as i32;
+			pos.tick = bbt.tick as i32;
+			pos.bar_start_tick = (bbt.bar - 1) as f64 * bbt.beats_per_bar as f64 * bbt.ticks_per_beat;
+			pos.beats_per_bar = bbt.beats_per_bar;
+			pos.beat_type = 4.0;
+			pos.ticks_per_beat = bbt.ticks_per_beat;
+			pos.beats_per_minute = bbt.beats_per_minute;
+		}
+	}
+}
+
+pub struct JackTransport {
+	client: *mut jack_sys::jack_client_t,
+	timebase: Arc<Mutex<Option<BbtPosition>>>
+}
+
+// the client outlives the audio thread, and the transport functions are thread-safe
+unsafe impl Send for JackTransport {}
+
+impl TransportTrait for JackTransport {
+	fn query(&self) -> TransportInfo {
+		let mut pos = std::mem::MaybeUninit::<jack_sys::jack_position_t>::zeroed();
+		let state = unsafe { jack_sys::jack_transport_query(self.client, pos.as_mut_ptr()) };
+		let pos = unsafe { pos.assume_init() };
+		TransportInfo {
+			rolling: state == jack_sys::JackTransportRolling,
+			frame: pos.frame,
+			bbt: if pos.valid & jack_sys::JackPositionBBT != 0 {
+				Some(BbtPosition {
+					bar: pos.bar as u32,
+					beat: pos.beat as u32,
+					tick: pos.tick as u32,
+					beats_per_bar: pos.beats_per_bar,
+					ticks_per_beat: pos.ticks_per_beat,
+					beats_per_minute: pos.beats_per_minute
+				})
+			}
+			else {
+				None
+			}
+		}
+	}
+
+	fn publish(&mut self, position: BbtPosition) {
+		if let Ok(mut guard) = self.timebase.try_lock() {
+			*guard = Some(position);
+		}
+	}
+}
+
 pub struct MidiDevice {
 	in_port: jack::Port<jack::MidiIn>,
 	out_port: jack::Port<jack::MidiOut>,
@@ -1,24 +1,79 @@
-use super::takes::{AudioTakeNode,MidiTakeNode};
+use super::takes::{AudioTakeNode,MidiTakeNode,AudioTakeAdapter,MidiTakeAdapter};
+use intrusive_collections::LinkedList;
+use super::data::{JackTransportMode, MetronomeSettings, MetronomeSound};
+
+/// A new song length together with replacements for all existing takes, rescaled to the
+/// new length. The replacements have the same ids as the takes they replace.
+pub struct SongLengthChange {
+	pub song_length: u32,
+	pub n_beats: u32,
+	pub audiotakes: LinkedList<AudioTakeAdapter>,
+	pub miditakes: LinkedList<MidiTakeAdapter>,
+}
+
+impl std::fmt::Debug for SongLengthChange {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SongLengthChange")
+			.field("song_length", &self.song_length)
+			.field("n_beats", &self.n_beats)
+			.finish()
+	}
+}
+
+/// Mono sample data, which is left out when messages are printed.
+pub struct Samples(pub Vec<f32>);
+
+impl std::fmt::Debug for Samples {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Samples({} samples)", self.0.len())
+	}
+}
 
 #[derive(Debug)]
 pub enum Message<AudioDevice, MidiDevice> {
 	SetSongLength(u32, u32),
+	ChangeSongLength(Box<SongLengthChange>),
 	UpdateAudioDevice(usize, Option<AudioDevice>),
 	UpdateMidiDevice(usize, Option<MidiDevice>),
 	NewAudioTake(Box<AudioTakeNode>),
 	NewMidiTake(Box<MidiTakeNode>),
 	RestartMidiTransport(usize),
+	StartTransport(bool),
+	StopTransport(bool),
+	SetMidiClock(bool, i32),
+	SetMidiDeviceClock(usize, Option<i32>),
+	SetFreeRunning(bool),
+	EndFreeRunning(u32, u32, u32),
+	SetClockSource(Option<usize>),
+	SetJackTransportMode(JackTransportMode),
 	SetAudioEcho(usize, bool),
+	SetAudioDeviceGain(usize, f32),
+	SetBusGains(f32, f32, f32),
+	SetMetronome(MetronomeSettings),
+	SetMetronomeSound(MetronomeSound, Option<Samples>),
 	SetAudioMute(u32,bool),
 	SetMidiMute(u32,bool),
+	ScheduleAudioMute(u32,Option<bool>),
+	ScheduleMidiMute(u32,Option<bool>),
+	SetAudioGain(u32, f32, f32),
+	SetAudioCue(u32, bool),
+	SetAudioOverdub(u32, Option<f32>),
 	FinishAudioTake(u32, u32),
 	FinishMidiTake(u32, u32),
+	UnfinishAudioTake(u32),
+	UnfinishMidiTake(u32),
+	RetireTake(u32),
+	RestoreTake(u32),
 	DeleteTake(u32)
 }
 
 pub enum DestructionRequest<AudioDevice, MidiDevice> {
 	AudioDevice(AudioDevice),
 	MidiDevice(MidiDevice),
+	AudioTake(Box<AudioTakeNode>),
+	MidiTake(Box<MidiTakeNode>),
+	SongLengthChange(Box<SongLengthChange>),
+	MetronomeSound(Vec<f32>),
 	End
 }
 
@@ -1,10 +1,24 @@
 use std::cmp::min;
 use super::driver_traits::*;
+use super::data::{MetronomeSettings, MetronomeMode, MetronomeSound};
+
+/// Level of the clicks between the beats, relative to the normal clicks
+const SUBDIVISION_LEVEL: f32 = 0.5;
+
+/// Samples played instead of the synthesized clicks. They must have the engine's sample
+/// rate, and are preloaded so that the audio thread never allocates.
+#[derive(Default)]
+pub struct MetronomeSounds {
+	/// Played on accented beats
+	pub accent: Option<Vec<f32>>,
+	/// Played on all other beats and subdivisions
+	pub click: Option<Vec<f32>>
+}
 
 pub struct AudioMetronome<T: AudioDeviceTrait> {
 	device: T,
-	volume: f32,
-	unmuted: bool
+	settings: MetronomeSettings,
+	sounds: MetronomeSounds
 }
 
 fn ceil_div(a: u32, b: u32) -> u32 { (a+b-1)/b }
@@ -13,35 +27,77 @@ impl<T: AudioDeviceTrait> AudioMetronome<T> {
 	pub fn new(device: T) -> AudioMetronome<T> {
 		AudioMetronome {
 			device,
-			volume: 0.3,
-			unmuted: true
+			settings: MetronomeSettings::default(),
+			sounds: MetronomeSounds::default()
+		}
+	}
+
+	pub fn set_settings(&mut self, settings: MetronomeSettings) {
+		self.settings = settings;
+	}
+
+	/// Replaces one of the click sounds. Returns the previous one, which must not be dropped in
+	/// the audio thread.
+	pub fn replace_sound(&mut self, which: MetronomeSound, sound: Option<Vec<f32>>) -> Option<Vec<f32>> {
+		match which {
+			MetronomeSound::Accent => std::mem::replace(&mut self.sounds.accent, sound),
+			MetronomeSound::Click => std::mem::replace(&mut self.sounds.click, sound)
 		}
 	}
 
+	/// Returns whether the metronome should click, depending on whether takes are currently
+	/// waiting to be recorded or recording.
+	pub fn is_active(&self, recording: bool) -> bool {
+		self.settings.unmuted && (self.settings.mode == MetronomeMode::Always || recording)
+	}
+
+	pub fn device_mut(&mut self) -> &mut T {
+		&mut self.device
+	}
+
 	pub fn process(&mut self, position: u32, song_length: u32, beats: u32, sample_rate: u32, scope: &T::Scope) {
-		if !self.unmuted { return; }
-		let period = ceil_div(song_length, beats);
+		let period = ceil_div(song_length, beats * self.settings.subdivision);
 		let latency = self.device.playback_latency();
+		let settings = self.settings;
+		let sounds = &*self.sounds;
 		for buffers in self.device.playback_and_capture_buffers(scope) {
 			for i in 0..scope.n_frames() {
-				buffers.0[i as usize] = self.volume * Self::process_one((position + i + latency) % song_length, period, sample_rate);
+				buffers.0[i as usize] = settings.volume * Self::process_one((position + i + latency) % song_length, period, sample_rate, &settings, sounds);
 			}
 		}
 	}
 
-	fn process_one(position: u32, period: u32, sample_rate: u32) -> f32 {
-		let position_in_beat = position % period;
-		let beat = position / period;
+	/// Returns the metronome's output at `position`, with a click every `period` frames.
+	fn process_one(position: u32, period: u32, sample_rate: u32, settings: &MetronomeSettings, sounds: &MetronomeSounds) -> f32 {
+		let position_in_click = position % period;
+		let click = position / period;
+		let beat = click / settings.subdivision;
 
-		let click_length = sample_rate / 10;
+		let accented = click % settings.subdivision == 0 && beat < 32 && settings.accents & (1 << beat) != 0;
+		let (sample, freq, level) =
+			if accented {
+				(&sounds.accent, 2.0 * settings.pitch, settings.accent)
+			}
+			else if click % settings.subdivision == 0 {
+				(&sounds.click, settings.pitch, 1.0)
+			}
+			else {
+				(&sounds.click, settings.pitch, SUBDIVISION_LEVEL)
+			};
 
-		let volume = 1.0 - min(position_in_beat, click_length) as f32 / click_length as f32;
-		let freq = if beat == 0 { 880 } else { 440 };
+		let value = match sample {
+			Some(sample) => sample.get(position_in_click as usize).cloned().unwrap_or(0.0),
+			None => {
+				let click_length = sample_rate / 10;
 
-		let sawtooth: f32 = (position_in_beat as f32 / sample_rate as f32 * freq as f32).fract();
-		let square = if sawtooth < 0.5 {-1.0} else {1.0};
+				let volume = 1.0 - min(position_in_click, click_length) as f32 / click_length as f32;
+				let sawtooth: f32 = (position_in_click as f32 / sample_rate as f32 * freq).fract();
+				let square = if sawtooth < 0.5 {-1.0} else {1.0};
+				square * volume
+			}
+		};
 
-		return square * volume;
+		return value * level;
 	}
 }
 
@@ -82,6 +138,53 @@ mod tests {
 		}
 	}
 
+	#[test]
+	pub fn accent_sets_the_level_of_beat_one() {
+		let song_length = SAMPLE_RATE * 2;
+		let device = DummyAudioDevice::new(1, 0, 0);
+		let mut metronome = AudioMetronome::new(device);
+		metronome.set_settings(MetronomeSettings { accent: 0.0, ..MetronomeSettings::default() });
+		let mut scope = DummyScope::new();
+		scope.run_for(song_length, 1024, |scope| metronome.process(scope.time, song_length, 4, SAMPLE_RATE, scope));
+		let ticks = testutils::ticks(&metronome.device.playback_buffers[0], 0.2);
+		assert!(ticks.len() == 3);
+		assert!(ticks.iter().all(|x| *x as u32 >= song_length / 4));
+	}
+
+	#[test]
+	pub fn subdivisions_add_clicks_between_beats() {
+		let song_length = SAMPLE_RATE * 2;
+		for subdivision in 2..=4 {
+			let device = DummyAudioDevice::new(1, 0, 0);
+			let mut metronome = AudioMetronome::new(device);
+			metronome.set_settings(MetronomeSettings { subdivision, ..MetronomeSettings::default() });
+			let mut scope = DummyScope::new();
+			scope.run_for(song_length, 1024, |scope| metronome.process(scope.time, song_length, 4, SAMPLE_RATE, scope));
+			assert!(testutils::ticks(&metronome.device.playback_buffers[0], 0.12).len() as u32 == 4 * subdivision);
+			assert!(testutils::ticks(&metronome.device.playback_buffers[0], 0.2).len() == 4, "subdivisions must be quieter than beats");
+		}
+	}
+
+	#[test]
+	pub fn samples_are_played_on_accented_and_other_beats() {
+		let song_length = SAMPLE_RATE * 2;
+		let device = DummyAudioDevice::new(1, 0, 0);
+		let mut metronome = AudioMetronome::new(device);
+		metronome.set_settings(MetronomeSettings { volume: 1.0, accents: 0b0101, ..MetronomeSettings::default() });
+		metronome.replace_sound(MetronomeSound::Accent, Some(vec![1.0; 10]));
+		metronome.replace_sound(MetronomeSound::Click, Some(vec![0.5; 20]));
+		let mut scope = DummyScope::new();
+		scope.run_for(song_length, 1024, |scope| metronome.process(scope.time, song_length, 4, SAMPLE_RATE, scope));
+
+		let beat = (song_length / 4) as usize;
+		let buffer = &metronome.device.playback_buffers[0];
+		for (i, expected) in [(0, 1.0), (1, 0.5), (2, 1.0), (3, 0.5)].iter() {
+			let length = if *expected == 1.0 { 10 } else { 20 };
+			assert!(buffer[i*beat .. i*beat + length].iter().all(|x| x == expected));
+			assert!(buffer[i*beat + length .. (i+1)*beat].iter().all(|x| *x == 0.0));
+		}
+	}
+
 	#[test]
 	pub fn all_channels_have_same_data() {
 		let channels = 3;
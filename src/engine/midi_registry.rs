@@ -50,13 +50,16 @@ impl MidiNoteRegistry {
 	}
 
 	pub fn send_noteons(&mut self, device: &mut impl MidiDeviceTrait) {
+		self.send_noteons_at(device, 0);
+	}
+	pub fn send_noteons_at(&mut self, device: &mut impl MidiDeviceTrait, timestamp: u32) {
 		// FIXME: queue_event could fail; better allow for a "second chance"
 		for channel in 0..16 {
 			for note in 0..128 {
 				let velocity = self.playing_notes[channel as usize][note as usize];
 				if velocity != 0 {
 					device.queue_event( MidiMessage {
-						timestamp: 0,
+						timestamp,
 						data: [0x90 | channel, note, velocity],
 						datalen: 3
 					}).unwrap();
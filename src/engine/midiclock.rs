@@ -2,52 +2,160 @@ use super::driver_traits::*;
 use crate::midi_message::MidiMessage;
 
 pub struct MidiClock<T: MidiDeviceTrait> {
-	device: T
+	device: T,
+	/// If disabled, neither clock ticks nor transport messages are sent.
+	enabled: bool,
+	/// Delay of the clock ticks in frames. Negative values send them earlier.
+	offset: i32,
+	transport_start: Option<PendingTransportStart>
 }
 
 impl<T: MidiDeviceTrait> MidiClock<T> {
 	pub fn new(device: T) -> MidiClock<T> {
 		MidiClock {
-			device
+			device,
+			enabled: true,
+			offset: 0,
+			transport_start: None
 		}
 	}
 
-	pub fn process(&mut self, position_uncompensated: u32, song_length: u32, n_beats: u32, scope: &T::Scope) {
-		let factor = n_beats * 24;
-
-		let n_frames = scope.n_frames();
-		let latency = self.device.playback_latency();
-		let position_f = factor * ((position_uncompensated + latency) % song_length);
-		let song_length_f = factor * song_length;
-		let n_frames_f = factor * n_frames;
-		
-		let song_wraps_at_f = std::cmp::min(song_length_f - position_f, n_frames_f);
-
-		let n_clocks = n_beats * 24;
-		let period_per_clock_f = (song_length_f+n_clocks-1) / n_clocks; // round towards +inf
-
-		let mut time_since_last_clock_f = position_f % period_per_clock_f;
-		if time_since_last_clock_f == 0 {
-			time_since_last_clock_f = period_per_clock_f;
-		}
+	pub fn configure(&mut self, enabled: bool, offset: i32) {
+		self.enabled = enabled;
+		self.offset = offset;
+	}
 
-		for timestamp_f in
-			((period_per_clock_f - time_since_last_clock_f)..song_wraps_at_f).step_by(period_per_clock_f as usize)
-			.chain( (song_wraps_at_f..n_frames_f).step_by(period_per_clock_f as usize) )
-		{
+	/// Queues Stop (0xFC), which is sent at the beginning of the next period, and cancels a
+	/// pending Start or Continue.
+	pub fn send_transport_stop(&mut self) {
+		self.transport_start = None;
+		if self.enabled {
 			self.device.queue_event(MidiMessage {
-				timestamp: timestamp_f / factor,
-				data: [0xF8, 0, 0],
+				timestamp: 0,
+				data: [0xFC, 0, 0],
 				datalen: 1
-			}).unwrap();
-			// we can't do anything about errors here. But this is so unlikely to happen
-			// and would mess up MIDI timing, so let's better crash instead of silently ignore this.
+			}).ok(); // we can't do anything about lost events
 		}
+	}
+
+	/// Sends Start or Continue, preceded by the Song Position Pointer, once the song has reached
+	/// the next sixteenth note. `position` is the number of frames since the last rewind.
+	pub fn send_transport_start(&mut self, position: i64, song_length: u32, n_beats: u32) {
+		if self.enabled {
+			let position = position + self.device.playback_latency() as i64 - self.offset as i64;
+			self.transport_start = Some(PendingTransportStart::new(position, song_length, n_beats));
+		}
+	}
 
+	/// Like `process`, but sends no clock ticks. Used while the transport is stopped.
+	pub fn process_stopped(&mut self, scope: &T::Scope) {
+		self.device.commit_out_buffer(scope);
+	}
+
+	pub fn process(&mut self, position_uncompensated: u32, song_length: u32, n_beats: u32, scope: &T::Scope) {
+		if let Some(start) = self.transport_start.as_mut() {
+			if start.process(&mut self.device, scope.n_frames()) {
+				self.transport_start = None;
+			}
+		}
+		if self.enabled {
+			queue_clock_ticks(&mut self.device, position_uncompensated, song_length, n_beats, self.offset, scope.n_frames());
+		}
 		self.device.commit_out_buffer(scope);
 	}
 }
 
+/// Returns a Song Position Pointer message. The position is counted in sixteenth notes and
+/// truncated to 14 bits.
+pub fn song_position_pointer(sixteenths: u16) -> MidiMessage {
+	MidiMessage {
+		timestamp: 0,
+		data: [0xF2, (sixteenths & 0x7F) as u8, ((sixteenths >> 7) & 0x7F) as u8],
+		datalen: 3
+	}
+}
+
+/// Start or Continue, preceded by the Song Position Pointer, waiting for the song to reach the
+/// sixteenth note given in the pointer. Devices resume playing at that sixteenth, so the
+/// messages must be sent exactly when the song gets there.
+#[derive(Clone, Copy)]
+pub struct PendingTransportStart {
+	/// Number of sixteenth notes since the last rewind
+	sixteenths: u32,
+	/// Number of frames until the song reaches the sixteenth
+	frames_left: u32
+}
+
+impl PendingTransportStart {
+	/// `position` is the number of frames since the last rewind, compensated for the device's
+	/// latency. It is rounded up to the next sixteenth note.
+	pub fn new(position: i64, song_length: u32, n_beats: u32) -> PendingTransportStart {
+		let sixteenths_per_loop = n_beats as i64 * 4;
+		let position = position.max(0);
+		let sixteenths = (position * sixteenths_per_loop + song_length as i64 - 1) / song_length as i64;
+		let start = (sixteenths * song_length as i64 + sixteenths_per_loop - 1) / sixteenths_per_loop;
+		PendingTransportStart {
+			sixteenths: sixteenths as u32,
+			frames_left: (start - position) as u32
+		}
+	}
+
+	/// Queues the messages on `device` if the song reaches the sixteenth within the next
+	/// `n_frames` frames. Returns whether they have been queued.
+	pub fn process<T: MidiDeviceTrait>(&mut self, device: &mut T, n_frames: u32) -> bool {
+		if self.frames_left >= n_frames {
+			self.frames_left -= n_frames;
+			return false;
+		}
+
+		let status = if self.sixteenths == 0 { 0xFA } else { 0xFB }; // Start or Continue
+		let spp = song_position_pointer((self.sixteenths & 0x3FFF) as u16);
+		device.queue_event(MidiMessage { timestamp: self.frames_left, ..spp }).ok(); // we can't do anything about lost events
+		device.queue_event(MidiMessage {
+			timestamp: self.frames_left,
+			data: [status, 0, 0],
+			datalen: 1
+		}).ok(); // we can't do anything about lost events
+		true
+	}
+}
+
+/// Queues the clock ticks (24 per beat) that fall into the current period of `n_frames`
+/// frames on `device`, compensating for its playback latency. `offset` delays the ticks by
+/// the given number of frames, negative values send them earlier.
+pub fn queue_clock_ticks<T: MidiDeviceTrait>(device: &mut T, position_uncompensated: u32, song_length: u32, n_beats: u32, offset: i32, n_frames: u32) {
+	let factor = n_beats * 24;
+
+	let latency = device.playback_latency();
+	let position = (position_uncompensated as i64 + latency as i64 - offset as i64).rem_euclid(song_length as i64) as u32;
+	let position_f = factor * position;
+	let song_length_f = factor * song_length;
+	let n_frames_f = factor * n_frames;
+	
+	let song_wraps_at_f = std::cmp::min(song_length_f - position_f, n_frames_f);
+
+	let n_clocks = n_beats * 24;
+	let period_per_clock_f = (song_length_f+n_clocks-1) / n_clocks; // round towards +inf
+
+	let mut time_since_last_clock_f = position_f % period_per_clock_f;
+	if time_since_last_clock_f == 0 {
+		time_since_last_clock_f = period_per_clock_f;
+	}
+
+	for timestamp_f in
+		((period_per_clock_f - time_since_last_clock_f)..song_wraps_at_f).step_by(period_per_clock_f as usize)
+		.chain( (song_wraps_at_f..n_frames_f).step_by(period_per_clock_f as usize) )
+	{
+		device.queue_event(MidiMessage {
+			timestamp: timestamp_f / factor,
+			data: [0xF8, 0, 0],
+			datalen: 1
+		}).unwrap();
+		// we can't do anything about errors here. But this is so unlikely to happen
+		// and would mess up MIDI timing, so let's better crash instead of silently ignore this.
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::super::dummy_driver::*;
@@ -179,6 +287,69 @@ mod tests {
 		}
 	}
 
+	#[test]
+	pub fn offset_delays_clockticks() {
+		let sample_rate = 44100;
+		let n_beats = 4;
+		let song_length = sample_rate * n_beats * 60 / 120;
+		for offset in [-4096, -51, 0, 1, 32, 4096].iter() {
+			let device = DummyMidiDevice::new(0, 0);
+			let mut clock = MidiClock::new(device);
+			clock.configure(true, *offset);
+			let mut scope = DummyScope::new();
+			scope.next(2*song_length);
+			clock.process(scope.time % song_length, song_length, n_beats, &scope);
+			let expected = (song_length as i32 + offset) as u32;
+			assert!(clock.device.committed.iter().filter(|x| x.timestamp == expected).count() == 1);
+		}
+	}
+
+	#[test]
+	pub fn disabled_clock_sends_nothing() {
+		let device = DummyMidiDevice::new(0, 0);
+		let mut clock = MidiClock::new(device);
+		clock.configure(false, 0);
+		let mut scope = DummyScope::new();
+		scope.next(44100);
+		clock.send_transport_start(0, 88200, 4);
+		clock.process(scope.time, 88200, 4, &scope);
+		clock.send_transport_stop();
+		clock.process(scope.time, 88200, 4, &scope);
+		assert!(clock.device.committed.is_empty());
+	}
+
+	#[test]
+	pub fn transport_start_waits_for_the_next_sixteenth() {
+		let mut device = DummyMidiDevice::new(0, 0);
+		let mut scope = DummyScope::new();
+		// a sixteenth is 2756.25 frames long
+		let mut start = PendingTransportStart::new(44100 + 1000, 44100, 4);
+		scope.next(1024);
+		assert!(!start.process(&mut device, scope.n_frames()));
+		device.commit_out_buffer(&scope);
+		scope.next(1024);
+		assert!(start.process(&mut device, scope.n_frames()));
+		device.commit_out_buffer(&scope);
+		assert_eq!(device.committed, vec![
+			MidiMessage { timestamp: 1024 + 733, data: [0xF2, 17, 0], datalen: 3 },
+			MidiMessage { timestamp: 1024 + 733, data: [0xFB, 0, 0], datalen: 1 },
+		]);
+	}
+
+	#[test]
+	pub fn transport_start_on_a_sixteenth_is_sent_right_away() {
+		let mut device = DummyMidiDevice::new(0, 0);
+		let mut scope = DummyScope::new();
+		let mut start = PendingTransportStart::new(0, 44100, 4);
+		scope.next(128);
+		assert!(start.process(&mut device, scope.n_frames()));
+		device.commit_out_buffer(&scope);
+		assert_eq!(device.committed, vec![
+			MidiMessage { timestamp: 0, data: [0xF2, 0, 0], datalen: 3 },
+			MidiMessage { timestamp: 0, data: [0xFA, 0, 0], datalen: 1 },
+		]);
+	}
+
 	#[test]
 	pub fn latency_handled_correctly() {
 		let sample_rate = 44100;
@@ -0,0 +1,276 @@
+/// Weight of a newly measured tick interval in the tempo estimate
+const TEMPO_SMOOTHING: f32 = 0.01;
+/// Relative deviation between the measured tempo and the loop length beyond which a new
+/// loop length is suggested. Smaller deviations are handled by drift correction.
+const LOOP_LENGTH_TOLERANCE: f32 = 0.005;
+/// Number of tick intervals that must have been smoothed before the tempo estimate is
+/// trusted enough to suggest a loop length
+const MIN_SMOOTHED_TICKS: u32 = 48;
+/// Number of seconds after which a suggestion that has not been followed is repeated
+const SUGGESTION_RETRY_SECONDS: u32 = 5;
+
+/// Changes to the transport requested by the clock source.
+#[derive(Debug, PartialEq)]
+pub enum TransportChange {
+	/// The song should start playing such that it is at `position` at frame `at` of the
+	/// current period. `position` is 0 after a Start message.
+	Start { at: u32, position: u32 },
+	Stop,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum State {
+	Stopped,
+	/// Start or Continue has been received; playing begins with the next clock tick.
+	Starting,
+	Running,
+}
+
+/// Follows the clock ticks (0xF8) and the Start (0xFA), Continue (0xFB) and Stop (0xFC)
+/// messages of an external MIDI device.
+pub struct MidiClockSlave {
+	/// Number of frames processed so far. Tick times are measured on this clock.
+	now: u32,
+	last_tick: Option<u32>,
+	/// Smoothed number of frames per clock tick
+	tick_length: Option<f32>,
+	/// Number of intervals that have been smoothed into `tick_length` since it was last reset
+	smoothed_ticks: u32,
+	/// Index of the most recent tick within the loop, counted from beat one
+	tick: u32,
+	next_tick: u32,
+	state: State,
+	capture_latency: u32,
+	loop_length_suggestion: LoopLengthSuggestion,
+}
+
+impl MidiClockSlave {
+	pub fn new(sample_rate: u32) -> MidiClockSlave {
+		MidiClockSlave {
+			now: 0,
+			last_tick: None,
+			tick_length: None,
+			smoothed_ticks: 0,
+			tick: 0,
+			next_tick: 0,
+			state: State::Stopped,
+			capture_latency: 0,
+			loop_length_suggestion: LoopLengthSuggestion::new(sample_rate),
+		}
+	}
+
+	/// Processes the single-byte messages `(frame, status)` received during one period.
+	/// Returns the last transport change requested in this period, if any.
+	pub fn process(&mut self, events: impl Iterator<Item=(u32, u8)>, n_frames: u32, song_length: u32, n_beats: u32, capture_latency: u32) -> Option<TransportChange> {
+		let ticks_per_loop = 24 * n_beats;
+		self.capture_latency = capture_latency;
+
+		let mut change = None;
+		for (at, status) in events {
+			match status {
+				0xF8 => {
+					let time = self.now.wrapping_add(at);
+					if let Some(last_tick) = self.last_tick {
+						let interval = time.wrapping_sub(last_tick) as f32;
+						self.tick_length = Some(match self.tick_length {
+							Some(length) if interval < 2.0 * length && interval > 0.5 * length => {
+								self.smoothed_ticks = self.smoothed_ticks.saturating_add(1);
+								length + (interval - length) * TEMPO_SMOOTHING
+							}
+							_ => {
+								// first tick or sudden tempo change
+								self.smoothed_ticks = 0;
+								interval
+							}
+						});
+					}
+					self.last_tick = Some(time);
+
+					if self.state != State::Stopped {
+						self.tick = self.next_tick % ticks_per_loop;
+						self.next_tick = self.tick + 1;
+					}
+					if self.state == State::Starting {
+						self.state = State::Running;
+						let position = (tick_position(self.tick, song_length, ticks_per_loop) + capture_latency) % song_length;
+						change = Some(TransportChange::Start { at, position });
+					}
+				}
+				0xFA => {
+					self.state = State::Starting;
+					self.next_tick = 0;
+				}
+				0xFB => {
+					if self.state == State::Stopped {
+						self.state = State::Starting;
+					}
+				}
+				0xFC => {
+					if self.state != State::Stopped {
+						self.state = State::Stopped;
+						change = Some(TransportChange::Stop);
+					}
+				}
+				_ => {}
+			}
+		}
+
+		self.now = self.now.wrapping_add(n_frames);
+		change
+	}
+
+	/// Returns the song position implied by the received ticks at the end of the last
+	/// processed period, or None if the clock source is not running.
+	pub fn song_position(&self, song_length: u32, n_beats: u32) -> Option<u32> {
+		if self.state != State::Running {
+			return None;
+		}
+		let tick_length = self.tick_length?;
+		// if ticks stop arriving, the position is held
+		let elapsed = f32::min(self.now.wrapping_sub(self.last_tick?) as f32, tick_length) as u32;
+		Some((tick_position(self.tick, song_length, 24 * n_beats) + elapsed + self.capture_latency) % song_length)
+	}
+
+	/// Returns the loop length implied by the measured tempo if it differs notably from both
+	/// `song_length` and the loop length that has been suggested recently. Must be called once
+	/// per period of `n_frames`. Nothing is suggested until the tempo estimate has settled.
+	pub fn loop_length_change(&mut self, song_length: u32, n_beats: u32, n_frames: u32) -> Option<u32> {
+		let loop_length = match self.tick_length {
+			Some(length) if self.smoothed_ticks >= MIN_SMOOTHED_TICKS => (length * (24 * n_beats) as f32).round() as u32,
+			_ => return None
+		};
+		self.loop_length_suggestion.check(loop_length, song_length, n_frames)
+	}
+}
+
+/// Keeps loop lengths derived from an external tempo from being suggested over and over again.
+/// A suggestion that has not been followed, e.g. because takes were still being recorded, is
+/// repeated after a while.
+pub struct LoopLengthSuggestion {
+	/// The loop length suggested the last time and the number of frames since then
+	suggested: Option<(u32, u32)>,
+	/// Number of frames after which a suggestion that has not been followed is repeated
+	retry_frames: u32
+}
+
+impl LoopLengthSuggestion {
+	pub fn new(sample_rate: u32) -> LoopLengthSuggestion {
+		LoopLengthSuggestion { suggested: None, retry_frames: SUGGESTION_RETRY_SECONDS * sample_rate }
+	}
+
+	/// Returns `loop_length` if it differs notably from `song_length` and has not been
+	/// suggested within the last `SUGGESTION_RETRY_SECONDS`. Must be called once per period
+	/// of `n_frames`.
+	pub fn check(&mut self, loop_length: u32, song_length: u32, n_frames: u32) -> Option<u32> {
+		if let Some((_, age)) = self.suggested.as_mut() {
+			*age = age.saturating_add(n_frames);
+		}
+		let deviates_from = |length: u32| (loop_length as f32 - length as f32).abs() > LOOP_LENGTH_TOLERANCE * length as f32;
+		let recently_suggested = self.suggested.map_or(false, |(suggested, age)| !deviates_from(suggested) && age < self.retry_frames);
+		if deviates_from(song_length) && !recently_suggested {
+			self.suggested = Some((loop_length, 0));
+			Some(loop_length)
+		}
+		else {
+			None
+		}
+	}
+}
+
+fn tick_position(tick: u32, song_length: u32, ticks_per_loop: u32) -> u32 {
+	(tick as u64 * song_length as u64 / ticks_per_loop as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SAMPLE_RATE: u32 = 44100;
+	const SONG_LENGTH: u32 = 88200;
+	const N_BEATS: u32 = 4;
+
+	/// Feeds `slave` with periods of `chunksize` frames, during which `events` (with absolute
+	/// timestamps) are received. Returns all transport changes with the period they occurred in.
+	fn run(slave: &mut MidiClockSlave, start: u32, end: u32, chunksize: u32, events: &[(u32, u8)]) -> Vec<(u32, TransportChange)> {
+		let mut changes = Vec::new();
+		for period_start in (start..end).step_by(chunksize as usize) {
+			let period = events.iter()
+				.filter(|(t, _)| (period_start..period_start + chunksize).contains(t))
+				.map(|(t, status)| (t - period_start, *status));
+			if let Some(change) = slave.process(period, chunksize, SONG_LENGTH, N_BEATS, 0) {
+				changes.push((period_start, change));
+			}
+		}
+		changes
+	}
+
+	fn clock(start: u32, n_ticks: u32, tick_length: u32) -> Vec<(u32, u8)> {
+		(0..n_ticks).map(|i| (start + i * tick_length, 0xF8)).collect()
+	}
+
+	#[test]
+	pub fn starts_on_the_first_tick_after_start() {
+		let mut slave = MidiClockSlave::new(SAMPLE_RATE);
+		let mut events = clock(0, 10, 918);
+		events.push((9000, 0xFA));
+		events.extend(clock(9180, 10, 918));
+		let changes = run(&mut slave, 0, 20000, 128, &events);
+		assert_eq!(changes, vec![(9088, TransportChange::Start { at: 92, position: 0 })]);
+	}
+
+	#[test]
+	pub fn measures_the_tempo() {
+		let mut slave = MidiClockSlave::new(SAMPLE_RATE);
+		let tick_length = SONG_LENGTH / (24 * N_BEATS) + 10;
+		run(&mut slave, 0, 100 * tick_length, 128, &clock(0, 100, tick_length));
+		assert!((slave.tick_length.unwrap() - tick_length as f32).abs() < 0.01);
+		let loop_length = slave.loop_length_change(SONG_LENGTH, N_BEATS, 128).expect("the tempo differs from the song length");
+		assert_eq!(loop_length, tick_length * 24 * N_BEATS);
+		assert_eq!(slave.loop_length_change(SONG_LENGTH, N_BEATS, 128), None, "the same loop length must not be suggested again right away");
+		assert_eq!(slave.loop_length_change(loop_length, N_BEATS, 128), None);
+		// two periods of 128 frames have passed since the suggestion
+		assert_eq!(slave.loop_length_change(SONG_LENGTH, N_BEATS, SUGGESTION_RETRY_SECONDS * SAMPLE_RATE - 3 * 128), None);
+		assert_eq!(slave.loop_length_change(SONG_LENGTH, N_BEATS, 128), Some(loop_length), "a suggestion that has not been followed must be repeated");
+	}
+
+	#[test]
+	pub fn waits_for_the_tempo_to_settle() {
+		let mut slave = MidiClockSlave::new(SAMPLE_RATE);
+		let tick_length = SONG_LENGTH / (24 * N_BEATS) + 10;
+		run(&mut slave, 0, MIN_SMOOTHED_TICKS * tick_length, 128, &clock(0, MIN_SMOOTHED_TICKS, tick_length));
+		assert_eq!(slave.loop_length_change(SONG_LENGTH, N_BEATS, 128), None, "a tempo measured from a few ticks must not be suggested");
+		run(&mut slave, MIN_SMOOTHED_TICKS * tick_length, (MIN_SMOOTHED_TICKS + 2) * tick_length, 128, &clock(MIN_SMOOTHED_TICKS * tick_length, 2, tick_length));
+		assert_eq!(slave.loop_length_change(SONG_LENGTH, N_BEATS, 128), Some(tick_length * 24 * N_BEATS));
+	}
+
+	#[test]
+	pub fn follows_the_song_position_and_stops() {
+		let mut slave = MidiClockSlave::new(SAMPLE_RATE);
+		let tick_length = SONG_LENGTH / (24 * N_BEATS);
+		let mut events = vec![(0, 0xFA)];
+		events.extend(clock(100, 200, tick_length));
+		events.push((100 + 150 * tick_length + 5, 0xFC));
+		events.sort();
+
+		run(&mut slave, 0, 100 + 50 * tick_length + 200, 100, &events);
+		assert_eq!(slave.song_position(SONG_LENGTH, N_BEATS), Some(50 * SONG_LENGTH / 96 + 200));
+
+		let changes = run(&mut slave, 100 + 50 * tick_length + 200, 100 + 200 * tick_length, 100, &events);
+		assert_eq!(changes.len(), 1);
+		assert_eq!(changes[0].1, TransportChange::Stop);
+		assert_eq!(slave.song_position(SONG_LENGTH, N_BEATS), None);
+	}
+
+	#[test]
+	pub fn continue_resumes_at_the_next_tick() {
+		let mut slave = MidiClockSlave::new(SAMPLE_RATE);
+		let tick_length = SONG_LENGTH / (24 * N_BEATS);
+		let mut events = vec![(0, 0xFA), (10 * tick_length + 50, 0xFC), (20 * tick_length + 50, 0xFB)];
+		events.extend(clock(100, 30, tick_length));
+		events.sort();
+
+		let changes = run(&mut slave, 0, 30 * tick_length, 100, &events);
+		assert_eq!(changes.len(), 3);
+		assert_eq!(changes[2].1, TransportChange::Start { at: (100 + 20 * tick_length) % 100, position: 10 * SONG_LENGTH / 96 });
+	}
+}
@@ -9,7 +9,10 @@ mod jack_driver;
 mod metronome;
 mod midi_registry;
 mod midiclock;
+mod midiclock_slave;
 mod driver_traits;
+mod ramp;
+mod output_bus;
 
 #[cfg(test)]
 mod dummy_driver;
@@ -23,7 +26,7 @@ use backend::*;
 
 use std::collections::HashMap;
 
-pub use data::{Event, RecordState};
+pub use data::{Event, RecordState, JackTransportMode, MetronomeMode, MetronomeSettings, MetronomeSound};
 
 use shared::SharedThreadState;
 
@@ -37,6 +40,7 @@ use crate::id_generator::IdGenerator;
 use driver_traits::*;
 
 use metronome::AudioMetronome;
+use output_bus::OutputBus;
 use midiclock::MidiClock;
 use crate::realtime_send_queue;
 
@@ -47,29 +51,43 @@ fn create_thread_states<Driver: DriverTrait>(mut driver: Driver, devices: Vec<Dr
 		song_length: AtomicU32::new(song_length),
 		song_position: AtomicU32::new(0),
 		transport_position: AtomicU32::new(0),
+		song_length_change_pending: AtomicBool::new(false),
+		playing: AtomicBool::new(true),
+		destroyed_devices: std::sync::Mutex::new(0),
+		device_destroyed: std::sync::Condvar::new(),
 	});
 
 	let (command_sender, command_receiver) = ringbuf::RingBuffer::<Message<Driver::AudioDev, Driver::MidiDev>>::new(16).split();
 
-	let frontend_devices = devices.iter().enumerate().map(|d| (d.0, frontend::GuiAudioDevice { info: d.1.info(), takes: HashMap::new() }) ).collect();
-	let frontend_mididevices = mididevices.iter().enumerate().map(|d| (d.0, frontend::GuiMidiDevice { info: d.1.info(), takes: HashMap::new() }) ).collect();
+	let frontend_devices = devices.iter().enumerate().map(|d| (d.0, frontend::GuiAudioDevice { info: d.1.info(), takes: HashMap::new(), retired_takes: HashMap::new() }) ).collect();
+	let frontend_mididevices = mididevices.iter().enumerate().map(|d| (d.0, frontend::GuiMidiDevice { info: d.1.info(), takes: HashMap::new(), retired_takes: HashMap::new() }) ).collect();
 
 	let (event_producer, event_consumer) = realtime_send_queue::new(64);
 
 	let metronome = AudioMetronome::new( driver.new_audio_device(1, "metronome").unwrap() );
+	let master_bus = OutputBus::new( driver.new_audio_device(2, "master").unwrap(), driver.sample_rate() / 200 );
+	let monitor_bus = OutputBus::new( driver.new_audio_device(2, "monitor").unwrap(), driver.sample_rate() / 200 );
 	let midiclock = MidiClock::new( driver.new_midi_device("clock").unwrap() );
 
-	let audio_thread_state = AudioThreadState::new(driver.sample_rate(), devices, mididevices, metronome, midiclock, command_receiver, song_length, shared.clone(), event_producer);
+	let audio_thread_state = AudioThreadState::new(driver.sample_rate(), devices, mididevices, metronome, master_bus, monitor_bus, midiclock, driver.transport(), command_receiver, song_length, shared.clone(), event_producer);
 
 	driver.activate(audio_thread_state);
 
+	let sample_rate = driver.sample_rate();
 	let frontend_thread_state = FrontendThreadState {
 		command_channel: RetryChannelPush(command_sender),
 		devices: frontend_devices,
 		mididevices: frontend_mididevices,
 		shared: Arc::clone(&shared),
 		next_id: IdGenerator::new(),
-		driver
+		driver,
+		n_beats: 4,
+		fade_length: sample_rate / 200,
+		crossfade_length: 0,
+		clock_source: None,
+		jack_transport_mode: JackTransportMode::Ignore,
+		free_running: false,
+		removed_devices: 0,
 	};
 
 	return (frontend_thread_state, event_consumer);
@@ -0,0 +1,96 @@
+use super::driver_traits::*;
+use super::ramp::LinearRamp;
+
+/// Sums several sources into one device, e.g. the main speakers or the monitoring headphones.
+pub struct OutputBus<T: AudioDeviceTrait> {
+	device: T,
+	n_channels: usize,
+	gain: LinearRamp,
+	/// Number of samples over which gain changes are smoothed.
+	ramp_length: u32,
+}
+
+impl<T: AudioDeviceTrait> OutputBus<T> {
+	/** not real-time-safe! */
+	pub fn new(device: T, ramp_length: u32) -> OutputBus<T> {
+		OutputBus {
+			n_channels: device.info().n_channels,
+			device,
+			gain: LinearRamp::new(1.0),
+			ramp_length
+		}
+	}
+
+	pub fn set_gain(&mut self, gain: f32) {
+		self.gain.set_target(gain, self.ramp_length);
+	}
+
+	/// The bus' device. Sources may add to its playback buffers directly between
+	/// `clear` and `finish`.
+	pub fn device_mut(&mut self) -> &mut T {
+		&mut self.device
+	}
+
+	/// Must be called once per period before any `add*` call.
+	pub fn clear(&mut self, scope: &T::Scope) {
+		for (buffer, _) in self.device.playback_and_capture_buffers(scope) {
+			for d in buffer.iter_mut() {
+				*d = 0.0;
+			}
+		}
+	}
+
+	/// Adds the playback buffers of `source`.
+	pub fn add(&mut self, scope: &T::Scope, source: &mut T) {
+		self.add_scaled(scope, source, &mut LinearRamp::new(1.0));
+	}
+
+	/// Adds the playback buffers of `source` scaled by `gain`, advancing `gain` by one period.
+	pub fn add_scaled(&mut self, scope: &T::Scope, source: &mut T, gain: &mut LinearRamp) {
+		let n_source_channels = source.playback_and_capture_buffers(scope).count();
+		let buffers = source.playback_and_capture_buffers(scope).map(|(playback, _)| &*playback);
+		*gain = mix(scope, &mut self.device, self.n_channels, buffers, n_source_channels, *gain);
+	}
+
+	/// Adds the capture buffers of `source`, i.e. what is currently being played into it.
+	pub fn add_capture(&mut self, scope: &T::Scope, source: &T) {
+		let n_source_channels = source.record_buffers(scope).count();
+		mix(scope, &mut self.device, self.n_channels, source.record_buffers(scope), n_source_channels, LinearRamp::new(1.0));
+	}
+
+	/// Applies the bus gain. Must be called once per period after all `add*` calls.
+	pub fn finish(&mut self, scope: &T::Scope) {
+		apply_gain(scope, &mut self.device, &mut self.gain);
+	}
+}
+
+/// Mono sources are added to all channels, otherwise source channel `i` is added to
+/// channel `i` modulo the number of channels. Returns the advanced `gain`.
+fn mix<'a, T: AudioDeviceTrait>(scope: &T::Scope, device: &mut T, n_channels: usize, source_buffers: impl Iterator<Item=&'a [f32]>, n_source_channels: usize, gain: LinearRamp) -> LinearRamp {
+	let mut result = gain;
+	for (i, source_buffer) in source_buffers.enumerate() {
+		for (j, (buffer, _)) in device.playback_and_capture_buffers(scope).enumerate() {
+			if n_source_channels == 1 || i % n_channels == j {
+				let mut channel_gain = gain;
+				for (d, s) in buffer.iter_mut().zip(source_buffer.iter()) {
+					*d += *s * channel_gain.next();
+				}
+				result = channel_gain;
+			}
+		}
+	}
+	result
+}
+
+/// Multiplies all playback buffers of `device` with `gain`, advancing `gain` by one period.
+pub fn apply_gain<T: AudioDeviceTrait>(scope: &T::Scope, device: &mut T, gain: &mut LinearRamp) {
+	let mut result = *gain;
+	for (buffer, _) in device.playback_and_capture_buffers(scope) {
+		let mut channel_gain = *gain;
+		for d in buffer.iter_mut() {
+			*d *= channel_gain.next();
+		}
+		result = channel_gain;
+	}
+	*gain = result;
+}
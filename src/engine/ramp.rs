@@ -0,0 +1,75 @@
+/// A value that linearly approaches its target, one step per sample. Used to avoid clicks
+/// when gains change abruptly. This is `Copy` so that it can be advanced independently for
+/// every channel of a multichannel buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearRamp {
+	value: f32,
+	target: f32,
+	step: f32,
+	remaining: u32,
+}
+
+impl LinearRamp {
+	pub fn new(value: f32) -> LinearRamp {
+		LinearRamp { value, target: value, step: 0.0, remaining: 0 }
+	}
+
+	/// Sets a new target that will be reached after `n_samples` calls to `next()`.
+	/// If `n_samples` is zero, the target is reached immediately. Setting the
+	/// target that is already being approached does not restart the ramp.
+	pub fn set_target(&mut self, target: f32, n_samples: u32) {
+		if self.target == target && (n_samples != 0 || self.remaining == 0) {
+			return;
+		}
+		self.target = target;
+		if n_samples == 0 {
+			self.value = target;
+			self.remaining = 0;
+		}
+		else {
+			self.step = (target - self.value) / n_samples as f32;
+			self.remaining = n_samples;
+		}
+	}
+
+	/// Returns the current value and advances the ramp by one sample.
+	pub fn next(&mut self) -> f32 {
+		let result = self.value;
+		if self.remaining > 0 {
+			self.remaining -= 1;
+			self.value = if self.remaining == 0 { self.target } else { self.value + self.step };
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	pub fn ramp_reaches_target_after_given_number_of_samples() {
+		let mut ramp = LinearRamp::new(0.0);
+		ramp.set_target(1.0, 4);
+		let values: Vec<f32> = (0..6).map(|_| ramp.next()).collect();
+		assert!(values == vec![0.0, 0.25, 0.5, 0.75, 1.0, 1.0]);
+	}
+
+	#[test]
+	pub fn ramp_with_zero_length_jumps() {
+		let mut ramp = LinearRamp::new(1.0);
+		ramp.set_target(0.0, 0);
+		assert!(ramp.next() == 0.0);
+	}
+
+	#[test]
+	pub fn retargeting_a_ramp_continues_from_the_current_value() {
+		let mut ramp = LinearRamp::new(0.0);
+		ramp.set_target(1.0, 4);
+		ramp.next();
+		ramp.next();
+		ramp.set_target(0.0, 4);
+		let values: Vec<f32> = (0..4).map(|_| ramp.next()).collect();
+		assert!(values == vec![0.5, 0.375, 0.25, 0.125]);
+	}
+}
@@ -1,8 +1,18 @@
 use std::sync::atomic::*;
+use std::sync::{Condvar, Mutex};
 
 pub struct SharedThreadState {
 	pub song_length: AtomicU32,
 	pub song_position: AtomicU32,
 	pub transport_position: AtomicU32,
+	/// Set by the frontend when it requests a song length change while takes exist and
+	/// cleared by the audio thread once the rescaled takes have been swapped in.
+	pub song_length_change_pending: AtomicBool,
+	/// Whether the transport is running. Song and transport position only advance while it is.
+	pub playing: AtomicBool,
+	/// Number of devices dropped by the destructor thread so far. Their names can be reused
+	/// from then on. `device_destroyed` is notified whenever it is increased.
+	pub destroyed_devices: Mutex<u32>,
+	pub device_destroyed: Condvar,
 }
 
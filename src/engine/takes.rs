@@ -9,11 +9,27 @@ use super::driver_traits::*;
 
 use super::midi_registry::MidiNoteRegistry;
 
-use crate::outsourced_allocation_buffer::Buffer;
+use crate::outsourced_allocation_buffer::{Buffer, BufferCursor};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::ramp::LinearRamp;
+
+/// Number of samples over which `AudioTake::seek_crossfaded` fades from the old to the new position
+const SEEK_CROSSFADE_LENGTH: u32 = 128;
+
+/// An audio sample that can be overwritten while other threads read it, e.g. when a take
+/// is overdubbed while it is being exported.
+pub struct AtomicSample(AtomicU32);
+
+impl AtomicSample {
+	pub fn new(value: f32) -> AtomicSample { AtomicSample(AtomicU32::new(value.to_bits())) }
+	pub fn get(&self) -> f32 { f32::from_bits(self.0.load(Ordering::Relaxed)) }
+	pub fn set(&self, value: f32) { self.0.store(value.to_bits(), Ordering::Relaxed) }
+}
 
 pub struct AudioTake {
 	/// Sequence of all samples. The take's duration and playhead position are implicitly managed by the underlying Buffer.
-	pub samples: Vec<Buffer<f32>>,
+	pub samples: Vec<Buffer<AtomicSample>>,
 	pub length: Option<u32>, // FIXME rename this in playback_length
 	pub recorded_length: u32,
 	pub record_state: RecordState,
@@ -21,8 +37,51 @@ pub struct AudioTake {
 	pub id: u32,
 	pub audiodev_id: usize,
 	pub unmuted: bool,
+	/// If set, `unmuted` will be changed to this value at the next loop boundary.
+	pub scheduled_unmute: Option<bool>,
 	pub started_recording_at: u32,
-	pub damaged: bool
+	pub damaged: bool,
+	/// Gain applied to the take, ramping between 0 and 1 when `unmuted` changes.
+	mute_gain: LinearRamp,
+	/// Linear gain of the take.
+	pub gain: f32,
+	/// Balance between the even (left) and odd (right) channels, from -1 to 1.
+	pub pan: f32,
+	/// Per channel, the gain resulting from `gain` and `pan`.
+	channel_gain: Vec<LinearRamp>,
+	/// If set, the take is also played into the cue output, regardless of `unmuted`.
+	pub cued: bool,
+	/// Gain applied to the cue output, ramping between 0 and 1 when `cued` changes.
+	cue_gain: LinearRamp,
+	/// If set, the input is summed into the take while it plays, and what was there before
+	/// is scaled by this factor.
+	pub overdub_feedback: Option<f32>,
+	/// Number of samples over which mute and cue changes are faded.
+	pub fade_length: u32,
+	/// Number of samples over which gain and pan changes are smoothed.
+	pub gain_ramp_length: u32,
+	/// Number of samples recorded beyond `length` that are crossfaded with the take's start
+	/// at the loop seam. Use `set_crossfade_length` to change this.
+	crossfade_length: u32,
+	/// Per channel, the samples recorded beyond `length` that are currently being faded out.
+	seam: Vec<Vec<f32>>,
+	/// Number of valid samples in `seam`.
+	seam_length: u32,
+	/// Per channel, what would have been played after the last `seek_crossfaded`. It is faded
+	/// out while the new position is faded in.
+	seek_fade: Vec<Vec<f32>>,
+	/// Number of samples of `seek_fade` that have been played already.
+	seek_fade_position: u32,
+	/// Per channel, where `overdub` continues writing, so that it does not need to seek.
+	overdub_cursors: Vec<BufferCursor<AtomicSample>>,
+	/// Per channel, where `overdub` continues writing the samples recorded beyond `length`.
+	overdub_seam_cursors: Vec<BufferCursor<AtomicSample>>,
+	/// Per channel, the first sample recorded beyond `length`.
+	overdub_seam_starts: Vec<BufferCursor<AtomicSample>>,
+	/// Position the `overdub_cursors` belong to, if they are valid.
+	overdub_position: Option<u32>,
+	/// Whether the samples beyond `length` were completely recorded when the cursors were set.
+	overdub_seam_complete: bool,
 }
 
 impl std::fmt::Debug for AudioTake {
@@ -42,8 +101,11 @@ impl std::fmt::Debug for AudioTake {
 impl AudioTake {
 	/** not real-time-safe! */
 	pub fn new(id: u32, audiodev_id: usize, unmuted: bool, n_channels: usize, chunksize: usize) -> AudioTake {
+		let samples: Vec<Buffer<AtomicSample>> = (0..n_channels).map(|_| Buffer::new(chunksize,chunksize/2)).collect();
+		// placeholders, which are set before being used by `overdub`
+		let cursors: Vec<_> = samples.iter().map(|channel| channel.cursor_at(0)).collect();
 		AudioTake {
-			samples: (0..n_channels).map(|_| Buffer::new(chunksize,chunksize/2)).collect(),
+			samples,
 			length: None,
 			recorded_length: 0,
 			playback_position: 0,
@@ -51,32 +113,136 @@ impl AudioTake {
 			id,
 			audiodev_id,
 			unmuted,
+			scheduled_unmute: None,
 			started_recording_at: 0,
-			damaged: false
+			damaged: false,
+			mute_gain: LinearRamp::new(if unmuted { 1.0 } else { 0.0 }),
+			gain: 1.0,
+			pan: 0.0,
+			channel_gain: (0..n_channels).map(|_| LinearRamp::new(1.0)).collect(),
+			cued: false,
+			cue_gain: LinearRamp::new(0.0),
+			overdub_feedback: None,
+			fade_length: 0,
+			gain_ramp_length: 0,
+			crossfade_length: 0,
+			seam: (0..n_channels).map(|_| Vec::new()).collect(),
+			seam_length: 0,
+			seek_fade: (0..n_channels).map(|_| vec![0.0; SEEK_CROSSFADE_LENGTH as usize]).collect(),
+			seek_fade_position: SEEK_CROSSFADE_LENGTH,
+			overdub_cursors: cursors.clone(),
+			overdub_seam_cursors: cursors.clone(),
+			overdub_seam_starts: cursors,
+			overdub_position: None,
+			overdub_seam_complete: false,
 		}
 	}
 
-	pub fn playback<T: AudioDeviceTrait>(&mut self, scope: &T::Scope, device: &mut T, range_u32: std::ops::Range<u32>) {
+	/** Creates a take that already contains the given samples and is ready for playback.
+	  * not real-time-safe! */
+	pub fn new_finished(id: u32, audiodev_id: usize, unmuted: bool, samples: &[Vec<f32>], length: u32, chunksize: usize) -> AudioTake {
+		let mut take = AudioTake::new(id, audiodev_id, unmuted, samples.len(), chunksize);
+		take.samples = samples.iter().map(|channel| Buffer::from_iter(channel.iter().map(|v| AtomicSample::new(*v)), chunksize, chunksize/2)).collect();
+		take.recorded_length = samples.iter().map(|channel| channel.len() as u32).min().unwrap_or(0);
+		take.length = Some(length);
+		take.record_state = RecordState::Finished;
+		take.rewind();
+		take
+	}
+
+	/** not real-time-safe! */
+	pub fn set_crossfade_length(&mut self, crossfade_length: u32) {
+		self.crossfade_length = crossfade_length;
+		for seam in self.seam.iter_mut() {
+			seam.resize(crossfade_length as usize, 0.0);
+		}
+		self.seam_length = 0;
+	}
+
+	pub fn crossfade_length(&self) -> u32 { self.crossfade_length }
+
+	/// Plays the take into `device`. If given, the take is also played into `cue` while it is cued,
+	/// with take channel `i` going to cue channel `i`.
+	pub fn playback<T: AudioDeviceTrait>(&mut self, scope: &T::Scope, device: &mut T, cue: Option<&mut T>, range_u32: std::ops::Range<u32>) {
+		self.mute_gain.set_target(if self.unmuted { 1.0 } else { 0.0 }, self.fade_length);
+		self.cue_gain.set_target(if self.cued { 1.0 } else { 0.0 }, self.fade_length);
+		let n_channels = self.channel_gain.len();
+		for (i, channel_gain) in self.channel_gain.iter_mut().enumerate() {
+			channel_gain.set_target(self.gain * balance(i, n_channels, self.pan), self.gain_ramp_length);
+		}
+
 		if let Some(length) = self.length {
 			let range = range_u32.start as usize .. range_u32.end as usize;
-			for (channel_buffer, channel_slices) in self.samples.iter_mut().zip(device.playback_and_capture_buffers(scope)) {
+			let crossfade_length = self.crossfade_length.min(length);
+			let mut mute_gain = self.mute_gain;
+			let mut cue_gain = self.cue_gain;
+			let mut seam_length = self.seam_length;
+			let mut seek_fade_position = self.seek_fade_position;
+			let mut cue_channels = cue.map(|cue| cue.playback_and_capture_buffers(scope));
+			for ((((channel_buffer, seam), seek_fade), channel_gain), channel_slices) in self.samples.iter_mut().zip(self.seam.iter_mut()).zip(self.seek_fade.iter()).zip(self.channel_gain.iter_mut()).zip(device.playback_and_capture_buffers(scope)) {
 				let mut position = self.playback_position;
+				mute_gain = self.mute_gain;
+				cue_gain = self.cue_gain;
+				seam_length = self.seam_length;
+				seek_fade_position = self.seek_fade_position;
 				let buffer = &mut channel_slices.0[range.clone()];
-				for d in buffer {
+				let mut cue_buffer = cue_channels.as_mut().and_then(|channels| channels.next()).map(|(cue_buffer, _)| &mut cue_buffer[range.clone()]);
+				for (k, d) in buffer.iter_mut().enumerate() {
+					let level = channel_gain.next();
+					let gain = mute_gain.next() * level;
+					let cue_level = cue_gain.next() * level;
 					let val = channel_buffer.next();
-					if let Some(v) = val {
-						if self.unmuted { // FIXME fade in / out to avoid clicks
-							*d += v;
+					let seeking = seek_fade_position < SEEK_CROSSFADE_LENGTH;
+					if val.is_some() || seeking {
+						let mut v = val.map_or(0.0, |v| v.get());
+						if position < seam_length {
+							// fade in the take's start while fading out what was recorded past its end
+							let fade_in = position as f32 / seam_length as f32;
+							v = v * fade_in + seam[position as usize] * (1.0 - fade_in);
+						}
+						if seeking {
+							let fade_in = (seek_fade_position + 1) as f32 / (SEEK_CROSSFADE_LENGTH + 1) as f32;
+							v = v * fade_in + seek_fade[seek_fade_position as usize] * (1.0 - fade_in);
+							seek_fade_position += 1;
+						}
+						if gain != 0.0 {
+							*d += v * gain;
+						}
+						if cue_level != 0.0 {
+							if let Some(cue_buffer) = cue_buffer.as_mut() {
+								cue_buffer[k] += v * cue_level;
+							}
 						}
 					}
 
 					position += 1;
 					if position >= length {
+						seam_length = 0;
+						while seam_length < crossfade_length {
+							match channel_buffer.next() {
+								Some(v) => { seam[seam_length as usize] = v.get(); }
+								None => break
+							}
+							seam_length += 1;
+						}
 						channel_buffer.rewind();
 						position = 0;
 					}
 				}
 			}
+			self.mute_gain = mute_gain;
+			self.cue_gain = cue_gain;
+			self.seam_length = seam_length;
+			self.seek_fade_position = seek_fade_position;
+		}
+		else {
+			for _ in range_u32.clone() {
+				self.mute_gain.next();
+				self.cue_gain.next();
+				for channel_gain in self.channel_gain.iter_mut() {
+					channel_gain.next();
+				}
+			}
 		}
 
 		self.playback_position += range_u32.len() as u32;
@@ -87,26 +253,58 @@ impl AudioTake {
 	}
 
 	pub fn seek(&mut self, position: u32) {
-		if position < self.playback_position {
-			self.rewind();
-		}
-		assert!(position >= self.playback_position);
-
-		let difference = position - self.playback_position;
 		for channel_buffer in self.samples.iter_mut() {
-			for _ in 0..difference {
-				channel_buffer.next();
-			}
+			channel_buffer.seek(position as usize);
 		}
 
 		self.playback_position = position;
+		self.seam_length = 0;
+		self.seek_fade_position = SEEK_CROSSFADE_LENGTH;
 	}
-	
+
+	/// Like `seek`, but crossfades from what would have been played next to the new position,
+	/// so that the jump does not cause a discontinuity.
+	pub fn seek_crossfaded(&mut self, position: u32) {
+		let length = match self.length {
+			Some(length) => length,
+			None => return self.seek(position)
+		};
+		for ((channel_buffer, seam), seek_fade) in self.samples.iter_mut().zip(self.seam.iter()).zip(self.seek_fade.iter_mut()) {
+			let mut old_position = self.playback_position;
+			let mut seam_length = self.seam_length;
+			for i in 0..SEEK_CROSSFADE_LENGTH {
+				let mut v = channel_buffer.next().map_or(0.0, |v| v.get());
+				if old_position < seam_length {
+					let fade_in = old_position as f32 / seam_length as f32;
+					v = v * fade_in + seam[old_position as usize] * (1.0 - fade_in);
+				}
+				// a previous crossfade may not be complete yet
+				let previous = self.seek_fade_position + i;
+				if previous < SEEK_CROSSFADE_LENGTH {
+					let fade_in = (previous + 1) as f32 / (SEEK_CROSSFADE_LENGTH + 1) as f32;
+					v = v * fade_in + seek_fade[previous as usize] * (1.0 - fade_in);
+				}
+				seek_fade[i as usize] = v;
+
+				old_position += 1;
+				if old_position >= length {
+					channel_buffer.rewind();
+					old_position = 0;
+					seam_length = 0;
+				}
+			}
+		}
+		self.seek(position);
+		self.seek_fade_position = 0;
+	}
+
 	pub fn rewind(&mut self) {
 		for channel_buffer in self.samples.iter_mut() {
 			channel_buffer.rewind();
 		}
 		self.playback_position = 0;
+		self.seam_length = 0;
+		self.seek_fade_position = SEEK_CROSSFADE_LENGTH;
 	}
 
 	pub fn record<T: AudioDeviceTrait>(&mut self, scope: &T::Scope, device: &T, range_u32: std::ops::Range<u32>) {
@@ -114,13 +312,76 @@ impl AudioTake {
 		for (channel_buffer, channel_slice) in self.samples.iter_mut().zip(device.record_buffers(scope)) {
 			let data = &channel_slice[range.clone()];
 			for d in data {
-				if channel_buffer.push(*d).is_err() {
+				if channel_buffer.push(AtomicSample::new(*d)).is_err() {
 					self.damaged = true;
 				}
 			}
 		}
 		self.recorded_length += range.len() as u32;
 	}
+
+	/// Sums the device's input into the take, scaling what was there by `overdub_feedback`.
+	/// Must be called after `playback` for the same period. `latency` is the sum of the device's
+	/// playback and capture latencies, i.e. how far the recorded input lags behind the playhead.
+	/// Does nothing unless overdubbing is enabled and the take's length is known.
+	/// The input continues into the samples recorded beyond `length`, which are crossfaded
+	/// at the loop seam.
+	pub fn overdub<T: AudioDeviceTrait>(&mut self, scope: &T::Scope, device: &T, latency: u32) {
+		let (feedback, length) = match (self.overdub_feedback, self.length) {
+			(Some(feedback), Some(length)) => (feedback, length),
+			_ => return
+		};
+		let n_frames = scope.n_frames();
+		let start = (self.playback_position as i64 - n_frames as i64 - latency as i64).rem_euclid(length as i64) as u32;
+		let crossfade_length = self.crossfade_length.min(length);
+		let seam_complete = self.recorded_length >= length + crossfade_length;
+
+		if self.overdub_position != Some(start) || self.overdub_seam_complete != seam_complete {
+			// overdubbing has just been enabled or the playhead has jumped. Only then, the
+			// fragments need to be walked to find the positions.
+			for (((channel_buffer, cursor), seam_cursor), seam_start) in self.samples.iter().zip(self.overdub_cursors.iter_mut()).zip(self.overdub_seam_cursors.iter_mut()).zip(self.overdub_seam_starts.iter_mut()) {
+				*cursor = channel_buffer.cursor_at(start as usize);
+				*seam_cursor = channel_buffer.cursor_at((length + start) as usize);
+				*seam_start = channel_buffer.cursor_at(length as usize);
+			}
+			self.overdub_seam_complete = seam_complete;
+		}
+
+		for ((((channel_buffer, cursor), seam_cursor), seam_start), channel_slice) in self.samples.iter().zip(self.overdub_cursors.iter_mut()).zip(self.overdub_seam_cursors.iter_mut()).zip(self.overdub_seam_starts.iter()).zip(device.record_buffers(scope)) {
+			let mut position = start;
+			for input in channel_slice[0..n_frames as usize].iter() {
+				if let Some(v) = channel_buffer.next_at(cursor) {
+					v.set(v.get() * feedback + *input);
+				}
+				if seam_complete && position < crossfade_length {
+					if let Some(v) = channel_buffer.next_at(seam_cursor) {
+						v.set(v.get() * feedback + *input);
+					}
+				}
+				position += 1;
+				if position >= length {
+					*cursor = channel_buffer.cursor_at(0);
+					*seam_cursor = *seam_start;
+					position = 0;
+				}
+			}
+		}
+		self.overdub_position = Some((start + n_frames) % length);
+	}
+}
+
+/// Returns the gain of `channel` for the balance `pan`. Even channels are considered left,
+/// odd channels right. Mono takes are not affected.
+pub fn balance(channel: usize, n_channels: usize, pan: f32) -> f32 {
+	if n_channels < 2 {
+		1.0
+	}
+	else if channel % 2 == 0 {
+		(1.0 - pan).min(1.0)
+	}
+	else {
+		(1.0 + pan).min(1.0)
+	}
 }
 
 pub struct MidiTake {
@@ -136,6 +397,8 @@ pub struct MidiTake {
 	pub mididev_id: usize,
 	pub unmuted: bool,
 	pub unmuted_old: bool,
+	/// If set, `unmuted` will be changed to this value at the next loop boundary.
+	pub scheduled_unmute: Option<bool>,
 	pub started_recording_at: u32,
 	pub note_registry: RefCell<MidiNoteRegistry>, // this RefCell here SUCKS. TODO.
 	pub damaged: bool // gets set when not all events could be recorded
@@ -166,6 +429,7 @@ impl MidiTake {
 			mididev_id,
 			unmuted,
 			unmuted_old: unmuted,
+			scheduled_unmute: None,
 			started_recording_at: 0,
 			playback_position: 0,
 			length: None,
@@ -175,13 +439,25 @@ impl MidiTake {
 		}
 	}
 
-	fn handle_mute_change(&mut self, device: &mut impl MidiDeviceTrait) {
+	/** Creates a take that already contains the given events and is ready for playback.
+	  * not real-time-safe! */
+	pub fn new_finished(id: u32, mididev_id: usize, unmuted: bool, events: &[MidiMessage], length: u32) -> MidiTake {
+		let mut take = MidiTake::new(id, mididev_id, unmuted);
+		take.events = Buffer::from_slice(events, 1024, 512);
+		take.recorded_length = length;
+		take.length = Some(length);
+		take.record_state = RecordState::Finished;
+		take.rewind();
+		take
+	}
+
+	fn handle_mute_change(&mut self, device: &mut impl MidiDeviceTrait, timestamp: u32) {
 		if self.unmuted != self.unmuted_old {
 			if self.unmuted {
-				self.note_registry.borrow_mut().send_noteons(device);
+				self.note_registry.borrow_mut().send_noteons_at(device, timestamp);
 			}
 			else {
-				self.note_registry.borrow_mut().send_noteoffs(device);
+				self.note_registry.borrow_mut().send_noteoffs_at(device, timestamp);
 			}
 			self.unmuted_old = self.unmuted;
 		}
@@ -192,7 +468,7 @@ impl MidiTake {
 	/// `self.length` frames.
 	pub fn playback(&mut self, device: &mut impl MidiDeviceTrait, range: std::ops::Range<u32>) {
 		if let Some(length) = self.length {
-			self.handle_mute_change(device);
+			self.handle_mute_change(device, range.start);
 
 			let mut rewind_offset = 0;
 			loop {
@@ -394,7 +670,7 @@ mod tests {
 		});
 
 		scope.run_for(44100, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		assert!(dev.playback_buffers[0].iter().all(|x| *x == 0.0));
@@ -414,7 +690,7 @@ mod tests {
 		t.rewind();
 
 		scope.run_for(44100, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		let offset = 44100;
@@ -441,7 +717,7 @@ mod tests {
 					*v = 1.0;
 				}
 			}
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 
@@ -469,13 +745,13 @@ mod tests {
 		t.rewind();
 
 		scope.run_for(1000, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		t.rewind();
 		
 		scope.run_for(1000, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		let offset = 44100;
@@ -499,19 +775,19 @@ mod tests {
 		t.rewind();
 
 		scope.run_for(1000, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		t.seek(3000); // seek forward
 		
 		scope.run_for(1000, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		t.seek(1000); // seek backward
 		
 		scope.run_for(1000, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		let offset = 44100;
@@ -536,14 +812,39 @@ mod tests {
 		t.rewind();
 
 		scope.run_for(44100, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		assert!(dev.capture_buffers[0].len() == dev.capture_buffers[1].len());
 		assert!(dev.playback_buffers[0].iter().all(|x| *x == 0.0));
 		assert!(dev.playback_buffers[1].iter().all(|x| *x == 0.0));
 	}
-	
+
+	#[test]
+	pub fn cued_audiotake_plays_into_the_cue_device_even_if_muted() {
+		let (mut t, mut scope, mut dev) = prepare();
+		let mut cue = DummyAudioDevice::new(2, 0, 0);
+
+		scope.run_for(44100, 1024, |scope| {
+			t.record(scope, &dev, 0..scope.n_frames());
+		});
+
+		t.length = Some(44100);
+		t.unmuted = false;
+		t.cued = true;
+		t.rewind();
+
+		scope.run_for(44100, 1024, |scope| {
+			t.playback(scope, &mut dev, Some(&mut cue), 0..scope.n_frames());
+		});
+
+		let offset = 44100;
+		assert!(dev.playback_buffers[0].iter().all(|x| *x == 0.0));
+		assert!(dev.playback_buffers[1].iter().all(|x| *x == 0.0));
+		assert!(cue.playback_buffers[0][offset..] == dev.capture_buffers[0][0..offset]);
+		assert!(cue.playback_buffers[1][offset..] == dev.capture_buffers[1][0..offset]);
+	}
+
 	#[test]
 	pub fn audiotake_past_the_end_loops() {
 		let (mut t, mut scope, mut dev) = prepare();
@@ -557,7 +858,7 @@ mod tests {
 		t.rewind();
 
 		scope.run_for(88200, 1024, |scope| {
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		let len = 44100;
@@ -581,7 +882,7 @@ mod tests {
 
 		scope.run_for(44100-1024, 1024, |scope| {
 			t.record(scope, &dev, 0..scope.n_frames());
-			t.playback(scope, &mut dev, 0..scope.n_frames());
+			t.playback(scope, &mut dev, None, 0..scope.n_frames());
 		});
 
 		assert!(dev.capture_buffers[0].len() == dev.capture_buffers[1].len());
@@ -913,4 +1214,13 @@ mod tests {
 
 		assert!(t.unmuted_old == true);
 	}
+
+	#[test]
+	pub fn balance_attenuates_the_opposite_side_only() {
+		assert!(balance(0, 2, 0.0) == 1.0 && balance(1, 2, 0.0) == 1.0);
+		assert!(balance(0, 2, 0.5) == 0.5 && balance(1, 2, 0.5) == 1.0);
+		assert!(balance(0, 2, -1.0) == 1.0 && balance(1, 2, -1.0) == 0.0);
+		assert!(balance(2, 4, 1.0) == 0.0 && balance(3, 4, 1.0) == 1.0);
+		assert!(balance(0, 1, 1.0) == 1.0);
+	}
 }
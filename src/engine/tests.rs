@@ -25,9 +25,11 @@ async fn special_devices_are_created() {
 	let (_frontend, _events) = launch(driver.clone(), 1000);
 
 	let guard = driver.lock();
-	assert_eq!(guard.audio_devices.len(), 1);
+	assert_eq!(guard.audio_devices.len(), 3);
 	assert_eq!(guard.midi_devices.len(), 1);
 	assert!(guard.audio_devices.contains_key("metronome"));
+	assert!(guard.audio_devices.contains_key("master"));
+	assert!(guard.audio_devices.contains_key("monitor"));
 	assert!(guard.midi_devices.contains_key("clock"));
 }
 
@@ -44,7 +46,7 @@ async fn device_creation() {
 	assert!(frontend.mididevices().contains_key(&mid));
 
 	let guard = driver.lock();
-	assert_eq!(guard.audio_devices.len(), 2);
+	assert_eq!(guard.audio_devices.len(), 4);
 	assert_eq!(guard.midi_devices.len(), 2);
 	assert!(guard.audio_devices.contains_key("My Audio Device"));
 	assert!(guard.midi_devices.contains_key("My Midi Device"));
@@ -119,21 +121,150 @@ async fn midiclock_is_always_active() {
 }
 
 #[tokio::test]
-async fn song_length_cannot_be_changed_if_takes_exist() {
+async fn song_length_cannot_be_changed_if_unfinished_takes_exist() {
 	{
 		let driver = DummyDriver::new(0, 0, 48000);
 		let (mut frontend, _) = launch(driver.clone(), 1000);
 		let id = frontend.add_device("dev", 2).unwrap();
-		frontend.add_audiotake(id, false).unwrap();
-		frontend.set_loop_length(48000, 8).expect_err("frontend should not allow changing song length when audio takes exist");
+		frontend.add_audiotake(id, false, None).unwrap();
+		frontend.set_loop_length(48000, 8).expect_err("frontend should not allow changing song length when unfinished audio takes exist");
 	}
 	{
 		let driver = DummyDriver::new(0, 0, 48000);
 		let (mut frontend, _) = launch(driver.clone(), 1000);
 		let id = frontend.add_mididevice("dev").unwrap();
-		frontend.add_miditake(id, false).unwrap();
-		frontend.set_loop_length(48000, 8).expect_err("frontend should not allow changing song length when midi takes exist");
+		frontend.add_miditake(id, false, None).unwrap();
+		frontend.set_loop_length(48000, 8).expect_err("frontend should not allow changing song length when unfinished midi takes exist");
 	}
+	{
+		let driver = DummyDriver::new(0, 0, 48000);
+		let (mut frontend, _) = launch(driver.clone(), 1000);
+		let id = frontend.add_mididevice("dev").unwrap();
+		frontend.add_miditake(id, false, Some(96000)).unwrap();
+		driver.process_for(1024, 128);
+		assert!(!frontend.can_change_loop_length());
+		frontend.set_loop_length(48000, 8).expect_err("a known length does not mean that a midi take has finished recording");
+	}
+}
+
+#[tokio::test]
+async fn removed_devices_can_be_waited_for() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	assert!(frontend.wait_for_removed_devices(std::time::Duration::from_millis(0)));
+
+	frontend.remove_device(audiodev_id).unwrap();
+	frontend.remove_mididevice(mididev_id).unwrap();
+	assert!(!frontend.wait_for_removed_devices(std::time::Duration::from_millis(10)), "the audio thread has not processed the removal yet");
+	driver.process_for(1024, 128);
+	assert!(frontend.wait_for_removed_devices(std::time::Duration::from_millis(1000)));
+}
+
+#[tokio::test]
+async fn song_length_change_is_applied_right_away_while_stopped() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let samples = vec![rand_vec_f32(1337, 44100), rand_vec_f32(42, 44100)];
+	let audiotake_id = frontend.add_finished_audiotake(audiodev_id, true, &samples, 44100).unwrap();
+	driver.process_for(11025, 128);
+	frontend.stop_transport(false).unwrap();
+	driver.process_for(1024, 128);
+	assert_eq!(frontend.song_position(), 11025);
+
+	assert!(frontend.can_change_loop_length());
+	frontend.set_loop_length(22050, 4).unwrap();
+	driver.process_for(1024, 128);
+	assert!(!frontend.loop_length_change_pending(), "the change must not wait for a wrap that never comes");
+	assert_eq!(frontend.loop_length(), 22050);
+	assert_eq!(frontend.song_position(), 5512, "the relative song position must be kept");
+	assert!(frontend.devices()[&audiodev_id].takes()[&audiotake_id].length == Some(22050));
+}
+
+#[tokio::test]
+async fn song_length_can_be_changed_while_finished_takes_exist() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	driver.process_for(11025, 128);
+
+	let samples = vec![rand_vec_f32(1337, 88200), rand_vec_f32(42, 88200)];
+	let events = vec![
+		MidiMessage { timestamp: 1000, data: [0x90, 42, 64], datalen: 3 },
+		MidiMessage { timestamp: 50000, data: [0x80, 42, 64], datalen: 3 },
+	];
+	let audiotake_id = frontend.add_finished_audiotake(audiodev_id, true, &samples, 88200).unwrap();
+	let miditake_id = frontend.add_finished_miditake(mididev_id, true, &events, 88200).unwrap();
+	driver.process_for(1024, 128);
+
+	frontend.set_loop_length(22050, 4).unwrap();
+	assert!(frontend.loop_length_change_pending());
+	frontend.add_miditake(mididev_id, true, None).expect_err("no takes must be added while the change is pending");
+	frontend.set_loop_length(11025, 4).expect_err("only one change can be pending at a time");
+	assert!(frontend.devices()[&audiodev_id].takes()[&audiotake_id].length == Some(44100));
+	assert!(frontend.mididevices()[&mididev_id].takes()[&miditake_id].length == Some(44100));
+
+	driver.process_for(44100*3 - 11025 - 1024, 128);
+	assert!(!frontend.loop_length_change_pending());
+	assert!(frontend.loop_length() == 22050);
+
+	// the song wraps at 44100 within the period starting at 44049, so the new takes start at 44177
+	let resampled: Vec<f32> = samples[0].iter().step_by(2).cloned().collect();
+	assert_sleq!(frontend.audiotake_samples(audiodev_id, audiotake_id).unwrap()[0][..], resampled[..]);
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert_sleq!(dev.playback_buffers[0][11025..44177], samples[0][11025..44177], "old take was not played until the loop boundary");
+	assert_sleq!(dev.playback_buffers[0][44177..88200], resampled[77..44100], "rescaled take did not start in sync with the song");
+	assert_sleq!(dev.playback_buffers[0][88200..132300], resampled[..], "rescaled take did not loop correctly");
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_iter_eq(
+		to_dummy_midi_event(mididev.committed.iter().cloned()),
+		vec![
+			DummyMidiEvent { time: 44100 + 500, data: smallvec![0x90, 42, 64] },
+			DummyMidiEvent { time: 44100 + 25000, data: smallvec![0x80, 42, 64] },
+			DummyMidiEvent { time: 88200 + 500, data: smallvec![0x90, 42, 64] },
+			DummyMidiEvent { time: 88200 + 25000, data: smallvec![0x80, 42, 64] },
+		].into_iter()
+	);
+}
+
+#[tokio::test]
+async fn takes_can_change_while_they_are_being_rescaled() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let samples = vec![vec![0.5; 44100], vec![0.5; 44100]];
+	let kept = frontend.add_finished_audiotake(audiodev_id, true, &samples, 44100).unwrap();
+	let deleted = frontend.add_finished_audiotake(audiodev_id, true, &samples, 44100).unwrap();
+	driver.process_for(1024, 128);
+
+	let rescaling = frontend.prepare_loop_length_change(22050, 4).unwrap().expect("existing takes must be rescaled");
+	assert!(frontend.loop_length_change_pending());
+	frontend.add_audiotake(audiodev_id, true, None).expect_err("no takes must be added while the change is pending");
+	frontend.set_audiotake_overdub(audiodev_id, kept, Some(1.0)).expect_err("the rescaled take would lose the overdub");
+	frontend.set_audiotake_gain(audiodev_id, kept, 0.5, 0.0).unwrap();
+	frontend.delete_audiotake(audiodev_id, deleted).unwrap();
+	let change = std::thread::spawn(move || rescaling.rescale()).join().unwrap();
+	frontend.finish_loop_length_change(change).unwrap();
+
+	assert!(frontend.devices()[&audiodev_id].takes().get(&deleted).is_none(), "deleted takes must not come back");
+	let take = &frontend.devices()[&audiodev_id].takes()[&kept];
+	assert_eq!(take.length, Some(22050));
+	assert_eq!(take.gain, 0.5, "gain changes must be kept");
+
+	driver.process_for(44100*2, 128);
+	assert!(!frontend.loop_length_change_pending());
+	assert_eq!(frontend.loop_length(), 22050);
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert!(dev.playback_buffers[0][30000] != 0.0);
+	assert_eq!(dev.playback_buffers[0][60000], dev.playback_buffers[0][30000], "only the rescaled take must play, with the changed gain");
 }
 
 #[tokio::test]
@@ -168,6 +299,50 @@ async fn metronome_is_always_active_and_reacts_to_set_loop_length() {
 	assert_eq!(*ticks.last().unwrap(), 480000-2048)
 }
 
+#[tokio::test]
+async fn metronome_in_recording_mode_only_clicks_during_count_in_and_recording() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	frontend.set_metronome(MetronomeSettings { mode: MetronomeMode::Recording, ..MetronomeSettings::default() }).unwrap();
+	driver.process_for(44100, 128);
+	frontend.add_audiotake(dev_id, true, None).unwrap();
+	driver.process_for(2*44100, 128);
+	frontend.set_metronome(MetronomeSettings { unmuted: false, ..MetronomeSettings::default() }).unwrap();
+	driver.process_for(44100, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("metronome").unwrap().lock().unwrap();
+	assert_sleq!(dev.playback_buffers[0][0..44100], 0.0, "the metronome must be silent while nothing is recorded");
+	assert_eq!(ticks(&dev.playback_buffers[0][44100..88200], 0.2).len(), 4, "the metronome must click during the count-in");
+	assert_eq!(ticks(&dev.playback_buffers[0][88200..132300], 0.2).len(), 4, "the metronome must click while recording");
+	assert_sleq!(dev.playback_buffers[0][132300..176400], 0.0, "the metronome must be silent when muted");
+}
+
+#[tokio::test]
+async fn metronome_plays_loaded_samples() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.set_metronome(MetronomeSettings { volume: 1.0, subdivision: 2, ..MetronomeSettings::default() }).unwrap();
+	frontend.set_metronome_sample(MetronomeSound::Accent, Some(vec![0.8; 100])).unwrap();
+	driver.process_for(44100, 128);
+	frontend.set_metronome_sample(MetronomeSound::Accent, Some(vec![0.4; 100])).unwrap();
+	frontend.set_metronome_sample(MetronomeSound::Click, Some(vec![0.6; 100])).unwrap();
+	driver.process_for(44100, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("metronome").unwrap().lock().unwrap();
+	let click = 44100 / 8 + 1; // one click every eighth note
+	let buffer = &dev.playback_buffers[0];
+	assert!(buffer[0..100].iter().all(|x| *x == 0.8));
+	assert!(buffer[2*click] != 0.0, "beats without a sample must use the synthesized click");
+	assert!(buffer[44100..44200].iter().all(|x| *x == 0.4), "the accent sample was not replaced");
+	assert!(buffer[44100+click .. 44100+click+100].iter().all(|x| *x == 0.3), "subdivisions must play the click sample at a lower level");
+	assert!(buffer[44100+2*click .. 44100+2*click+100].iter().all(|x| *x == 0.6));
+}
+
 #[tokio::test]
 async fn restart_midi_transport() {
 	let driver = DummyDriver::new(2048, 0, 44100);
@@ -186,6 +361,389 @@ async fn restart_midi_transport() {
 	]);
 }
 
+#[tokio::test]
+async fn transport_can_be_paused_stopped_and_started() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	frontend.add_mididevice("mididev").unwrap();
+	let ramp: Vec<f32> = (0..44100).map(|i| i as f32).collect();
+	frontend.add_finished_audiotake(dev_id, true, &vec![ramp.clone(), ramp.clone()], 44100).unwrap();
+
+	driver.process_for(11025, 128);
+	frontend.stop_transport(false).unwrap(); // pause
+	driver.process_for(11025, 128);
+	assert!(!frontend.playing());
+	assert_eq!(frontend.song_position(), 11025);
+	assert_eq!(frontend.transport_position(), 11025);
+	frontend.resume_transport().unwrap(); // continue
+	driver.process_for(11025, 128);
+	assert!(frontend.playing());
+	frontend.stop_transport(true).unwrap(); // stop and rewind
+	driver.process_for(11025, 128);
+	assert_eq!(frontend.song_position(), 0);
+	assert_eq!(frontend.transport_position(), 22050);
+	frontend.start_transport().unwrap();
+	driver.process_for(11025, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][0..11025], ramp[0..11025]);
+		assert_sleq!(dev.playback_buffers[channel][11025..22050], 0.0, "expected silence while paused");
+		assert_sleq!(dev.playback_buffers[channel][22050..33075], ramp[11025..22050], "take did not continue where it was paused");
+		assert_sleq!(dev.playback_buffers[channel][33075..44100], 0.0, "expected silence while stopped");
+		assert_sleq!(dev.playback_buffers[channel][44100..55125], ramp[0..11025], "take did not start from the beginning after rewinding");
+	}
+
+	let metronome = d.audio_devices.get("metronome").unwrap().lock().unwrap();
+	assert_sleq!(metronome.playback_buffers[0][11025..22050], 0.0, "the metronome must be silent while paused");
+	assert_sleq!(metronome.playback_buffers[0][33075..44100], 0.0, "the metronome must be silent while stopped");
+	assert!(metronome.playback_buffers[0][44100] != 0.0, "the metronome must click on beat one");
+
+	let transport_messages = vec![
+		MidiMessage { timestamp: 11025, data: [0xFC, 0, 0], datalen: 1 },
+		MidiMessage { timestamp: 22050, data: [0xF2, 4, 0], datalen: 3 }, // paused on the second beat
+		MidiMessage { timestamp: 22050, data: [0xFB, 0, 0], datalen: 1 },
+		MidiMessage { timestamp: 33075, data: [0xFC, 0, 0], datalen: 1 },
+		MidiMessage { timestamp: 44100, data: [0xF2, 0, 0], datalen: 3 },
+		MidiMessage { timestamp: 44100, data: [0xFA, 0, 0], datalen: 1 },
+	];
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_eq!(mididev.committed, transport_messages);
+	let clock = d.midi_devices.get("clock").unwrap().lock().unwrap();
+	assert_eq!(clock.committed.iter().filter(|m| m.data[0] != 0xF8).cloned().collect::<Vec<_>>(), transport_messages);
+	assert!(clock.committed.iter().filter(|m| m.data[0] == 0xF8).all(|m| !(11025..22050).contains(&m.timestamp) && !(33075..44100).contains(&m.timestamp)), "no clock ticks must be sent while stopped");
+	assert!(clock.committed.iter().any(|m| m.timestamp == 44100 && m.data[0] == 0xF8), "clock ticks must restart on beat one");
+}
+
+#[tokio::test]
+async fn starting_after_a_pause_begins_on_beat_one() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	frontend.add_mididevice("mididev").unwrap();
+	let ramp: Vec<f32> = (0..44100).map(|i| i as f32).collect();
+	frontend.add_finished_audiotake(dev_id, true, &vec![ramp.clone(), ramp.clone()], 44100).unwrap();
+
+	driver.process_for(11025, 128);
+	frontend.stop_transport(false).unwrap(); // pause
+	driver.process_for(11025, 128);
+	frontend.start_transport().unwrap();
+	driver.process_for(11025, 128);
+	assert_eq!(frontend.song_position(), 11025);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][22050..33075], ramp[0..11025], "take did not start from the beginning");
+	}
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_eq!(mididev.committed, vec![
+		MidiMessage { timestamp: 11025, data: [0xFC, 0, 0], datalen: 1 },
+		MidiMessage { timestamp: 22050, data: [0xF2, 0, 0], datalen: 3 },
+		MidiMessage { timestamp: 22050, data: [0xFA, 0, 0], datalen: 1 },
+	]);
+}
+
+#[tokio::test]
+async fn song_position_pointer_counts_loops_since_rewind() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.add_mididevice("mididev").unwrap();
+
+	driver.process_for(2*44100 + 22050, 128);
+	frontend.stop_transport(false).unwrap();
+	driver.process_for(128, 128);
+	frontend.resume_transport().unwrap();
+	driver.process_for(128, 128);
+
+	let d = driver.lock();
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	let spp = mididev.committed.iter().find(|m| m.data[0] == 0xF2).expect("Continue must be preceded by a Song Position Pointer");
+	assert_eq!(spp.data, [0xF2, 2*16 + 8, 0]);
+}
+
+#[tokio::test]
+async fn continue_is_sent_when_the_song_reaches_the_song_position_pointer() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.add_mididevice("mididev").unwrap();
+
+	driver.process_for(1000, 100);
+	frontend.stop_transport(false).unwrap();
+	driver.process_for(100, 100);
+	frontend.resume_transport().unwrap();
+	driver.process_for(4000, 100);
+
+	// paused between two sixteenths, so the devices continue on the next one, 2757 frames into the song
+	let expected = vec![
+		MidiMessage { timestamp: 1100 + 1757, data: [0xF2, 1, 0], datalen: 3 },
+		MidiMessage { timestamp: 1100 + 1757, data: [0xFB, 0, 0], datalen: 1 },
+	];
+	let d = driver.lock();
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_eq!(mididev.committed.iter().filter(|m| m.data[0] != 0xFC).cloned().collect::<Vec<_>>(), expected);
+	let clock = d.midi_devices.get("clock").unwrap().lock().unwrap();
+	assert_eq!(clock.committed.iter().filter(|m| m.data[0] == 0xF2 || m.data[0] == 0xFB).cloned().collect::<Vec<_>>(), expected);
+}
+
+#[tokio::test]
+async fn clock_is_sent_to_selected_devices_with_offset() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let with_clock = frontend.add_mididevice("with_clock").unwrap();
+	frontend.add_mididevice("without_clock").unwrap();
+	frontend.set_mididevice_clock(with_clock, Some(100)).unwrap();
+	frontend.set_midiclock(false, 0).unwrap();
+	driver.process_for(2*44100, 128);
+
+	let d = driver.lock();
+	let clock = d.midi_devices.get("clock").unwrap().lock().unwrap();
+	assert!(clock.committed.is_empty(), "the disabled clock port must not send anything");
+	let without_clock = d.midi_devices.get("without_clock").unwrap().lock().unwrap();
+	assert!(without_clock.committed.is_empty());
+	let with_clock = d.midi_devices.get("with_clock").unwrap().lock().unwrap();
+	let ticks: Vec<u32> = with_clock.committed.iter().filter(|m| m.data[0] == 0xF8).map(|m| m.timestamp).collect();
+	assert!(ticks.contains(&(44100 + 100)), "the clock ticks must be delayed by the offset");
+	assert_eq!(ticks.iter().filter(|t| (44100..88200).contains(*t)).count(), 4*24);
+}
+
+#[tokio::test]
+async fn waiting_takes_do_not_start_recording_while_stopped() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	let take_id = frontend.add_audiotake(dev_id, true, None).unwrap();
+	frontend.stop_transport(false).unwrap();
+	driver.process_for(88200, 128);
+
+	frontend.start_transport().unwrap();
+	driver.process_for(44100 + 128, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(dev_id, take_id, RecordState::Recording, 44100)).await;
+}
+
+#[tokio::test]
+async fn first_recording_defines_the_loop_length_while_free_running() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	frontend.add_mididevice("mididev").unwrap();
+	fill_audio_device(&driver, "dev", 44100*4);
+	frontend.set_free_running(true).unwrap();
+	driver.process_for(1000, 128);
+
+	let take_id = frontend.add_audiotake(dev_id, true, None).unwrap();
+	driver.process_for(60000, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(dev_id, take_id, RecordState::Recording, 1000)).await;
+	frontend.end_free_running(60000, 4, take_id).unwrap();
+	frontend.finish_audiotake(dev_id, take_id, 60000).unwrap();
+	driver.process_for(60000 + 128, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(dev_id, take_id, RecordState::Finished, 61000)).await;
+	assert_eq!(frontend.loop_length(), 60000);
+
+	let d = driver.lock();
+	let metronome = d.audio_devices.get("metronome").unwrap().lock().unwrap();
+	assert_sleq!(metronome.playback_buffers[0][0..61000], 0.0, "the metronome must be silent while free-running");
+	assert!(metronome.playback_buffers[0][61000] != 0.0, "the metronome must click when the loop starts");
+
+	let clock = d.midi_devices.get("clock").unwrap().lock().unwrap();
+	assert!(clock.committed.iter().filter(|m| m.data[0] == 0xF8).all(|m| m.timestamp >= 61000), "the MIDI clock must not run while free-running");
+	assert!(clock.committed.iter().any(|m| m.timestamp == 61000 && m.data[0] == 0xFA));
+
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][61000..121000], dev.capture_buffers[channel][1000..61000], "the take must start with the loop");
+	}
+}
+
+fn send_midi_clock(driver: &DummyDriver, name: &str, start: Option<u32>, first_tick: u32, n_ticks: u32, tick_length: u32) {
+	let d = driver.lock();
+	let mut dev = d.midi_devices.get(name).unwrap().lock().unwrap();
+	if let Some(time) = start {
+		dev.incoming_events.push(DummyMidiEvent { data: smallvec![0xFA], time });
+	}
+	for i in 0..n_ticks {
+		dev.incoming_events.push(DummyMidiEvent { data: smallvec![0xF8], time: first_tick + i * tick_length });
+	}
+	dev.incoming_events.sort_by_key(|e| e.time);
+}
+
+#[tokio::test]
+async fn song_starts_with_the_clock_source() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44160,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	let drums_id = frontend.add_mididevice("drums").unwrap();
+	let ramp: Vec<f32> = (0..44160).map(|i| i as f32).collect();
+	frontend.add_finished_audiotake(dev_id, true, &vec![ramp.clone(), ramp.clone()], 44160).unwrap();
+	frontend.set_clock_source(Some(drums_id)).unwrap();
+	frontend.stop_transport(true).unwrap();
+
+	send_midi_clock(&driver, "drums", Some(10000), 10050, 300, 460);
+	driver.process_for(10050 + 2 * 44160, 128);
+	assert!(frontend.playing());
+	assert_receive(&mut events, &Event::TransportChanged(true)).await;
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][0..10050 - 128], 0.0, "expected silence before the clock source has started");
+		assert_sleq!(dev.playback_buffers[channel][10050..10050 + 44160], ramp[..], "take is not aligned with the first clock tick");
+		assert_sleq!(dev.playback_buffers[channel][10050 + 44160..10050 + 2 * 44160], ramp[..], "take drifted away from the clock source");
+	}
+	let clock = d.midi_devices.get("clock").unwrap().lock().unwrap();
+	assert!(clock.committed.is_empty(), "the internal MIDI clock must not be sent while following a clock source");
+}
+
+#[tokio::test]
+async fn song_position_follows_a_drifting_clock_source() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44160,4).unwrap();
+	let drums_id = frontend.add_mididevice("drums").unwrap();
+	frontend.set_clock_source(Some(drums_id)).unwrap();
+	frontend.stop_transport(true).unwrap();
+
+	// slightly slower than the loop length, but too close to suggest a new one
+	send_midi_clock(&driver, "drums", Some(0), 50, 400, 461);
+	driver.process_for(50 + 250 * 461 + 200, 128);
+
+	let expected = (250 % 96) * 460 + 200;
+	let error = (frontend.song_position() as i64 - expected as i64).abs();
+	assert!(error <= 70, "song position {} deviates from the clock source's {}", frontend.song_position(), expected);
+}
+
+#[tokio::test]
+async fn following_a_drifting_clock_source_causes_no_discontinuities() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44160,4).unwrap();
+	let dev_id = frontend.add_device("dev", 1).unwrap();
+	let drums_id = frontend.add_mididevice("drums").unwrap();
+	// 96 periods fit into the loop, so the take itself has no seam
+	frontend.add_finished_audiotake(dev_id, true, &vec![sine_vec_f32(460.0, 0.5, 44160)], 44160).unwrap();
+	frontend.set_clock_source(Some(drums_id)).unwrap();
+	frontend.stop_transport(true).unwrap();
+
+	// slightly faster than the loop length, but too close to suggest a new one
+	send_midi_clock(&driver, "drums", Some(0), 50, 500, 458);
+	driver.process_for(50 + 480 * 458 + 1000, 128);
+
+	let expected = (482 % 96) * 460 + (1000 - 2 * 458);
+	let error = (frontend.song_position() as i64 - expected as i64).abs();
+	assert!(error <= 70, "song position {} deviates from the clock source's {}", frontend.song_position(), expected);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert!(max_jump(&dev.playback_buffers[0][50 + 1024..]) < 0.05, "drift corrections must not cause discontinuities");
+}
+
+#[tokio::test]
+async fn clock_source_suggests_loop_length_for_its_tempo() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44160,4).unwrap();
+	let drums_id = frontend.add_mididevice("drums").unwrap();
+	frontend.set_clock_source(Some(drums_id)).unwrap();
+
+	send_midi_clock(&driver, "drums", None, 50, 100, 480);
+	driver.process_for(100 * 480, 128);
+	assert_receive(&mut events, &Event::SuggestedLoopLength(480 * 96)).await;
+}
+
+#[tokio::test]
+async fn song_follows_jack_transport() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	let ramp: Vec<f32> = (0..44100).map(|i| i as f32).collect();
+	frontend.add_finished_audiotake(dev_id, true, &vec![ramp.clone(), ramp.clone()], 44100).unwrap();
+	frontend.set_jack_transport_mode(JackTransportMode::Follow).unwrap();
+
+	driver.process_for(11025, 128);
+	assert!(!frontend.playing());
+	assert_receive(&mut events, &Event::TransportChanged(false)).await;
+
+	// another JACK client locates to 0.5s and starts the transport
+	{
+		let d = driver.lock();
+		let mut transport = d.transport.lock().unwrap();
+		transport.info.frame = 22050;
+		transport.info.rolling = true;
+	}
+	driver.process_for(11025, 128);
+	assert!(frontend.playing());
+	assert_receive(&mut events, &Event::TransportChanged(true)).await;
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][0..11025], 0.0, "expected silence while the JACK transport is stopped");
+		assert_sleq!(dev.playback_buffers[channel][11025..22050], ramp[22050..33075], "take does not follow the JACK transport position");
+	}
+}
+
+#[tokio::test]
+async fn song_follows_jack_transport_bbt_position_and_tempo() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(88200,4).unwrap();
+	frontend.set_jack_transport_mode(JackTransportMode::Follow).unwrap();
+
+	let mut bbt = BbtPosition { bar: 3, beat: 2, tick: 960, beats_per_bar: 4.0, ticks_per_beat: 1920.0, beats_per_minute: 120.0 };
+	driver.lock().transport.lock().unwrap().info.bbt = Some(bbt);
+	driver.process_for(1024, 128);
+	assert_eq!(frontend.song_position(), 33075, "song position should be at beat 1.5 of the loop");
+	assert_receive(&mut events, &Event::TransportChanged(false)).await;
+
+	bbt.beats_per_minute = 100.0;
+	driver.lock().transport.lock().unwrap().info.bbt = Some(bbt);
+	driver.process_for(1024, 128);
+	assert_receive(&mut events, &Event::SuggestedLoopLength(105840)).await;
+}
+
+#[tokio::test]
+async fn invalid_jack_transport_bbt_positions_are_tolerated() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(88200,4).unwrap();
+	frontend.set_jack_transport_mode(JackTransportMode::Follow).unwrap();
+
+	let bbt = BbtPosition { bar: 0, beat: 0, tick: 0, beats_per_bar: 4.0, ticks_per_beat: 1920.0, beats_per_minute: 0.0 };
+	driver.lock().transport.lock().unwrap().info.bbt = Some(bbt);
+	driver.process_for(1024, 128);
+	assert_eq!(frontend.song_position(), 0);
+	assert_receive(&mut events, &Event::TransportChanged(false)).await;
+	let next = async_std::future::timeout(std::time::Duration::from_millis(100), events.receive()).await;
+	assert!(!matches!(next, Ok(Event::SuggestedLoopLength(_))), "no loop length must be suggested for a tempo of 0");
+}
+
+#[tokio::test]
+async fn timebase_master_publishes_song_position_and_tempo() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(88200,4).unwrap();
+	frontend.set_jack_transport_mode(JackTransportMode::TimebaseMaster).unwrap();
+
+	driver.process_for(88200 + 33075, 128 + 3); // period boundary at 33075
+	driver.process(128);
+	let published = driver.lock().transport.lock().unwrap().published.expect("no position was published");
+	assert_eq!(published, BbtPosition { bar: 2, beat: 2, tick: 960, beats_per_bar: 4.0, ticks_per_beat: 1920.0, beats_per_minute: 120.0 });
+
+	frontend.set_jack_transport_mode(JackTransportMode::Ignore).unwrap();
+	assert!(!driver.lock().transport.lock().unwrap().timebase_master);
+}
+
 fn fill_audio_device(driver: &DummyDriver, name: &str, length: usize) {
 	let d = driver.lock();
 	let mut dev = d.audio_devices.get(name).unwrap().lock().unwrap();
@@ -233,6 +791,77 @@ async fn audio_echo_can_be_enabled_and_disabled() {
 	}
 }
 
+#[tokio::test]
+async fn chains_are_mixed_into_the_master_bus() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	driver.process_for(4410, 128); // only the metronome is playing
+
+	frontend.add_finished_audiotake(dev_id, true, &vec![vec![0.5; 44100], vec![0.5; 44100]], 44100).unwrap();
+	frontend.set_audiodevice_gain(dev_id, 0.5).unwrap();
+	frontend.set_bus_gains(1.0, 1.0, 1.0).unwrap();
+	driver.process_for(4410, 128);
+	frontend.set_bus_gains(0.5, 1.0, 1.0).unwrap();
+	frontend.set_bus_gains(-1.0, 1.0, 1.0).expect_err("negative gains must be rejected");
+	driver.process_for(4410, 128);
+
+	let d = driver.lock();
+	let master = d.audio_devices.get("master").unwrap().lock().unwrap();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	let ramp = 44100 / 200;
+	for channel in 0..=1 {
+		assert_sleq!(master.playback_buffers[channel][0..4410], 0.0, "the metronome must not be mixed into the master bus");
+		assert_sleq!(dev.playback_buffers[channel][4410+ramp..13230], 0.25, "the chain gain was not applied");
+		assert_sleq!(master.playback_buffers[channel][4410+ramp..8820], 0.25, "the chain was not mixed into the master bus");
+		assert_sleq!(master.playback_buffers[channel][8820+ramp..13230], 0.125, "the master gain was not applied");
+	}
+}
+
+#[tokio::test]
+async fn monitor_bus_carries_metronome_echo_and_cued_takes() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	fill_audio_device(&driver, "dev", 17640);
+	let take_id = frontend.add_finished_audiotake(dev_id, false, &vec![vec![0.5; 44100], vec![0.5; 44100]], 44100).unwrap();
+	driver.process_for(4410, 128); // only the metronome is audible on the monitor bus
+
+	frontend.set_audiodevice_echo(dev_id, true).unwrap();
+	driver.process_for(4410, 128);
+
+	frontend.set_audiotake_cued(dev_id, take_id, true).unwrap();
+	driver.process_for(4410, 128);
+
+	frontend.set_audiotake_cued(dev_id, take_id, false).unwrap();
+	frontend.set_audiotake_unmuted(dev_id, take_id, true).unwrap();
+	driver.process_for(4410, 128);
+
+	let d = driver.lock();
+	let master = d.audio_devices.get("master").unwrap().lock().unwrap();
+	let monitor = d.audio_devices.get("monitor").unwrap().lock().unwrap();
+	let metronome = d.audio_devices.get("metronome").unwrap().lock().unwrap();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	let ramp = 44100 / 200;
+	for channel in 0..=1 {
+		let click = &metronome.playback_buffers[0];
+		let input = &dev.capture_buffers[channel];
+		let echo_and_click: Vec<f32> = (0..17640).map(|i| input[i] + click[i]).collect();
+		let cue_echo_and_click: Vec<f32> = (0..17640).map(|i| (0.5 + input[i]) + click[i]).collect();
+
+		assert_sleq!(monitor.playback_buffers[channel][0..4410], click[0..4410], "the metronome was not mixed into the monitor bus");
+		assert_sleq!(monitor.playback_buffers[channel][4410..8820], echo_and_click[4410..8820], "the echo was not mixed into the monitor bus");
+		assert_sleq!(monitor.playback_buffers[channel][8820+ramp..13230], cue_echo_and_click[8820+ramp..13230], "the cued take was not mixed into the monitor bus");
+		assert_sleq!(monitor.playback_buffers[channel][13230+ramp..17640], echo_and_click[13230+ramp..17640], "the take was not uncued");
+
+		assert_sleq!(dev.playback_buffers[channel][4410..13230], input[4410..13230], "cueing must not affect the chain's output");
+		assert_sleq!(master.playback_buffers[channel][0..13230], 0.0, "only unmuted takes may be mixed into the master bus");
+		assert_sleq!(master.playback_buffers[channel][13230+ramp..17640], 0.5, "the unmuted take was not mixed into the master bus");
+	}
+}
+
 #[tokio::test]
 async fn timestamp_events_are_sent() {
 	for chunksize in vec![256, 100] {
@@ -281,7 +910,7 @@ macro_rules! recording_test {
 
 			// add a take during the first period
 			driver.process_for(30000, 128);
-			let take_id = frontend.$add_take(dev_id, true).unwrap();
+			let take_id = frontend.$add_take(dev_id, true, None).unwrap();
 			driver.process_for(14100 + on_point_offset, 128);
 			assert_receive(&mut events, &Event::$TakeStateChanged(dev_id, take_id, RecordState::Recording, 44100)).await;
 			
@@ -359,6 +988,40 @@ async fn midi_takes_can_be_recorded() {
 	);
 }
 
+#[tokio::test]
+async fn fixed_length_takes_finish_by_themselves() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 1).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	fill_audio_device(&driver, "dev", 44100*6);
+	fill_midi_device(&driver, "mididev", 44100*6);
+
+	driver.process_for(30000, 128);
+	let audiotake_id = frontend.add_audiotake(audiodev_id, true, Some(88200)).unwrap();
+	let miditake_id = frontend.add_miditake(mididev_id, true, Some(88200)).unwrap();
+	frontend.finish_audiotake(audiodev_id, audiotake_id, 44100).expect_err("the take's length is already known");
+	driver.process_for(14100, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(audiodev_id, audiotake_id, RecordState::Recording, 44100)).await;
+	assert_receive(&mut events, &Event::MidiTakeStateChanged(mididev_id, miditake_id, RecordState::Recording, 44100)).await;
+
+	// no finish_*take call is needed
+	driver.process_for(88200 + 2*88200, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(audiodev_id, audiotake_id, RecordState::Finished, 44100+88200)).await;
+	assert_receive(&mut events, &Event::MidiTakeStateChanged(mididev_id, miditake_id, RecordState::Finished, 44100+88200)).await;
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert_sleq!(dev.playback_buffers[0][0..44100+88200], 0.0, "expected silence while waiting and recording");
+	assert_sleq!(dev.playback_buffers[0][44100+88200..44100+2*88200], dev.capture_buffers[0][44100..44100+88200]);
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_iter_eq(
+		midi_events_in_range(mididev.incoming_events.iter().cloned(), 44100..44100+88200),
+		midi_events_in_range(to_dummy_midi_event(mididev.committed.iter().cloned()), 44100+88200..44100+2*88200)
+	);
+}
+
 #[tokio::test]
 async fn midi_takes_capture_held_notes() {
 	let driver = DummyDriver::new(0, 0, 44100);
@@ -380,7 +1043,7 @@ async fn midi_takes_capture_held_notes() {
 	}
 
 	driver.process_for(5000, 128); // not capturing, but a note has been played
-	let take_id = frontend.add_miditake(dev_id, true).unwrap();
+	let take_id = frontend.add_miditake(dev_id, true, None).unwrap();
 	frontend.finish_miditake(dev_id, take_id, 10000).unwrap();
 	driver.process_for(25000, 128); // capture will start, complete and the first iteration will be played back
 
@@ -423,8 +1086,8 @@ async fn latency_compensation() {
 		});
 	}
 
-	let audiotake_id = frontend.add_audiotake(audiodev_id, true).unwrap();
-	let miditake_id = frontend.add_miditake(mididev_id, true).unwrap();
+	let audiotake_id = frontend.add_audiotake(audiodev_id, true, None).unwrap();
+	let miditake_id = frontend.add_miditake(mididev_id, true, None).unwrap();
 	frontend.finish_audiotake(audiodev_id, audiotake_id, 44100).unwrap();
 	frontend.finish_miditake(mididev_id, miditake_id, 44100).unwrap();
 	driver.process_for(3*44100, 128);
@@ -462,10 +1125,11 @@ macro_rules! mute_test {
 		let driver = DummyDriver::new(0, 0, 44100);
 		let (mut frontend, _) = launch(driver.clone(), 1000);
 		frontend.set_loop_length(44100,4).unwrap();
+		frontend.set_audiotake_fade_lengths(0, 0);
 		let dev_id = $setup_device(&mut frontend, &driver);
 
 		driver.process_for(22050, 128); // not capturing
-		let take_id = frontend.$add_take(dev_id, false).unwrap();
+		let take_id = frontend.$add_take(dev_id, false, None).unwrap();
 		frontend.$finish_take(dev_id, take_id, 44100).unwrap();
 		driver.process_for(22050, 128); // not capturing
 		driver.process_for(44100, 128); // capturing
@@ -523,3 +1187,509 @@ async fn midi_takes_can_be_muted_and_unmuted() {
 	);
 	assert_eq!(midi_events_in_range(to_dummy_midi_event(dev.committed.iter().cloned()), 7*t..8*t).count(), 0, "expected silence when muted");
 }
+
+macro_rules! scheduled_mute_test {
+	($add_take:ident, $finish_take:ident, $schedule_unmuted:ident, $TakeStateChanged:ident, $TakeMuteChanged:ident, setup_device: $setup_device:expr) => {{
+		let driver = DummyDriver::new(0, 0, 44100);
+		let (mut frontend, mut events) = launch(driver.clone(), 1000);
+		frontend.set_loop_length(44100,4).unwrap();
+		frontend.set_audiotake_fade_lengths(0, 0);
+		let dev_id = $setup_device(&mut frontend, &driver);
+
+		driver.process_for(22050, 128); // not capturing
+		let take_id = frontend.$add_take(dev_id, false, None).unwrap();
+		frontend.$finish_take(dev_id, take_id, 44100).unwrap();
+		driver.process_for(22050, 128); // not capturing
+		driver.process_for(44100, 128); // capturing
+		assert_receive(&mut events, &Event::$TakeStateChanged(dev_id, take_id, RecordState::Recording, 44100)).await;
+		assert_receive(&mut events, &Event::$TakeStateChanged(dev_id, take_id, RecordState::Finished, 88200)).await;
+
+		driver.process_for(22050, 128); // playback, muted
+		frontend.$schedule_unmuted(dev_id, take_id, Some(true)).unwrap();
+		driver.process_for(22050, 128); // playback, still muted until the loop boundary
+		driver.process_for(22050, 128); // playback, unmuted
+		assert_receive(&mut events, &Event::$TakeMuteChanged(dev_id, take_id, true)).await;
+		frontend.$schedule_unmuted(dev_id, take_id, Some(false)).unwrap();
+		driver.process_for(22050, 128); // playback, still unmuted until the loop boundary
+		driver.process_for(44100, 128); // playback, muted
+		assert_receive(&mut events, &Event::$TakeMuteChanged(dev_id, take_id, false)).await;
+
+		driver
+	}}
+}
+
+#[tokio::test]
+async fn audio_take_mute_changes_can_be_scheduled() {
+	let driver = scheduled_mute_test!(add_audiotake, finish_audiotake, schedule_audiotake_unmuted, AudioTakeStateChanged, AudioTakeMuteChanged,
+		setup_device: |frontend: &mut FrontendThreadState<DummyDriver>, driver| {
+			let dev_id = frontend.add_device("dev", 2).unwrap();
+			fill_audio_device(driver, "dev", 44100*8);
+			dev_id
+		}
+	);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	let t = 44100;
+	assert_sleq!(dev.playback_buffers[0][2*t..3*t], 0.0, "expected silence before the scheduled unmute");
+	assert_sleq!(dev.playback_buffers[0][3*t..4*t], dev.capture_buffers[0][1*t..2*t], "take was not played back after the scheduled unmute");
+	assert_sleq!(dev.playback_buffers[0][4*t..5*t], 0.0, "expected silence after the scheduled mute");
+}
+
+#[tokio::test]
+async fn midi_take_mute_changes_can_be_scheduled() {
+	let driver = scheduled_mute_test!(add_miditake, finish_miditake, schedule_miditake_unmuted, MidiTakeStateChanged, MidiTakeMuteChanged,
+		setup_device: |frontend: &mut FrontendThreadState<DummyDriver>, driver| {
+			let dev_id = frontend.add_mididevice("dev").unwrap();
+			fill_midi_device(driver, "dev", 44100*8);
+			dev_id
+		}
+	);
+
+	let d = driver.lock();
+	let dev = d.midi_devices.get("dev").unwrap().lock().unwrap();
+	let t = 44100;
+	assert_eq!(midi_events_in_range(to_dummy_midi_event(dev.committed.iter().cloned()), 2*t..3*t).count(), 0, "expected silence before the scheduled unmute");
+	assert_iter_eq(
+		midi_events_in_range(dev.incoming_events.iter().cloned(), 1*t..2*t),
+		midi_events_in_range(to_dummy_midi_event(dev.committed.iter().cloned()), 3*t..4*t)
+	);
+	assert_eq!(midi_events_in_range(to_dummy_midi_event(dev.committed.iter().cloned()), 4*t..5*t).count(), 0, "expected silence after the scheduled mute");
+}
+
+#[tokio::test]
+async fn late_mute_changes_of_deleted_takes_are_ignored() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	let audiotake_id = frontend.add_finished_audiotake(audiodev_id, true, &vec![vec![0.5; 44100]; 2], 44100).unwrap();
+	let miditake_id = frontend.add_finished_miditake(mididev_id, true, &[], 44100).unwrap();
+
+	frontend.schedule_audiotake_unmuted(audiodev_id, audiotake_id, Some(false)).unwrap();
+	driver.process_for(44100 + 128, 128);
+	// the take is deleted after the engine has muted it, but before the event is handled
+	frontend.delete_audiotake(audiodev_id, audiotake_id).unwrap();
+	assert_receive(&mut events, &Event::AudioTakeMuteChanged(audiodev_id, audiotake_id, false)).await;
+	frontend.audiotake_mute_changed(audiodev_id, audiotake_id, false);
+	assert!(frontend.devices()[&audiodev_id].takes().get(&audiotake_id).is_none(), "the deleted take must not come back");
+
+	frontend.schedule_miditake_unmuted(mididev_id, miditake_id, Some(false)).unwrap();
+	driver.process_for(44100, 128);
+	frontend.delete_miditake(mididev_id, miditake_id).unwrap();
+	assert_receive(&mut events, &Event::MidiTakeMuteChanged(mididev_id, miditake_id, false)).await;
+	frontend.miditake_mute_changed(mididev_id, miditake_id, false);
+	assert!(frontend.mididevices()[&mididev_id].takes().get(&miditake_id).is_none(), "the deleted take must not come back");
+
+	driver.process_for(44100, 128);
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert_sleq!(dev.playback_buffers[0][88200..132300], 0.0, "expected silence after deletion");
+}
+
+#[tokio::test]
+async fn audio_take_mute_changes_are_faded() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.set_audiotake_fade_lengths(441, 0);
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	driver.lock().audio_devices.get("dev").unwrap().lock().unwrap().capture_buffers[0] = sine_vec_f32(97.0, 0.5, 44100*8);
+
+	let take_id = frontend.add_audiotake(dev_id, false, None).unwrap();
+	frontend.finish_audiotake(dev_id, take_id, 44100).unwrap();
+	driver.process_for(44100, 128); // not capturing
+	driver.process_for(44100, 128); // capturing
+
+	driver.process_for(22050, 128); // playback, muted
+	frontend.set_audiotake_unmuted(dev_id, take_id, true).unwrap();
+	driver.process_for(11025, 128); // playback, unmuted
+	frontend.set_audiotake_unmuted(dev_id, take_id, false).unwrap();
+	driver.process_for(11025, 128); // playback, muted
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert!(max_jump(&dev.playback_buffers[0][88200..132300]) < 0.05, "mute changes must not cause discontinuities");
+	assert_sleq!(dev.playback_buffers[0][110250+441..121275], dev.capture_buffers[0][66150+441..77175], "take was not played back at full volume after the fade");
+	assert_sleq!(dev.playback_buffers[0][121275+441..132300], 0.0, "take was not silent after the fade");
+}
+
+#[tokio::test]
+async fn audio_take_gain_and_pan_are_applied_smoothly() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.set_audiotake_fade_lengths(0, 0); // gain and pan are smoothed regardless of the mute fade
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	let take_id = frontend.add_finished_audiotake(dev_id, true, &vec![vec![0.5; 44100], vec![0.5; 44100]], 44100).unwrap();
+
+	driver.process_for(11025, 128); // unity gain
+	frontend.set_audiotake_gain(dev_id, take_id, -1.0, 0.0).expect_err("negative gains must be rejected");
+	frontend.set_audiotake_gain(dev_id, take_id, 1.0, 1.5).expect_err("pan beyond 1 must be rejected");
+	frontend.set_audiotake_gain(dev_id, take_id, 0.5, 0.5).unwrap();
+	driver.process_for(11025, 128); // half gain, panned to the right
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert_sleq!(dev.playback_buffers[0][0..11025], 0.5);
+	assert_sleq!(dev.playback_buffers[1][0..11025], 0.5);
+	let ramp = 44100 / 200;
+	assert!(max_jump(&dev.playback_buffers[0][0..22050]) < 0.002, "gain changes must be smoothed");
+	assert_sleq!(dev.playback_buffers[0][11025+ramp..22050], 0.125, "left channel was not attenuated by gain and pan");
+	assert_sleq!(dev.playback_buffers[1][11025+ramp..22050], 0.25, "right channel was not attenuated by gain");
+}
+
+#[tokio::test]
+async fn audio_takes_can_be_overdubbed() {
+	for (playback_latency, capture_latency) in vec![(0, 0), (100, 50)] {
+		let driver = DummyDriver::new(playback_latency, capture_latency, 44100);
+		let (mut frontend, _) = launch(driver.clone(), 1000);
+		frontend.set_loop_length(44100,4).unwrap();
+		let dev_id = frontend.add_device("dev", 2).unwrap();
+		fill_audio_device(&driver, "dev", 44100*3);
+		let take_id = frontend.add_finished_audiotake(dev_id, true, &vec![vec![1.0; 44100], vec![0.25; 44100]], 44100).unwrap();
+
+		frontend.set_audiotake_overdub(dev_id, take_id, Some(1.5)).expect_err("feedback beyond 1 must be rejected");
+		frontend.set_audiotake_overdub(dev_id, take_id, Some(0.5)).unwrap();
+		frontend.set_loop_length(22050, 2).expect_err("overdubbed takes cannot be rescaled");
+		driver.process_for(44100, 128); // overdubbing
+		frontend.set_audiotake_overdub(dev_id, take_id, None).unwrap();
+		driver.process_for(88200, 128);
+
+		let d = driver.lock();
+		let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+		let latency = (playback_latency + capture_latency) as usize;
+		for (channel, original) in vec![(0, 1.0), (1, 0.25)] {
+			let input = &dev.capture_buffers[channel];
+			let overdubbed: Vec<f32> = (0..44100).map(|i| original * 0.5 + input[(i + latency) % 44100]).collect();
+			assert_sleq!(dev.playback_buffers[channel][0..44100-latency], vec![original; 44100-latency], "the overdub was audible too early");
+			assert_sleq!(dev.playback_buffers[channel][44100..88200], overdubbed, "the input was not summed into the take");
+			assert_sleq!(dev.playback_buffers[channel][88200..132300], overdubbed, "overdubbing did not stop");
+		}
+	}
+}
+
+#[tokio::test]
+async fn overdubbing_continues_into_the_loop_seam() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.set_audiotake_fade_lengths(0, 441);
+	let dev_id = frontend.add_device("dev", 1).unwrap();
+	driver.lock().audio_devices.get("dev").unwrap().lock().unwrap().capture_buffers[0] = vec![1.0; 44100*4];
+	let take_id = frontend.add_finished_audiotake(dev_id, true, &vec![vec![0.0; 44100+441]], 44100).unwrap();
+
+	frontend.set_audiotake_overdub(dev_id, take_id, Some(0.5)).unwrap();
+	driver.process_for(44100 + 1024, 128); // overdubbing
+	frontend.set_audiotake_overdub(dev_id, take_id, None).unwrap();
+	driver.process_for(44100, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	// what has been recorded beyond the end is faded out at the loop seam, and must have been overdubbed as well
+	assert!(dev.playback_buffers[0][44100..88200].iter().all(|x| (x - 1.0).abs() < 1e-5), "the loop seam was not overdubbed");
+}
+
+#[tokio::test]
+async fn audio_take_loop_seams_are_crossfaded() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	frontend.set_audiotake_fade_lengths(0, 441);
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	driver.lock().audio_devices.get("dev").unwrap().lock().unwrap().capture_buffers[0] = sine_vec_f32(97.0, 0.5, 44100*8);
+
+	let take_id = frontend.add_audiotake(dev_id, true, None).unwrap();
+	frontend.finish_audiotake(dev_id, take_id, 44100).unwrap();
+	driver.process_for(44100, 128); // not capturing
+	driver.process_for(44100, 128); // capturing
+	driver.process_for(88200, 128); // playback, wrapping twice
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert!(max_jump(&dev.capture_buffers[0][44100..88200]) < 0.05);
+	assert!((dev.capture_buffers[0][88199] - dev.capture_buffers[0][44100]).abs() > 0.1, "the test signal should have a discontinuous loop seam");
+	assert!(max_jump(&dev.playback_buffers[0][88200..176400]) < 0.05, "loop seams must be crossfaded");
+	assert_sleq!(dev.playback_buffers[0][132300+441..176400], dev.capture_buffers[0][44100+441..88200], "take was not played back correctly after the crossfade");
+}
+
+#[tokio::test]
+async fn recorded_take_data_can_be_read_back() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	fill_audio_device(&driver, "dev", 44100*4);
+	fill_midi_device(&driver, "mididev", 44100*4);
+
+	let audiotake_id = frontend.add_audiotake(audiodev_id, true, None).unwrap();
+	let miditake_id = frontend.add_miditake(mididev_id, true, None).unwrap();
+	frontend.finish_audiotake(audiodev_id, audiotake_id, 44100).unwrap();
+	frontend.finish_miditake(mididev_id, miditake_id, 44100).unwrap();
+	driver.process_for(44100, 128); // not capturing
+	driver.process_for(44100, 128); // capturing
+	driver.process_for(22050, 128); // playback
+
+	let samples = frontend.audiotake_samples(audiodev_id, audiotake_id).unwrap();
+	let events = frontend.miditake_events(mididev_id, miditake_id).unwrap();
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert_sleq!(samples[0][..], dev.capture_buffers[0][44100..88200]);
+	assert_sleq!(samples[1][..], dev.capture_buffers[1][44100..88200]);
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_iter_eq(
+		to_dummy_midi_event(events.into_iter()),
+		midi_events_in_range(mididev.incoming_events.iter().cloned(), 44100..88200)
+	);
+}
+
+#[tokio::test]
+async fn finished_takes_can_be_added_and_play_in_sync() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 2).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	driver.process_for(11025, 128);
+
+	let samples = vec![rand_vec_f32(1337, 88200), rand_vec_f32(42, 88200)];
+	let events = vec![
+		MidiMessage { timestamp: 1000, data: [0x90, 42, 64], datalen: 3 },
+		MidiMessage { timestamp: 50000, data: [0x80, 42, 64], datalen: 3 },
+	];
+	frontend.add_finished_audiotake(audiodev_id, true, &samples[0..1], 88200).expect_err("channel count mismatch must be rejected");
+	frontend.add_finished_miditake(mididev_id, true, &events, 44100).expect_err("events beyond the take length must be rejected");
+	let audiotake_id = frontend.add_finished_audiotake(audiodev_id, true, &samples, 88200).unwrap();
+	frontend.add_finished_miditake(mididev_id, true, &events, 88200).unwrap();
+	driver.process_for(44100*3, 128);
+
+	assert_sleq!(frontend.audiotake_samples(audiodev_id, audiotake_id).unwrap()[1][..], samples[1][..]);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert_sleq!(dev.playback_buffers[0][11025..88200], samples[0][11025..88200], "take did not start in sync with the song");
+	assert_sleq!(dev.playback_buffers[0][88200..176400], samples[0][..], "take did not loop correctly");
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_iter_eq(
+		to_dummy_midi_event(mididev.committed.iter().cloned()),
+		vec![
+			DummyMidiEvent { time: 50000, data: smallvec![0x80, 42, 64] },
+			DummyMidiEvent { time: 88200 + 1000, data: smallvec![0x90, 42, 64] },
+			DummyMidiEvent { time: 88200 + 50000, data: smallvec![0x80, 42, 64] },
+		].into_iter()
+	);
+}
+
+#[tokio::test]
+async fn notes_held_at_the_end_of_finished_midi_takes_are_released() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	driver.process_for(11025, 128);
+
+	// the matching note off lies beyond the take length and was dropped
+	let events = vec![ MidiMessage { timestamp: 40000, data: [0x90, 42, 64], datalen: 3 } ];
+	frontend.add_finished_miditake(mididev_id, true, &events, 44100).unwrap();
+	driver.process_for(44100*2, 128);
+
+	let d = driver.lock();
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert_iter_eq(
+		to_dummy_midi_event(mididev.committed.iter().cloned()),
+		vec![
+			DummyMidiEvent { time: 40000, data: smallvec![0x90, 42, 64] },
+			DummyMidiEvent { time: 44099, data: smallvec![0x80, 42, 64] },
+			DummyMidiEvent { time: 44100 + 40000, data: smallvec![0x90, 42, 64] },
+			DummyMidiEvent { time: 44100 + 44099, data: smallvec![0x80, 42, 64] },
+		].into_iter()
+	);
+}
+
+#[tokio::test]
+async fn audio_takes_can_be_deleted() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	fill_audio_device(&driver, "dev", 44100*8);
+
+	let take_id = frontend.add_audiotake(dev_id, true, None).unwrap();
+	frontend.finish_audiotake(dev_id, take_id, 44100).unwrap();
+	driver.process_for(44100, 128); // not capturing
+	driver.process_for(44100, 128); // capturing
+	driver.process_for(22050, 128); // playback
+	frontend.delete_audiotake(dev_id, take_id).unwrap();
+	assert!(frontend.devices()[&dev_id].takes().is_empty());
+	driver.process_for(22050, 128); // take is gone
+
+	frontend.remove_device(dev_id).unwrap();
+	assert!(frontend.devices().is_empty());
+	driver.process_for(1024, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	let t = 22050;
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][4*t..5*t], dev.capture_buffers[channel][2*t..3*t], "take was not played correctly before deletion");
+		assert_sleq!(dev.playback_buffers[channel][5*t..6*t], 0.0, "expected silence after deletion");
+	}
+}
+
+#[tokio::test]
+async fn waiting_and_recording_takes_can_be_cancelled() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let audiodev_id = frontend.add_device("dev", 1).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	fill_audio_device(&driver, "dev", 44100*5);
+	fill_midi_device(&driver, "mididev", 44100*5);
+
+	let waiting_id = frontend.add_audiotake(audiodev_id, true, Some(44100)).unwrap();
+	driver.process_for(20000, 128);
+	frontend.delete_audiotake(audiodev_id, waiting_id).unwrap(); // cancelled before it started recording
+	let audiotake_id = frontend.add_audiotake(audiodev_id, true, Some(44100)).unwrap();
+	let miditake_id = frontend.add_miditake(mididev_id, true, Some(44100)).unwrap();
+	driver.process_for(24100 + 22050, 128);
+	frontend.delete_audiotake(audiodev_id, audiotake_id).unwrap(); // cancelled while recording
+	frontend.delete_miditake(mididev_id, miditake_id).unwrap();
+	assert!(frontend.devices()[&audiodev_id].takes().is_empty());
+	assert!(frontend.mididevices()[&mididev_id].takes().is_empty());
+	driver.process_for(3*44100, 128);
+
+	// the takes are gone from the engine as well
+	frontend.remove_device(audiodev_id).unwrap();
+	frontend.remove_mididevice(mididev_id).unwrap();
+	driver.process_for(1024, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	assert!(dev.playback_buffers[0].iter().all(|x| *x == 0.0), "cancelled takes must not be played");
+	let mididev = d.midi_devices.get("mididev").unwrap().lock().unwrap();
+	assert!(mididev.committed.is_empty(), "cancelled takes must not be played");
+}
+
+#[tokio::test]
+async fn deleting_midi_takes_stops_held_notes() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_mididevice("dev").unwrap();
+	{
+		let d = driver.lock();
+		let mut dev = d.midi_devices.get("dev").unwrap().lock().unwrap();
+		dev.incoming_events.push(DummyMidiEvent {
+			data: smallvec![0x90, 42, 92],
+			time: 44100 + 1000
+		});
+		dev.incoming_events.push(DummyMidiEvent {
+			data: smallvec![0x80, 42, 55],
+			time: 44100 + 30000
+		});
+	}
+
+	let take_id = frontend.add_miditake(dev_id, true, None).unwrap();
+	frontend.finish_miditake(dev_id, take_id, 44100).unwrap();
+	driver.process_for(44100, 128); // not capturing
+	driver.process_for(44100, 128); // capturing
+	driver.process_for(10000, 128); // playback, the note is held down
+	frontend.delete_miditake(dev_id, take_id).unwrap();
+	driver.process_for(44100, 128); // take is gone
+
+	let d = driver.lock();
+	let dev = d.midi_devices.get("dev").unwrap().lock().unwrap();
+	assert_eq!(dev.committed, vec![
+		MidiMessage {
+			timestamp: 88200 + 1000,
+			data: [0x90, 42, 92],
+			datalen: 3
+		},
+		MidiMessage {
+			timestamp: 88200 + 10000,
+			data: [0x80, 42, 64],
+			datalen: 3
+		},
+	]);
+}
+
+#[tokio::test]
+async fn audio_takes_can_be_retired_and_restored() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+	let ramp: Vec<f32> = (0..44100).map(|i| i as f32).collect();
+	let take_id = frontend.add_finished_audiotake(dev_id, true, &vec![ramp.clone(), ramp.clone()], 44100).unwrap();
+
+	driver.process_for(11025, 128); // playback
+	frontend.retire_audiotake(dev_id, take_id).unwrap();
+	assert!(frontend.devices()[&dev_id].takes().is_empty());
+	frontend.set_loop_length(22050, 2).expect_err("retired takes cannot be rescaled");
+	driver.process_for(11025, 128); // take is retired
+	frontend.remove_device(dev_id).expect_err("frontend should not allow removing an audio device with retired takes");
+	frontend.restore_audiotake(dev_id, take_id).unwrap();
+	assert!(frontend.devices()[&dev_id].takes().contains_key(&take_id));
+	driver.process_for(22050, 128); // playback
+
+	frontend.retire_audiotake(dev_id, take_id).unwrap();
+	frontend.delete_audiotake(dev_id, take_id).unwrap();
+	frontend.restore_audiotake(dev_id, take_id).expect_err("deleted takes cannot be restored");
+	driver.process_for(1024, 128);
+	frontend.remove_device(dev_id).unwrap();
+	driver.process_for(1024, 128);
+
+	let d = driver.lock();
+	let dev = d.audio_devices.get("dev").unwrap().lock().unwrap();
+	for channel in 0..=1 {
+		assert_sleq!(dev.playback_buffers[channel][0..11025], ramp[0..11025], "take was not played correctly before retiring it");
+		assert_sleq!(dev.playback_buffers[channel][11025..22050], 0.0, "expected silence while the take is retired");
+		assert_sleq!(dev.playback_buffers[channel][22050..44100], ramp[22050..44100], "restored take was not played in sync");
+	}
+}
+
+#[tokio::test]
+async fn finishing_audio_takes_can_be_undone_while_recording() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, mut events) = launch(driver.clone(), 1000);
+	frontend.set_loop_length(44100,4).unwrap();
+	let dev_id = frontend.add_device("dev", 2).unwrap();
+
+	let take_id = frontend.add_audiotake(dev_id, true, None).unwrap();
+	driver.process_for(44100 + 128, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(dev_id, take_id, RecordState::Recording, 44100)).await;
+
+	frontend.finish_audiotake(dev_id, take_id, 44100).unwrap();
+	driver.process_for(22050, 128);
+	frontend.unfinish_audiotake(dev_id, take_id).unwrap();
+	driver.process_for(44100, 128); // passes the end the take was first finished at
+	frontend.finish_audiotake(dev_id, take_id, 88200).unwrap();
+	driver.process_for(22050, 128);
+	assert_receive(&mut events, &Event::AudioTakeStateChanged(dev_id, take_id, RecordState::Finished, 44100 + 88200)).await;
+}
+
+#[tokio::test]
+async fn devices_cannot_be_removed_while_takes_exist() {
+	let driver = DummyDriver::new(0, 0, 44100);
+	let (mut frontend, _) = launch(driver.clone(), 1000);
+	let audiodev_id = frontend.add_device("audiodev", 2).unwrap();
+	let mididev_id = frontend.add_mididevice("mididev").unwrap();
+	let audiotake_id = frontend.add_audiotake(audiodev_id, false, None).unwrap();
+	let miditake_id = frontend.add_miditake(mididev_id, false, None).unwrap();
+	driver.process_for(1024, 128);
+
+	frontend.remove_device(audiodev_id).expect_err("frontend should not allow removing an audio device with takes");
+	frontend.remove_mididevice(mididev_id).expect_err("frontend should not allow removing a midi device with takes");
+
+	frontend.delete_audiotake(audiodev_id, audiotake_id).unwrap();
+	frontend.delete_miditake(mididev_id, miditake_id).unwrap();
+	frontend.delete_miditake(mididev_id, miditake_id).expect_err("deleting a take twice should fail");
+	driver.process_for(1024, 128);
+
+	frontend.remove_device(audiodev_id).unwrap();
+	frontend.remove_mididevice(mididev_id).unwrap();
+	driver.process_for(1024, 128);
+}
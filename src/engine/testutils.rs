@@ -65,6 +65,15 @@ pub fn ticks(samples: &[f32], level: f32) -> Vec<usize> {
 	return result;
 }
 
+/// Returns the largest absolute difference between two consecutive samples.
+pub fn max_jump(samples: &[f32]) -> f32 {
+	samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+}
+
+pub fn sine_vec_f32(period: f32, amplitude: f32, length: usize) -> Vec<f32> {
+	(0..length).map(|i| amplitude * (2.0 * std::f32::consts::PI * i as f32 / period + 1.0).sin()).collect()
+}
+
 // GRCOV_EXCL_START
 pub fn slice_diff<T: PartialEq + std::fmt::Debug>(lhs: &[T], rhs: &[T]) {
 	if let Some(result) = lhs.iter().zip(rhs.iter()).map(|x| x.0 != x.1).enumerate().find(|t| t.1) {
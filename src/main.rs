@@ -27,6 +27,8 @@ mod outsourced_allocation_buffer;
 mod id_generator;
 mod rest_api;
 mod realtime_send_queue;
+mod wav;
+mod smf;
 
 #[macro_use] extern crate rocket;
 
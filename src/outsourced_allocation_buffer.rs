@@ -1,5 +1,7 @@
 use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListLink};
 use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 use ringbuf::RingBuffer;
 use std::thread;
 
@@ -17,19 +19,74 @@ struct BufferFragment<T> {
 	  * as they are enqueued in the actual Buffer. Any borrowing rules
 	  * violation of a) - c) would require a similar violation on
 	  * the `Buffer` object.
+	  *
+	  * `BufferReader`s never access the Vec itself, but only read the
+	  * already published elements through `data`.
 	  */
 	buf: UnsafeCell<Vec<T>>,
+
+	/// Points to the Vec's storage, which never moves because the Vec never grows beyond its capacity.
+	data: *const T,
+	capacity: usize,
+	/// Same as the `link`'s successor, but accessible to `BufferReader`s.
+	next: AtomicPtr<BufferFragment<T>>,
 }
 intrusive_adapter!(BufferFragmentAdapter<T> = Box<BufferFragment<T>>: BufferFragment<T> { link: LinkedListLink });
 
+impl<T> BufferFragment<T> {
+	fn new(capacity: usize) -> Box<BufferFragment<T>> {
+		let vec = Vec::with_capacity(capacity);
+		Box::new(BufferFragment {
+			link: LinkedListLink::new(),
+			data: vec.as_ptr(),
+			capacity: vec.capacity(),
+			buf: UnsafeCell::new(vec),
+			next: AtomicPtr::new(std::ptr::null_mut()),
+		})
+	}
+}
+
+/// The fragments of a `Buffer`, shared between the `Buffer` and its `BufferReader`s.
+struct FragmentList<T> {
+	/// Only ever accessed by the owning `Buffer`.
+	list: UnsafeCell<LinkedList<BufferFragmentAdapter<T>>>,
+	/// The first fragment, which never changes.
+	first: *const BufferFragment<T>,
+	/// Number of elements which have been completely written and may be read by `BufferReader`s.
+	published: AtomicUsize,
+}
+
+// BufferReaders only access published elements and the `next` pointers of fragments,
+// which are atomic. Published elements are only handed out as shared references, so they
+// can only be changed afterwards if `T` has interior mutability, which `Sync` requires to
+// be synchronized (e.g. atomics).
+unsafe impl<T: Send> Send for FragmentList<T> {}
+unsafe impl<T: Send + Sync> Sync for FragmentList<T> {}
+
 enum ThreadRequest {
 	Fragment,
 	End
 }
 
+/// A position in a `Buffer`, which can be advanced independently of the buffer's own iterator.
+pub struct BufferCursor<T> {
+	/// The `FragmentList` this cursor belongs to
+	list: *const FragmentList<T>,
+	fragment: *const BufferFragment<T>,
+	index: usize
+}
+
+impl<T> Clone for BufferCursor<T> {
+	fn clone(&self) -> BufferCursor<T> { *self }
+}
+impl<T> Copy for BufferCursor<T> {}
+
+unsafe impl<T: Send> Send for BufferCursor<T> {}
+
 pub struct Buffer<T> {
-	fragments: LinkedList<BufferFragmentAdapter<T>>,
+	fragments: Arc<FragmentList<T>>,
 	remaining_threshold: usize,
+	len: usize,
 
 	request_pending: bool,
 	incoming_fragment_ringbuf: ringbuf::Consumer<std::boxed::Box<BufferFragment<T>>>,
@@ -65,10 +122,8 @@ impl<T: 'static + Send> Buffer<T> {
 		if capacity_increment < 1 {
 			panic!("capacity_increment must be > 0");
 		}
-		let node = Box::new(BufferFragment {
-			link: LinkedListLink::new(),
-			buf: UnsafeCell::new(Vec::with_capacity(capacity_increment))
-		});
+		let node = BufferFragment::new(capacity_increment);
+		let first: *const BufferFragment<T> = &*node;
 		let mut list = LinkedList::new(BufferFragmentAdapter::new());
 		list.push_back(node);
 
@@ -87,10 +142,7 @@ impl<T: 'static + Send> Buffer<T> {
 				while let Some(request) = request_consumer.pop() {
 					match request {
 						ThreadRequest::Fragment => {
-							let fragment = Box::new(BufferFragment {
-								link: LinkedListLink::new(),
-								buf: UnsafeCell::new(Vec::with_capacity(capacity_increment))
-							});
+							let fragment = BufferFragment::new(capacity_increment);
 							// there is always enough space for pushing the fragment
 							incoming_producer.push(fragment).map_err(|_|()).unwrap();
 						}
@@ -103,8 +155,13 @@ impl<T: 'static + Send> Buffer<T> {
 		});
 
 		Buffer {
-			fragments: list,
+			fragments: Arc::new(FragmentList {
+				list: UnsafeCell::new(list),
+				first,
+				published: AtomicUsize::new(0),
+			}),
 			remaining_threshold,
+			len: 0,
 			request_pending: false,
 			incoming_fragment_ringbuf: incoming_consumer,
 			new_fragment_request_ringbuf: request_producer,
@@ -114,17 +171,46 @@ impl<T: 'static + Send> Buffer<T> {
 		}
 	}
 
+	/// Create a new buffer containing `data`.
+	/// This function is not real-time-safe and will allocate memory.
+	pub fn from_slice(data: &[T], capacity_increment: usize, remaining_threshold: usize) -> Buffer<T> where T: Clone {
+		Buffer::from_iter(data.iter().cloned(), capacity_increment, remaining_threshold)
+	}
+
+	/// Create a new buffer containing the elements of `data`.
+	/// This function is not real-time-safe and will allocate memory.
+	pub fn from_iter(data: impl IntoIterator<Item=T>, capacity_increment: usize, remaining_threshold: usize) -> Buffer<T> {
+		let mut buffer = Buffer::new(capacity_increment, remaining_threshold);
+		for elem in data {
+			if buffer.remaining() < 1 {
+				buffer.append_fragment(BufferFragment::new(capacity_increment));
+			}
+			buffer.push_unchecked(elem);
+		}
+
+		if buffer.remaining() <= buffer.remaining_threshold {
+			buffer.request_fragment();
+		}
+
+		buffer
+	}
+
+	/// Returns a reader which can access all elements pushed to this buffer from another thread,
+	/// even after the buffer has been dropped.
+	pub fn reader(&self) -> BufferReader<T> {
+		BufferReader { fragments: Arc::clone(&self.fragments) }
+	}
+
 	/// Checks if the buffer is empty
 	pub fn empty(&self) -> bool {
 		// fragments is never empty, but the Vec in fragments.front() may be
-		unsafe { (*self.fragments.front().get().unwrap().buf.get()).len() == 0 }
+		unsafe { (*(*self.fragments.first).buf.get()).len() == 0 }
 	}
 
 	/// Rewind the iterator state to the beginning of the stored data.
 	pub fn rewind(&mut self) {
 		if !self.empty() {
-			// fragments is never empty, hence the unwrap()
-			self.iter_cursor = self.fragments.front().get().unwrap(); 
+			self.iter_cursor = self.fragments.first;
 			self.iter_index = 0;
 		}
 		else {
@@ -132,35 +218,76 @@ impl<T: 'static + Send> Buffer<T> {
 		}
 	}
 
+	/// Sets the iterator state such that the next call to `next()` returns the element
+	/// at `index`. Seeking beyond the end behaves like calling `next()` beyond the end.
+	/// The cost is proportional to the number of fragments, not to `index`.
+	pub fn seek(&mut self, index: usize) {
+		let cursor = self.cursor_at(index);
+		self.iter_cursor = cursor.fragment;
+		self.iter_index = cursor.index;
+	}
+
+	/// Returns a cursor whose first call to `next_at()` returns the element at `index`,
+	/// with the same cost as `seek()`.
+	pub fn cursor_at(&self, index: usize) -> BufferCursor<T> {
+		let list = unsafe { &*self.fragments.list.get() };
+		let mut cursor = list.front();
+		let mut remaining = index;
+		while let Some(frag) = cursor.get() {
+			let len = unsafe { (*frag.buf.get()).len() };
+			if remaining < len {
+				return BufferCursor { list: &*self.fragments, fragment: frag, index: remaining };
+			}
+			remaining -= len;
+			cursor.move_next();
+		}
+		BufferCursor { list: &*self.fragments, fragment: std::ptr::null(), index: 0 }
+	}
+
 	/// Returns a reference to the current item, if one exists, and advances the cursor to the next item.
 	/// Returns None if none exists.
 	pub fn next<'a>(&mut self) -> Option<&'a T> {
-		if self.iter_cursor.is_null() {
+		Self::advance(&self.fragments, &mut self.iter_cursor, &mut self.iter_index).map(|elem| unsafe { &*elem })
+	}
+
+	/// Like `next()`, but advances `cursor` instead of the buffer's own iterator. `cursor`
+	/// must have been obtained from this buffer.
+	pub fn next_at<'a>(&self, cursor: &mut BufferCursor<T>) -> Option<&'a T> {
+		assert!(std::ptr::eq(cursor.list, &*self.fragments), "cursor belongs to a different buffer");
+		Self::advance(&self.fragments, &mut cursor.fragment, &mut cursor.index).map(|elem| unsafe { &*elem })
+	}
+
+	/// Returns a pointer to the item at `fragment` and `index`, if one exists, and advances
+	/// them to the next item.
+	fn advance(fragments: &FragmentList<T>, fragment: &mut *const BufferFragment<T>, index: &mut usize) -> Option<*const T> {
+		if fragment.is_null() {
 			return None;
 		}
 
-		// Get a cursor from the pointer. This places a borrow on self.fragments
-		// This is safe iif iter_cursor points to an element current in the list.
-		// Since list elements are only added, but never removed, and since iter_cursor
+		// Get a cursor from the pointer. This places a borrow on fragments
+		// This is safe iif `fragment` points to an element current in the list.
+		// Since list elements are only added, but never removed, and since `fragment`
 		// has already belonged to the list when it was set, this is fine.
-		let mut cursor = unsafe{ self.fragments.cursor_from_ptr(self.iter_cursor) };
-		let buf = unsafe {&*cursor.get().unwrap().buf.get() };
+		let list = unsafe { &*fragments.list.get() };
+		let mut cursor = unsafe{ list.cursor_from_ptr(*fragment) };
+		let buf = unsafe { &*cursor.get().unwrap().buf.get() };
 	
 		// Perform the actual access. This is always a valid element because no elements can
-		// be deleted.
-		let result = &buf[self.iter_index];
+		// be deleted. No reference to the element is created here, because `BufferReader`s
+		// might be reading it concurrently.
+		let result: *const T = unsafe { buf.as_ptr().add(*index) };
 	
 		// Now advance the iterator
-		if self.iter_index + 1 < buf.len() {
-			self.iter_index += 1;
+		if *index + 1 < buf.len() {
+			*index += 1;
 		}
 		else {
-			self.iter_index = 0;
+			*index = 0;
 			cursor.move_next();
 		};
 
 		// And turn the borrowed cursor into a borrow-free pointer again
-		self.iter_cursor =
+		*fragment =
 			match cursor.get() {
 				Some(frag) => {
 					unsafe { assert!((*frag.buf.get()).len() > 0); }
@@ -183,7 +310,8 @@ impl<T: 'static + Send> Buffer<T> {
 		// This is safe iif iter_cursor points to an element current in the list.
 		// Since list elements are only added, but never removed, and since iter_cursor
 		// has already belonged to the list when it was set, this is fine.
-		let cursor = unsafe{ self.fragments.cursor_from_ptr(self.iter_cursor) };
+		let list = unsafe { &*self.fragments.list.get() };
+		let cursor = unsafe{ list.cursor_from_ptr(self.iter_cursor) };
 		let buf = unsafe { &*cursor.get().unwrap().buf.get() };
 	
 		// Perform the actual access. This is always a valid element because no elements can
@@ -194,18 +322,14 @@ impl<T: 'static + Send> Buffer<T> {
 	/// Tries to push elem into the buffer. Fails if no capacity is available, usually
 	/// because the manager thread was too slow in adding new capacity.
 	pub fn push(&mut self, elem: T) -> Result<(), T> {
-		let remaining = {
-			let frag = self.fragments.back_mut();
-			let buf = unsafe { &*frag.get().unwrap().buf.get() };
-			buf.capacity() - buf.len()
-		};
+		let remaining = self.remaining();
 
 		if remaining < 1 {
 			// we can't fit the data into the current fragment, let's check whether
 			// a new fragment has been queued already
 			match self.incoming_fragment_ringbuf.pop() {
 				Some(fragment) => {
-					self.fragments.push_back(fragment);
+					self.append_fragment(fragment);
 					self.request_pending = false;
 				}
 				None => {
@@ -214,18 +338,93 @@ impl<T: 'static + Send> Buffer<T> {
 			}
 		}
 		
-		unsafe {
-			(*self.fragments.back_mut().get().unwrap().buf.get()).push(elem);
-		}
+		self.push_unchecked(elem);
 
 		if remaining <= self.remaining_threshold && !self.request_pending {
-			self.new_fragment_request_ringbuf.push(ThreadRequest::Fragment).map_err(|_|()).unwrap();
-			self.request_pending = true;
-			self.thread_handle.thread().unpark();
+			self.request_fragment();
 		}
 
 		Ok(())
 	}
+
+	/// Returns the free capacity of the last fragment.
+	fn remaining(&self) -> usize {
+		let list = unsafe { &*self.fragments.list.get() };
+		let buf = unsafe { &*list.back().get().unwrap().buf.get() };
+		buf.capacity() - buf.len()
+	}
+
+	/// Pushes `elem` into the last fragment, which must have free capacity, and publishes it to readers.
+	fn push_unchecked(&mut self, elem: T) {
+		let list = unsafe { &mut *self.fragments.list.get() };
+		unsafe {
+			let buf = &mut *list.back_mut().get().unwrap().buf.get();
+			debug_assert!(buf.len() < buf.capacity());
+			buf.push(elem);
+		}
+		self.len += 1;
+		self.fragments.published.store(self.len, Ordering::Release);
+	}
+
+	fn append_fragment(&mut self, fragment: Box<BufferFragment<T>>) {
+		// `BufferReader::to_vec` relies on all fragments but the last one being full
+		assert!(self.remaining() == 0, "only full fragments may be followed by another one");
+		let list = unsafe { &mut *self.fragments.list.get() };
+		let fragment_ptr = &*fragment as *const BufferFragment<T> as *mut BufferFragment<T>;
+		list.back().get().unwrap().next.store(fragment_ptr, Ordering::Release);
+		list.push_back(fragment);
+	}
+
+	fn request_fragment(&mut self) {
+		self.new_fragment_request_ringbuf.push(ThreadRequest::Fragment).map_err(|_|()).unwrap();
+		self.request_pending = true;
+		self.thread_handle.thread().unpark();
+	}
+}
+
+/// Read-only view on all elements that have been pushed to a `Buffer`. Can be used from a
+/// different thread than the buffer itself, and keeps the data alive after the buffer is dropped.
+pub struct BufferReader<T> {
+	fragments: Arc<FragmentList<T>>
+}
+
+impl<T> BufferReader<T> {
+	/// Returns the number of elements that have been pushed so far.
+	pub fn len(&self) -> usize {
+		self.fragments.published.load(Ordering::Acquire)
+	}
+
+	/// Returns whether no elements have been pushed yet.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Copies up to `max` elements, starting at the beginning of the buffer.
+	/// This function is not real-time-safe and will allocate memory.
+	pub fn to_vec(&self, max: usize) -> Vec<T> where T: Clone {
+		self.map_to_vec(max, T::clone)
+	}
+
+	/// Like `to_vec()`, but converts the elements using `f`.
+	pub fn map_to_vec<U>(&self, max: usize, mut f: impl FnMut(&T) -> U) -> Vec<U> {
+		let mut remaining = std::cmp::min(self.len(), max);
+		let mut result = Vec::with_capacity(remaining);
+		let mut frag = self.fragments.first;
+		while remaining > 0 {
+			// frag is valid: it's the first fragment or was linked to a fragment that is full,
+			// because it contains less elements than were published. Since only full fragments
+			// are followed by another one, all elements up to its capacity are published.
+			let n = std::cmp::min(remaining, unsafe { (*frag).capacity });
+			for i in 0..n {
+				result.push( f(unsafe { &*(*frag).data.add(i) }) );
+			}
+			remaining -= n;
+			if remaining > 0 {
+				frag = unsafe { (*frag).next.load(Ordering::Acquire) };
+			}
+		}
+		result
+	}
 }
 
 #[cfg(test)]
@@ -373,6 +572,87 @@ mod tests {
 		assert!(assert_no_alloc(|| buffer.next()).is_none());
 	}
 
+	#[test]
+	pub fn seek_works_across_fragments() {
+		let mut buffer = Buffer::<u32>::new(8, 4);
+		for i in 0..6 {
+			rt_assert!( buffer.push(i).is_ok() );
+		}
+		wait();
+		for i in 6..12 {
+			rt_assert!( buffer.push(i).is_ok() );
+		}
+
+		assert_no_alloc(|| buffer.seek(10));
+		assert!( *assert_no_alloc(|| buffer.next()).unwrap() == 10 );
+		assert_no_alloc(|| buffer.seek(3));
+		assert!( *assert_no_alloc(|| buffer.next()).unwrap() == 3 );
+		assert_no_alloc(|| buffer.seek(8));
+		assert!( *assert_no_alloc(|| buffer.next()).unwrap() == 8 );
+		assert_no_alloc(|| buffer.seek(12));
+		assert!( assert_no_alloc(|| buffer.next()).is_none() );
+	}
+
+	#[test]
+	pub fn cursors_advance_independently_and_can_overwrite_atomic_items() {
+		use std::sync::atomic::AtomicU32;
+		let mut buffer = Buffer::from_iter((0u32..12).map(AtomicU32::new), 4, 2);
+		let reader = buffer.reader();
+
+		let mut cursor = buffer.cursor_at(3);
+		buffer.rewind();
+		for i in 3..9 {
+			let item = assert_no_alloc(|| buffer.next_at(&mut cursor)).unwrap();
+			item.store(i * 10, Ordering::Relaxed);
+		}
+		assert!( assert_no_alloc(|| buffer.next_at(&mut cursor)).unwrap().load(Ordering::Relaxed) == 9 );
+
+		for i in 0..12 {
+			let expected = if (3..9).contains(&i) { i * 10 } else { i };
+			assert!( assert_no_alloc(|| buffer.next()).unwrap().load(Ordering::Relaxed) == expected );
+		}
+		assert!(reader.map_to_vec(12, |x| x.load(Ordering::Relaxed)) == vec![0, 1, 2, 30, 40, 50, 60, 70, 80, 9, 10, 11]);
+		assert!( assert_no_alloc(|| buffer.next_at(&mut buffer.cursor_at(12))).is_none() );
+	}
+
+	#[test]
+	pub fn buffer_can_be_created_from_slice() {
+		let data: Vec<u32> = (0..100).collect();
+		let mut buffer = Buffer::from_slice(&data, 8, 4);
+		buffer.rewind();
+		for i in 0..100 {
+			assert!( *assert_no_alloc(|| buffer.next()).unwrap() == i );
+		}
+		assert!( assert_no_alloc(|| buffer.next()).is_none() );
+
+		wait();
+		for i in 100..104 {
+			rt_assert!( buffer.push(i).is_ok() );
+		}
+	}
+
+	#[test]
+	pub fn reader_sees_published_elements_only() {
+		let mut buffer = Buffer::<u32>::new(8, 4);
+		let reader = buffer.reader();
+		assert!( reader.to_vec(100).is_empty() );
+
+		for i in 0..6 {
+			rt_assert!( buffer.push(i).is_ok() );
+		}
+		assert!( reader.to_vec(100) == vec![0,1,2,3,4,5] );
+
+		wait();
+		for i in 6..12 {
+			rt_assert!( buffer.push(i).is_ok() );
+		}
+		assert!( reader.to_vec(100) == (0..12).collect::<Vec<_>>() );
+		assert!( reader.to_vec(9) == (0..9).collect::<Vec<_>>() );
+
+		drop(buffer);
+		assert!( reader.to_vec(100) == (0..12).collect::<Vec<_>>() );
+	}
+
 	#[test]
 	pub fn push_fails_gracefully_when_too_fast() {
 		let mut buffer = Buffer::<u32>::new(2, 1);
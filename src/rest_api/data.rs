@@ -1,11 +1,89 @@
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
+use crate::engine::{JackTransportMode, MetronomeMode, MetronomeSettings};
 
 #[derive(Serialize,Clone)]
 pub struct Song {
 	pub song_position: f64,
 	pub transport_position: f64,
 	pub playing: bool,
-	pub loop_length: f64
+	pub loop_length: f64,
+	pub beats: u32,
+	/// Tempo in beats per minute, derived from the loop length and the number of beats
+	pub bpm: f64,
+	/// Id of the synth whose MIDI clock the song follows
+	pub clock_source: Option<u32>,
+	/// Set while the first recording is going to define the loop length
+	pub free_running: Option<FreeRunning>,
+	pub jack_transport: JackTransport,
+	/// How the length of a take is determined when its recording is finished
+	pub finish_rounding: FinishRounding
+}
+
+/// Lets the first take define the loop length. It starts recording right away and the loop
+/// length is set to its duration once it is finished.
+#[derive(Serialize,Deserialize,Clone)]
+pub struct FreeRunning {
+	/// Number of beats in the loop. Defaults to the current number of beats.
+	pub beats: Option<u32>,
+	/// If set, the tempo is rounded to whole beats per minute within this range, e.g.
+	/// `[80, 160]`, and the loop length is adjusted accordingly.
+	pub bpm_range: Option<(f64, f64)>
+}
+
+/// Policy for rounding the duration of a take to a whole number of loops when its recording
+/// is finished. Takes are always at least one loop long.
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishRounding {
+	/// Shortens takes that exceed a number of loops by up to a quarter loop and extends all others
+	Lenient,
+	Nearest,
+	Up,
+	Down,
+	/// Rounds to the nearest power of two loops, i.e. 1, 2, 4, 8...
+	PowerOfTwo
+}
+
+impl std::str::FromStr for FinishRounding {
+	type Err = ();
+	fn from_str(s: &str) -> Result<FinishRounding, ()> {
+		match s {
+			"lenient" => Ok(FinishRounding::Lenient),
+			"nearest" => Ok(FinishRounding::Nearest),
+			"up" => Ok(FinishRounding::Up),
+			"down" => Ok(FinishRounding::Down),
+			"power_of_two" => Ok(FinishRounding::PowerOfTwo),
+			_ => Err(())
+		}
+	}
+}
+
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JackTransport {
+	Ignore,
+	Follow,
+	TimebaseMaster
+}
+
+impl From<JackTransportMode> for JackTransport {
+	fn from(mode: JackTransportMode) -> JackTransport {
+		match mode {
+			JackTransportMode::Ignore => JackTransport::Ignore,
+			JackTransportMode::Follow => JackTransport::Follow,
+			JackTransportMode::TimebaseMaster => JackTransport::TimebaseMaster
+		}
+	}
+}
+
+impl From<JackTransport> for JackTransportMode {
+	fn from(mode: JackTransport) -> JackTransportMode {
+		match mode {
+			JackTransport::Ignore => JackTransportMode::Ignore,
+			JackTransport::Follow => JackTransportMode::Follow,
+			JackTransport::TimebaseMaster => JackTransportMode::TimebaseMaster
+		}
+	}
 }
 
 #[derive(Serialize,Clone)]
@@ -13,6 +91,10 @@ pub struct Synth {
 	pub id: u32,
 	pub name: String,
 	pub chains: Vec<Chain>,
+	/// Whether the synth receives MIDI clock ticks
+	pub clock: bool,
+	/// Delay of the clock ticks in samples
+	pub clock_offset: i32,
 
 	#[serde(skip)]
 	pub engine_mididevice_id: usize
@@ -25,11 +107,96 @@ pub struct Chain {
 	pub takes: Vec<Take>,
 	pub midi: bool,
 	pub echo: bool,
+	pub gain: f32,
 
 	#[serde(skip)]
 	pub engine_audiodevice_id: usize
 }
 
+/// Levels of the output buses. The master bus sums the unmuted takes of all chains, the
+/// monitor bus carries the metronome, echoed inputs and cued takes.
+#[derive(Serialize,Clone)]
+pub struct Mixer {
+	pub master_gain: f32,
+	pub monitor_gain: f32,
+	pub metronome_gain: f32,
+	/// Fade time of mute and cue changes in seconds. Only applies to audio takes created afterwards.
+	pub fade_length: f32,
+	/// Crossfade time at the loop seam in seconds. Only applies to audio takes created afterwards.
+	pub crossfade_length: f32
+}
+
+/// Settings of the "clock" MIDI port
+#[derive(Serialize,Clone)]
+pub struct MidiClock {
+	pub enabled: bool,
+	/// Delay of the clock ticks in samples. Negative values send them earlier.
+	pub offset: i32
+}
+
+#[derive(Serialize,Clone)]
+pub struct Metronome {
+	pub muted: bool,
+	pub mode: MetronomeClickMode,
+	pub volume: f32,
+	/// Level of the clicks on accented beats, relative to the other clicks
+	pub accent: f32,
+	/// Frequency of the clicks in Hz. Accented beats are one octave higher.
+	pub pitch: f32,
+	/// Number of clicks per beat, e.g. 2 for eighths or 3 for triplets
+	pub subdivision: u32,
+	/// Accented beats, counted from zero
+	pub accents: Vec<u32>,
+	/// Whether a sample has been loaded for the accented beats
+	pub accent_sample: bool,
+	/// Whether a sample has been loaded for the other clicks
+	pub click_sample: bool
+}
+
+/// `recording` only clicks while takes are waiting to be recorded or recording
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetronomeClickMode {
+	Always,
+	Recording
+}
+
+impl From<&Metronome> for MetronomeSettings {
+	fn from(metronome: &Metronome) -> MetronomeSettings {
+		MetronomeSettings {
+			unmuted: !metronome.muted,
+			mode: match metronome.mode {
+				MetronomeClickMode::Always => MetronomeMode::Always,
+				MetronomeClickMode::Recording => MetronomeMode::Recording
+			},
+			volume: metronome.volume,
+			accent: metronome.accent,
+			pitch: metronome.pitch,
+			subdivision: metronome.subdivision,
+			accents: metronome.accents.iter().filter(|beat| **beat < 32).fold(0, |mask, beat| mask | 1 << beat)
+		}
+	}
+}
+
+impl From<MetronomeSettings> for Metronome {
+	fn from(settings: MetronomeSettings) -> Metronome {
+		Metronome {
+			muted: !settings.unmuted,
+			mode: match settings.mode {
+				MetronomeMode::Always => MetronomeClickMode::Always,
+				MetronomeMode::Recording => MetronomeClickMode::Recording
+			},
+			volume: settings.volume,
+			accent: settings.accent,
+			pitch: settings.pitch,
+			subdivision: settings.subdivision,
+			accents: (0..32).filter(|beat| settings.accents & 1 << beat != 0).collect(),
+			accent_sample: false,
+			click_sample: false
+		}
+	}
+}
+
 #[derive(Clone,PartialEq)]
 pub enum RecordingState {
 	Waiting,
@@ -75,6 +242,14 @@ pub struct Take {
 	pub state: RecordingState,
 	pub muted: bool,
 	pub muted_scheduled: bool,
+	pub gain: f32,
+	pub pan: f32,
+	/// Cued takes can be heard on the monitor bus even while muted.
+	pub cued: bool,
+	/// While overdubbing, the chain's input is summed into the take as it plays.
+	pub overdub: bool,
+	/// Factor by which the take's previous content is scaled while overdubbing.
+	pub overdub_feedback: f32,
 	pub associated_midi_takes: Vec<u32>,
 	pub playing_since: Option<f64>,
 	pub duration: Option<f64>,
@@ -0,0 +1,147 @@
+use super::gui_state::*;
+use rocket::State;
+use rocket::http::Status;
+use super::updates::*;
+use super::history::Operation;
+use super::patch::set_metronome_sample_;
+use crate::engine::{FrontendTrait, MetronomeSound};
+
+#[delete("/synths/<synthid>")]
+pub async fn delete_synth(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	if let Some(index) = guard.synths.iter().position(|s| s.id == synthid) {
+		check_synth_deletable(guard.engine.as_ref(), &guard.synths[index])?;
+		// retired takes would keep the devices alive
+		guard.history.forget_chains(guard.engine.as_mut(), &guard.synths, |s, _| s == synthid);
+		let was_clock_source = guard.engine.clock_source() == Some(guard.synths[index].engine_mididevice_id);
+		delete_synth_(guard.engine.as_mut(), &mut guard.synths[index])?;
+		guard.synths.remove(index);
+		state.update_list.push(make_update_synth_deleted(synthid)).await;
+		if was_clock_source {
+			state.update_list.push( make_update_song(UpdateSong {
+				clock_source: Some(None),
+				..Default::default()
+			})).await;
+		}
+		return Ok(());
+	}
+	Err(Status::NotFound)
+}
+
+/// Restores the synthesized click in place of the metronome's `accent_sample`.
+#[delete("/metronome/accent_sample")]
+pub async fn delete_metronome_accent_sample(state: State<'_, std::sync::Arc<GuiState>>) -> Result<(), Status> {
+	delete_metronome_sample(&state, MetronomeSound::Accent).await
+}
+
+/// Restores the synthesized click in place of the metronome's `click_sample`.
+#[delete("/metronome/click_sample")]
+pub async fn delete_metronome_click_sample(state: State<'_, std::sync::Arc<GuiState>>) -> Result<(), Status> {
+	delete_metronome_sample(&state, MetronomeSound::Click).await
+}
+
+async fn delete_metronome_sample(state: &GuiState, which: MetronomeSound) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	set_metronome_sample_(guard, &state.update_list, which, None).await
+}
+
+#[delete("/synths/<synthid>/chains/<chainid>")]
+pub async fn delete_chain(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, chainid: u32) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	if let Some(synth_index) = guard.synths.iter().position(|s| s.id == synthid) {
+		if let Some(index) = guard.synths[synth_index].chains.iter().position(|c| c.id == chainid) {
+			let synth = &guard.synths[synth_index];
+			check_chain_deletable(guard.engine.as_ref(), synth.engine_mididevice_id, &synth.chains[index])?;
+			// retired takes would keep the device alive
+			guard.history.forget_chains(guard.engine.as_mut(), &guard.synths, |s, c| s == synthid && c == chainid);
+			let synth = &mut guard.synths[synth_index];
+			delete_chain_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains[index])?;
+			synth.chains.remove(index);
+			state.update_list.push(make_update_chain_deleted(chainid, synthid)).await;
+			return Ok(());
+		}
+	}
+	Err(Status::NotFound)
+}
+
+#[delete("/synths/<synthid>/chains/<chainid>/takes/<takeid>")]
+pub async fn delete_take(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, chainid: u32, takeid: u32) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	if let Some(synth) = guard.synths.iter().find(|s| s.id == synthid) {
+		if let Some(chain) = synth.chains.iter().find(|c| c.id == chainid) {
+			if chain.takes.iter().any(|t| t.id == takeid) {
+				// finished takes are only retired, so that the deletion can be undone
+				let mut updates = Vec::new();
+				let result = guard.history.perform(guard.engine.as_mut(), &mut guard.synths, vec![Operation::Remove(takeid)], &mut updates);
+				for update in updates {
+					state.update_list.push(update).await;
+				}
+				return result;
+			}
+		}
+	}
+	Err(Status::NotFound)
+}
+
+/// Deletes all chains and takes of the synth and its devices. Retired takes of the synth
+/// must have been deleted before. Nothing is changed if this fails.
+pub fn delete_synth_(engine: &mut dyn FrontendTrait, synth: &mut Synth) -> Result<(), Status> {
+	check_synth_deletable(engine, synth)?;
+	while let Some(chain) = synth.chains.last_mut() {
+		delete_chain_(engine, synth.engine_mididevice_id, chain)?;
+		synth.chains.pop();
+	}
+	engine.remove_mididevice(synth.engine_mididevice_id).map_err(|_| Status::InternalServerError)
+}
+
+/// Deletes all takes of the chain and its audio device. Retired takes of the chain must have
+/// been deleted before. Nothing is changed if this fails.
+pub fn delete_chain_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chain: &mut Chain) -> Result<(), Status> {
+	check_chain_deletable(engine, mididevice_id, chain)?;
+	while let Some(take) = chain.takes.last() {
+		delete_take_(engine, mididevice_id, chain.engine_audiodevice_id, take)?;
+		chain.takes.pop();
+	}
+	engine.remove_device(chain.engine_audiodevice_id).map_err(|_| Status::InternalServerError)
+}
+
+/// Checks that the engine knows all takes of the synth's chains and that its MIDI device has
+/// no other takes, so that `delete_synth_` cannot fail halfway. Retired takes are ignored.
+pub fn check_synth_deletable(engine: &dyn FrontendTrait, synth: &Synth) -> Result<(), Status> {
+	for chain in synth.chains.iter() {
+		check_chain_deletable(engine, synth.engine_mididevice_id, chain)?;
+	}
+	let n_miditakes = synth.chains.iter().flat_map(|c| c.takes.iter()).filter(|t| t.is_midi()).count();
+	if engine.mididevices().get(&synth.engine_mididevice_id).map(|dev| dev.takes().len()) != Some(n_miditakes) {
+		return Err(Status::InternalServerError);
+	}
+	Ok(())
+}
+
+/// Checks that the engine knows all takes of the chain and that its audio device has no other
+/// takes, so that `delete_chain_` cannot fail halfway. Retired takes are ignored.
+pub fn check_chain_deletable(engine: &dyn FrontendTrait, mididevice_id: usize, chain: &Chain) -> Result<(), Status> {
+	let dev = engine.devices().get(&chain.engine_audiodevice_id).ok_or(Status::InternalServerError)?;
+	let mididev = engine.mididevices().get(&mididevice_id).ok_or(Status::InternalServerError)?;
+	let known = chain.takes.iter().all(|take| match take.engine_take_id {
+		EngineTakeRef::Audio(id) => dev.takes().contains_key(&id),
+		EngineTakeRef::Midi(id) => mididev.takes().contains_key(&id)
+	});
+	let n_audiotakes = chain.takes.iter().filter(|t| !t.is_midi()).count();
+	if !known || dev.takes().len() != n_audiotakes {
+		return Err(Status::InternalServerError);
+	}
+	Ok(())
+}
+
+pub fn delete_take_(engine: &mut dyn FrontendTrait, mididevice_id: usize, audiodevice_id: usize, take: &Take) -> Result<(), Status> {
+	let result = match take.engine_take_id {
+		EngineTakeRef::Audio(id) => engine.delete_audiotake(audiodevice_id, id),
+		EngineTakeRef::Midi(id) => engine.delete_miditake(mididevice_id, id)
+	};
+	result.map_err(|_| Status::InternalServerError)
+}
@@ -1,6 +1,16 @@
 use rocket_contrib::json::Json;
 use super::gui_state::*;
 use rocket::State;
+use rocket::http::{Status, ContentType};
+use rocket::response::content::Content;
+use super::util::tempo;
+use crate::wav::write_wav;
+use crate::smf::write_smf;
+use crate::midi_message::{MidiMessage, MidiEvent};
+use std::convert::TryFrom;
+
+/// Resolution of exported Standard MIDI Files
+const TICKS_PER_QUARTER: u32 = 960;
 
 #[get("/song")]
 pub async fn song_get(state: State<'_, std::sync::Arc<GuiState>>) -> Json<Song> {
@@ -10,10 +20,34 @@ pub async fn song_get(state: State<'_, std::sync::Arc<GuiState>>) -> Json<Song>
 		song_position: e.song_position() as f64 / e.sample_rate() as f64,
 		transport_position: e.transport_position() as f64 / e.sample_rate() as f64,
 		loop_length: e.loop_length() as f64 / e.sample_rate() as f64,
-		playing: true
+		beats: e.n_beats(),
+		bpm: tempo(e.loop_length(), e.n_beats(), e.sample_rate()),
+		playing: e.playing(),
+		free_running: lock.free_running.clone(),
+		clock_source: lock.synths.iter().find(|s| Some(s.engine_mididevice_id) == e.clock_source()).map(|s| s.id),
+		jack_transport: e.jack_transport_mode().into(),
+		finish_rounding: lock.finish_rounding
 	})
 }
 
+#[get("/mixer")]
+pub async fn mixer_get(state: State<'_, std::sync::Arc<GuiState>>) -> Json<Mixer> {
+	let lock = state.mutex.lock().await;
+	Json(lock.mixer.clone())
+}
+
+#[get("/midiclock")]
+pub async fn midiclock_get(state: State<'_, std::sync::Arc<GuiState>>) -> Json<MidiClock> {
+	let lock = state.mutex.lock().await;
+	Json(lock.midiclock.clone())
+}
+
+#[get("/metronome")]
+pub async fn metronome_get(state: State<'_, std::sync::Arc<GuiState>>) -> Json<Metronome> {
+	let lock = state.mutex.lock().await;
+	Json(lock.metronome.clone())
+}
+
 #[get("/synths")]
 pub async fn synths_get(state: State<'_, std::sync::Arc<GuiState>>) -> Json< Vec<Synth> > {
 	let lock = state.mutex.lock().await;
@@ -64,3 +98,80 @@ pub async fn takes_get_one(state: State<'_, std::sync::Arc<GuiState>>, synthnum:
 	).flatten().flatten()
 }
 
+
+#[get("/synths/<synthnum>/chains/<chainnum>/takes/<takenum>/audio.wav")]
+pub async fn takes_get_wav(state: State<'_, std::sync::Arc<GuiState>>, synthnum: u32, chainnum:u32, takenum: u32) -> Result<Content<Vec<u8>>, Status> {
+	let lock = state.mutex.lock().await;
+	let chain = lock.synths.iter().find(|s| s.id == synthnum)
+		.and_then(|s| s.chains.iter().find(|c| c.id == chainnum))
+		.ok_or(Status::NotFound)?;
+	let take = chain.takes.iter().find(|t| t.id == takenum).ok_or(Status::NotFound)?;
+
+	if let EngineTakeRef::Audio(id) = take.engine_take_id {
+		if take.state != RecordingState::Finished {
+			return Err(Status::UnprocessableEntity);
+		}
+		let length = lock.engine.devices()[&chain.engine_audiodevice_id].takes()[&id].length.ok_or(Status::UnprocessableEntity)?;
+		let mut samples = lock.engine.audiotake_samples(chain.engine_audiodevice_id, id).ok_or(Status::InternalServerError)?;
+		for channel in samples.iter_mut() {
+			channel.resize(length as usize, 0.0);
+		}
+
+		let mut wav = Vec::new();
+		write_wav(&mut wav, lock.engine.sample_rate(), &samples).map_err(|_| Status::InternalServerError)?;
+		Ok(Content(ContentType::new("audio", "wav"), wav))
+	}
+	else {
+		Err(Status::NotFound)
+	}
+}
+
+#[get("/synths/<synthnum>/chains/<chainnum>/takes/<takenum>/take.mid")]
+pub async fn takes_get_smf(state: State<'_, std::sync::Arc<GuiState>>, synthnum: u32, chainnum:u32, takenum: u32) -> Result<Content<Vec<u8>>, Status> {
+	let lock = state.mutex.lock().await;
+	let synth = lock.synths.iter().find(|s| s.id == synthnum).ok_or(Status::NotFound)?;
+	let chain = synth.chains.iter().find(|c| c.id == chainnum).ok_or(Status::NotFound)?;
+	let take = chain.takes.iter().find(|t| t.id == takenum).ok_or(Status::NotFound)?;
+
+	if let EngineTakeRef::Midi(id) = take.engine_take_id {
+		if take.state != RecordingState::Finished {
+			return Err(Status::UnprocessableEntity);
+		}
+		let e = &lock.engine;
+		let length = e.mididevices()[&synth.engine_mididevice_id].takes()[&id].length.ok_or(Status::UnprocessableEntity)?;
+		let events = e.miditake_events(synth.engine_mididevice_id, id).ok_or(Status::InternalServerError)?;
+
+		// one beat of the song is one quarter note
+		let loop_length = e.loop_length() as u64;
+		let n_beats = e.n_beats() as u64;
+		let to_ticks = |timestamp: u32| ((timestamp as u64 * n_beats * TICKS_PER_QUARTER as u64 + loop_length / 2) / loop_length) as u32;
+		let end_tick = to_ticks(length);
+
+		let mut held_notes = Vec::new();
+		let mut smf_events: Vec<MidiMessage> = events.iter().map(|event| {
+			match MidiEvent::parse(&event.data) {
+				MidiEvent::NoteOn(channel, note, _) => held_notes.push((channel, note)),
+				MidiEvent::NoteOff(channel, note, _) => held_notes.retain(|n| *n != (channel, note)),
+				MidiEvent::Unknown => {}
+			}
+			MidiMessage { timestamp: to_ticks(event.timestamp), data: event.data, datalen: event.datalen }
+		}).collect();
+		// notes that are still held at the end of the take are released by the engine when looping
+		for (channel, note) in held_notes {
+			smf_events.push(MidiMessage { timestamp: end_tick, data: [0x80 | channel, note, 0], datalen: 3 });
+		}
+
+		// the tempo meta event has three bytes, which cover tempos down to about 3.6 bpm. Slower
+		// songs are written at that tempo; the events are placed by beat anyway.
+		let microseconds_per_quarter = std::cmp::min(loop_length * 1000000 / n_beats / e.sample_rate() as u64, 0xFFFFFF) as u32;
+		// the time signature's numerator is a single byte
+		let numerator = u8::try_from(n_beats).map_err(|_| Status::UnprocessableEntity)?;
+		let mut smf = Vec::new();
+		write_smf(&mut smf, TICKS_PER_QUARTER as u16, microseconds_per_quarter, numerator, &smf_events, end_tick)
+			.map_err(|_| Status::InternalServerError)?;
+		Ok(Content(ContentType::new("audio", "midi"), smf))
+	}
+	else {
+		Err(Status::NotFound)
+	}
+}
@@ -4,13 +4,22 @@ use super::updates::*;
 use crate::id_generator::IdGenerator;
 use async_std::sync::Mutex;
 use crate::engine::*;
+use super::history::History;
+use super::util::TapTempo;
 
 pub struct GuiMutexedState {
 	pub engine: Box<dyn FrontendTrait>,
 	pub synths: Vec<Synth>,
+	pub mixer: Mixer,
+	pub midiclock: MidiClock,
+	pub metronome: Metronome,
 	pub take_id: IdGenerator,
 	pub chain_id: IdGenerator,
 	pub synth_id: IdGenerator,
+	pub history: History,
+	pub tap_tempo: TapTempo,
+	pub free_running: Option<FreeRunning>,
+	pub finish_rounding: FinishRounding,
 }
 
 impl GuiMutexedState {
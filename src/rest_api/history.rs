@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+use rocket::http::Status;
+use super::gui_state::*;
+use super::updates::*;
+use super::delete::delete_take_;
+use crate::engine::FrontendTrait;
+
+/// Number of user actions that can be undone
+const MAX_HISTORY_LENGTH: usize = 64;
+
+/// A finished take that has been removed from its chain. Its engine take is retired
+/// and can be restored, or must be deleted once the history entry expires.
+pub struct RemovedTake {
+	synthid: u32,
+	chainid: u32,
+	mididevice_id: usize,
+	audiodevice_id: usize,
+	index: usize,
+	take: Take,
+	/// Audio takes in the same chain that were associated with this MIDI take.
+	associated_with: Vec<u32>,
+}
+
+/// A single change to a take, identified by its (globally unique) REST id. Applying an
+/// operation yields the operation that reverts it.
+pub enum Operation {
+	/// Removes the take. Finished takes are retired, all others are deleted for good.
+	Remove(u32),
+	Reinsert(RemovedTake),
+	/// Finishes a take that is still recording after the given number of samples.
+	Finish(u32, u32),
+	Unfinish(u32),
+	/// Sets whether the take is muted and whether a toggle of that is scheduled for the
+	/// next loop boundary.
+	SetMuted(u32, bool, bool),
+	Rename(u32, String),
+}
+
+impl Operation {
+	fn takeid(&self) -> u32 {
+		match self {
+			Operation::Remove(takeid) | Operation::Finish(takeid, _) | Operation::Unfinish(takeid) |
+			Operation::SetMuted(takeid, _, _) | Operation::Rename(takeid, _) => *takeid,
+			Operation::Reinsert(removed) => removed.take.id
+		}
+	}
+}
+
+/// Undo and redo stacks. Each entry holds the operations that revert one user action.
+pub struct History {
+	undo: VecDeque<Vec<Operation>>,
+	redo: Vec<Vec<Operation>>,
+}
+
+impl History {
+	pub fn new() -> History {
+		History { undo: VecDeque::new(), redo: Vec::new() }
+	}
+
+	/// Records the operations that revert a user action which has just been performed.
+	pub fn push(&mut self, engine: &mut dyn FrontendTrait, entry: Vec<Operation>) {
+		if entry.is_empty() {
+			return;
+		}
+		for entry in self.redo.drain(..) {
+			forget_entry(engine, entry);
+		}
+		self.undo.push_back(entry);
+		if self.undo.len() > MAX_HISTORY_LENGTH {
+			forget_entry(engine, self.undo.pop_front().unwrap());
+		}
+	}
+
+	/// Performs `entry` as a user action and records it.
+	pub fn perform(&mut self, engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, entry: Vec<Operation>, updates: &mut Vec<UpdateRoot>) -> Result<(), Status> {
+		let (result, inverse) = apply_entry(engine, synths, entry, updates);
+		self.push(engine, inverse);
+		result
+	}
+
+	pub fn undo(&mut self, engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, updates: &mut Vec<UpdateRoot>) -> Result<(), Status> {
+		let entry = self.undo.pop_back().ok_or(Status::Conflict)?;
+		let (result, inverse) = apply_entry(engine, synths, entry, updates);
+		if !inverse.is_empty() {
+			self.redo.push(inverse);
+		}
+		result
+	}
+
+	pub fn redo(&mut self, engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, updates: &mut Vec<UpdateRoot>) -> Result<(), Status> {
+		let entry = self.redo.pop().ok_or(Status::Conflict)?;
+		let (result, inverse) = apply_entry(engine, synths, entry, updates);
+		if !inverse.is_empty() {
+			self.undo.push_back(inverse);
+		}
+		result
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.undo.is_empty() && self.redo.is_empty()
+	}
+
+	/// Forgets the operations concerning takes of the chains for which `affected(synthid, chainid)`
+	/// holds and deletes the affected takes that were only kept for undoing. Must be called
+	/// before the chains' devices are removed. Entries that become empty are dropped.
+	pub fn forget_chains(&mut self, engine: &mut dyn FrontendTrait, synths: &[Synth], affected: impl Fn(u32, u32) -> bool) {
+		let mut takeids = Vec::new();
+		for synth in synths {
+			for chain in synth.chains.iter().filter(|c| affected(synth.id, c.id)) {
+				takeids.extend(chain.takes.iter().map(|t| t.id));
+			}
+		}
+		for op in self.undo.iter().chain(self.redo.iter()).flatten() {
+			if let Operation::Reinsert(removed) = op {
+				if affected(removed.synthid, removed.chainid) {
+					takeids.push(removed.take.id);
+				}
+			}
+		}
+		self.forget_takes(engine, &takeids);
+	}
+
+	/// Forgets the operations concerning the takes `takeids` and deletes those of them that
+	/// were only kept for undoing. Entries that become empty are dropped.
+	pub fn forget_takes(&mut self, engine: &mut dyn FrontendTrait, takeids: &[u32]) {
+		for entry in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+			let (forgotten, kept): (Vec<Operation>, Vec<Operation>) = std::mem::take(entry).into_iter()
+				.partition(|op| takeids.contains(&op.takeid()));
+			*entry = kept;
+			forget_entry(engine, forgotten);
+		}
+		self.undo.retain(|entry| !entry.is_empty());
+		self.redo.retain(|entry| !entry.is_empty());
+	}
+
+	/// Forgets the operations concerning removed takes and deletes these takes, which were
+	/// only kept for undoing. Must be called before the loop length is changed, as they
+	/// cannot be rescaled.
+	pub fn forget_removed_takes(&mut self, engine: &mut dyn FrontendTrait) {
+		let takeids: Vec<u32> = self.undo.iter().chain(self.redo.iter()).flatten()
+			.filter_map(|op| match op {
+				Operation::Reinsert(removed) => Some(removed.take.id),
+				_ => None
+			})
+			.collect();
+		self.forget_takes(engine, &takeids);
+	}
+
+	/// Forgets all entries and deletes the takes that were only kept for undoing.
+	pub fn clear(&mut self, engine: &mut dyn FrontendTrait) {
+		for entry in self.undo.drain(..).chain(self.redo.drain(..)) {
+			forget_entry(engine, entry);
+		}
+	}
+}
+
+/// Applies the operations of `entry` in reverse order and returns the entry that reverts
+/// them. If an operation fails, the remaining ones are dropped.
+fn apply_entry(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, mut entry: Vec<Operation>, updates: &mut Vec<UpdateRoot>) -> (Result<(), Status>, Vec<Operation>) {
+	let mut inverse = Vec::new();
+	while let Some(op) = entry.pop() {
+		match apply(engine, synths, op, updates) {
+			Ok(Some(op)) => inverse.push(op),
+			Ok(None) => {}
+			Err(status) => {
+				forget_entry(engine, entry);
+				return (Err(status), inverse);
+			}
+		}
+	}
+	(Ok(()), inverse)
+}
+
+fn forget_entry(engine: &mut dyn FrontendTrait, entry: Vec<Operation>) {
+	for op in entry {
+		if let Operation::Reinsert(removed) = op {
+			forget_removed_take(engine, &removed);
+		}
+	}
+}
+
+fn forget_removed_take(engine: &mut dyn FrontendTrait, removed: &RemovedTake) {
+	if delete_take_(engine, removed.mididevice_id, removed.audiodevice_id, &removed.take).is_err() {
+		println!("failed to delete retired take {}", removed.take.id);
+	}
+}
+
+/// Returns the synth's id and MIDI device, the chain and the index of the take `takeid`.
+fn find_take(synths: &mut Vec<Synth>, takeid: u32) -> Option<(u32, usize, &mut Chain, usize)> {
+	for synth in synths.iter_mut() {
+		for chain in synth.chains.iter_mut() {
+			if let Some(index) = chain.takes.iter().position(|t| t.id == takeid) {
+				return Some((synth.id, synth.engine_mididevice_id, chain, index));
+			}
+		}
+	}
+	None
+}
+
+fn apply(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, op: Operation, updates: &mut Vec<UpdateRoot>) -> Result<Option<Operation>, Status> {
+	match op {
+		Operation::Remove(takeid) => {
+			let (synthid, mididevice_id, chain, index) = find_take(synths, takeid).ok_or(Status::Conflict)?;
+			let audiodevice_id = chain.engine_audiodevice_id;
+			let take = &chain.takes[index];
+			let retired = take.state == RecordingState::Finished && match take.engine_take_id {
+				EngineTakeRef::Audio(id) => engine.retire_audiotake(audiodevice_id, id).is_ok(),
+				EngineTakeRef::Midi(id) => engine.retire_miditake(mididevice_id, id).is_ok()
+			};
+			if !retired {
+				delete_take_(engine, mididevice_id, audiodevice_id, take)?;
+			}
+			let take = chain.takes.remove(index);
+			updates.push(make_update_take_deleted(takeid, synthid, chain.id));
+
+			// audio takes must not refer to the removed midi take anymore
+			let mut associated_with = Vec::new();
+			for t in chain.takes.iter_mut().filter(|t| t.associated_midi_takes.contains(&takeid)) {
+				t.associated_midi_takes.retain(|id| *id != takeid);
+				associated_with.push(t.id);
+				updates.push(make_update_take(t, synthid, chain.id));
+			}
+
+			if retired {
+				Ok(Some(Operation::Reinsert(RemovedTake { synthid, chainid: chain.id, mididevice_id, audiodevice_id, index, take, associated_with })))
+			}
+			else {
+				Ok(None)
+			}
+		}
+		Operation::Reinsert(removed) => {
+			let chain = synths.iter_mut()
+				.find(|s| s.id == removed.synthid)
+				.and_then(|s| s.chains.iter_mut().find(|c| c.id == removed.chainid));
+			let restored = match removed.take.engine_take_id {
+				EngineTakeRef::Audio(id) => engine.restore_audiotake(removed.audiodevice_id, id),
+				EngineTakeRef::Midi(id) => engine.restore_miditake(removed.mididevice_id, id)
+			};
+			let chain = match (chain, restored) {
+				(Some(chain), Ok(())) => chain,
+				_ => {
+					forget_removed_take(engine, &removed);
+					return Err(Status::Conflict);
+				}
+			};
+
+			let takeid = removed.take.id;
+			let index = std::cmp::min(removed.index, chain.takes.len());
+			chain.takes.insert(index, removed.take);
+			updates.push(make_update_take(&chain.takes[index], removed.synthid, removed.chainid));
+			for t in chain.takes.iter_mut().filter(|t| removed.associated_with.contains(&t.id)) {
+				t.associated_midi_takes.push(takeid);
+				updates.push(make_update_take(t, removed.synthid, removed.chainid));
+			}
+			Ok(Some(Operation::Remove(takeid)))
+		}
+		Operation::Finish(takeid, length) => {
+			let (synthid, mididevice_id, chain, index) = find_take(synths, takeid).ok_or(Status::Conflict)?;
+			let take = &chain.takes[index];
+			if !matches!(take.state, RecordingState::Recording(_)) {
+				return Err(Status::Conflict);
+			}
+			let result = match take.engine_take_id {
+				EngineTakeRef::Audio(id) => engine.finish_audiotake(chain.engine_audiodevice_id, id, length),
+				EngineTakeRef::Midi(id) => engine.finish_miditake(mididevice_id, id, length)
+			};
+			result.map_err(|_| Status::Conflict)?;
+			let take = &mut chain.takes[index];
+			take.duration = Some(length as f64 / engine.sample_rate() as f64);
+			updates.push(make_update_take(take, synthid, chain.id));
+			Ok(Some(Operation::Unfinish(takeid)))
+		}
+		Operation::Unfinish(takeid) => {
+			let (synthid, mididevice_id, chain, index) = find_take(synths, takeid).ok_or(Status::Conflict)?;
+			let take = &chain.takes[index];
+			// once the take has stopped recording, finishing it cannot be undone anymore
+			if !matches!(take.state, RecordingState::Recording(_)) {
+				return Err(Status::Conflict);
+			}
+			let length = match take.engine_take_id {
+				EngineTakeRef::Audio(id) => engine.devices().get(&chain.engine_audiodevice_id).and_then(|d| d.takes().get(&id)).and_then(|t| t.length),
+				EngineTakeRef::Midi(id) => engine.mididevices().get(&mididevice_id).and_then(|d| d.takes().get(&id)).and_then(|t| t.length)
+			}.ok_or(Status::Conflict)?;
+			let result = match take.engine_take_id {
+				EngineTakeRef::Audio(id) => engine.unfinish_audiotake(chain.engine_audiodevice_id, id),
+				EngineTakeRef::Midi(id) => engine.unfinish_miditake(mididevice_id, id)
+			};
+			result.map_err(|_| Status::Conflict)?;
+			let take = &mut chain.takes[index];
+			take.duration = None;
+			updates.push(make_update_take(take, synthid, chain.id));
+			Ok(Some(Operation::Finish(takeid, length)))
+		}
+		Operation::SetMuted(takeid, muted, muted_scheduled) => {
+			let (synthid, mididevice_id, chain, index) = find_take(synths, takeid).ok_or(Status::Conflict)?;
+			let audiodevice_id = chain.engine_audiodevice_id;
+			let take = &mut chain.takes[index];
+			let previous = Operation::SetMuted(takeid, take.muted, take.muted_scheduled);
+			// a scheduled mute toggles the take's mute state at the next loop boundary
+			let scheduled_unmute = if muted_scheduled { Some(muted) } else { None };
+			let result = match take.engine_take_id {
+				EngineTakeRef::Audio(id) => engine.set_audiotake_unmuted(audiodevice_id, id, !muted)
+					.and_then(|_| engine.schedule_audiotake_unmuted(audiodevice_id, id, scheduled_unmute)),
+				EngineTakeRef::Midi(id) => engine.set_miditake_unmuted(mididevice_id, id, !muted)
+					.and_then(|_| engine.schedule_miditake_unmuted(mididevice_id, id, scheduled_unmute))
+			};
+			result.map_err(|_| Status::InternalServerError)?;
+			take.muted = muted;
+			take.muted_scheduled = muted_scheduled;
+			updates.push(make_update_take(take, synthid, chain.id));
+			Ok(Some(previous))
+		}
+		Operation::Rename(takeid, name) => {
+			let (synthid, _, chain, index) = find_take(synths, takeid).ok_or(Status::Conflict)?;
+			let chainid = chain.id;
+			let take = &mut chain.takes[index];
+			let previous = std::mem::replace(&mut take.name, name);
+			updates.push(make_update_take(take, synthid, chainid));
+			Ok(Some(Operation::Rename(takeid, previous)))
+		}
+	}
+}
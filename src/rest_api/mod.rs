@@ -6,15 +6,20 @@ mod data;
 mod get;
 mod patch;
 mod post;
+mod delete;
+mod session;
+mod history;
 
 use get::*;
 use patch::*;
 use post::*;
+use delete::*;
+use session::*;
 use updates::*;
 use gui_state::*;
 
 
-use crate::engine::{Event, FrontendTrait};
+use crate::engine::{Event, FrontendTrait, MetronomeSettings};
 use async_std::sync::Mutex;
 use std::sync::Arc;
 use crate::id_generator::IdGenerator;
@@ -52,15 +57,29 @@ fn not_found(req: &rocket::Request) -> String {
 
 pub async fn launch_server(engine: Box<dyn FrontendTrait>, event_channel_: realtime_send_queue::Consumer<Event>) {
 	let sample_rate = engine.sample_rate();
+	let (fade_length, crossfade_length) = engine.audiotake_fade_lengths();
 	let update_list = Arc::new(UpdateList::new());
 	let state = Arc::new( GuiState {
 		update_list: update_list.clone(),
 		mutex: Mutex::new( GuiMutexedState {
 			engine,
 			synths: vec![],
+			mixer: Mixer {
+				master_gain: 1.0,
+				monitor_gain: 1.0,
+				metronome_gain: 1.0,
+				fade_length: fade_length as f32 / sample_rate as f32,
+				crossfade_length: crossfade_length as f32 / sample_rate as f32
+			},
+			midiclock: MidiClock { enabled: true, offset: 0 },
+			metronome: MetronomeSettings::default().into(),
 			take_id: IdGenerator::new(),
 			chain_id: IdGenerator::new(),
-			synth_id: IdGenerator::new()
+			synth_id: IdGenerator::new(),
+			history: history::History::new(),
+			tap_tempo: util::TapTempo::new(),
+			free_running: None,
+			finish_rounding: FinishRounding::Lenient
 		})
 	} );
 
@@ -74,14 +93,16 @@ pub async fn launch_server(engine: Box<dyn FrontendTrait>, event_channel_: realt
 				{
 					println!("\n\n\n############# audio state {:?}\n\n\n", new_state);
 					let mut guard = state2.mutex.lock().await;
+					let mut finished_length = None;
 					if let Some((synthid, chainid, mut take)) = guard.find_audiotake_by_engine_id(dev_id, take_id) {
 						use crate::engine::RecordState;
 						if new_state == RecordState::Finished {
 							take.playing_since = Some(timestamp as f64 / sample_rate as f64);
-							take.duration = match take.state {
-								RecordingState::Recording(started_recording_at) => Some((timestamp - started_recording_at) as f64 / sample_rate as f64),
+							finished_length = match take.state {
+								RecordingState::Recording(started_recording_at) => Some(timestamp - started_recording_at),
 								_ => unimplemented!()
 							};
+							take.duration = finished_length.map(|l| l as f64 / sample_rate as f64);
 						}
 						take.state =
 							match new_state {
@@ -92,21 +113,28 @@ pub async fn launch_server(engine: Box<dyn FrontendTrait>, event_channel_: realt
 						state2.update_list.push( make_update_take(&take, synthid, chainid) ).await;
 					}
 					else {
-						panic!();
+						let retired = guard.engine.devices().get(&dev_id).map_or(false, |dev| dev.retired_takes().contains_key(&take_id));
+						println!("ignoring state change to {:?} of unknown (probably deleted) audio take {} (retired: {})", new_state, take_id, retired);
+					}
+					// the take might have been unfinished too late
+					if let Some(length) = finished_length {
+						guard.engine.audiotake_finished(dev_id, take_id, length);
 					}
 				}
 				Event::MidiTakeStateChanged(mididev_id, take_id, new_state, timestamp) =>
 				{
 					println!("\n\n\n############# midi state {:?}\n\n\n", new_state);
 					let mut guard = state2.mutex.lock().await;
+					let mut finished_length = None;
 					if let Some((synthid, chainid, mut take)) = guard.find_miditake_by_engine_id(mididev_id, take_id) {
 						use crate::engine::RecordState;
 						if new_state == RecordState::Finished {
 							take.playing_since = Some(timestamp as f64 / sample_rate as f64);
-							take.duration = match take.state {
-								RecordingState::Recording(started_recording_at) => Some((timestamp - started_recording_at) as f64 / sample_rate as f64),
+							finished_length = match take.state {
+								RecordingState::Recording(started_recording_at) => Some(timestamp - started_recording_at),
 								_ => unimplemented!()
 							};
+							take.duration = finished_length.map(|l| l as f64 / sample_rate as f64);
 						}
 						take.state =
 							match new_state {
@@ -117,19 +145,83 @@ pub async fn launch_server(engine: Box<dyn FrontendTrait>, event_channel_: realt
 						state2.update_list.push( make_update_take(&take, synthid, chainid) ).await;
 					}
 					else {
-						panic!();
+						let retired = guard.engine.mididevices().get(&mididev_id).map_or(false, |dev| dev.retired_takes().contains_key(&take_id));
+						println!("ignoring state change to {:?} of unknown (probably deleted) MIDI take {} (retired: {})", new_state, take_id, retired);
+					}
+					// the take might have been unfinished too late
+					if let Some(length) = finished_length {
+						guard.engine.miditake_finished(mididev_id, take_id, length);
+					}
+				}
+				Event::AudioTakeMuteChanged(dev_id, take_id, unmuted) =>
+				{
+					let mut guard = state2.mutex.lock().await;
+					guard.engine.audiotake_mute_changed(dev_id, take_id, unmuted);
+					if let Some((synthid, chainid, take)) = guard.find_audiotake_by_engine_id(dev_id, take_id) {
+						take.muted = !unmuted;
+						take.muted_scheduled = false;
+						state2.update_list.push( make_update_take(&take, synthid, chainid) ).await;
+					}
+					else {
+						let retired = guard.engine.devices().get(&dev_id).map_or(false, |dev| dev.retired_takes().contains_key(&take_id));
+						println!("ignoring mute change (unmuted: {}) of unknown (probably deleted) audio take {} (retired: {})", unmuted, take_id, retired);
+					}
+				}
+				Event::MidiTakeMuteChanged(mididev_id, take_id, unmuted) =>
+				{
+					let mut guard = state2.mutex.lock().await;
+					guard.engine.miditake_mute_changed(mididev_id, take_id, unmuted);
+					if let Some((synthid, chainid, take)) = guard.find_miditake_by_engine_id(mididev_id, take_id) {
+						take.muted = !unmuted;
+						take.muted_scheduled = false;
+						state2.update_list.push( make_update_take(&take, synthid, chainid) ).await;
+					}
+					else {
+						let retired = guard.engine.mididevices().get(&mididev_id).map_or(false, |dev| dev.retired_takes().contains_key(&take_id));
+						println!("ignoring mute change (unmuted: {}) of unknown (probably deleted) MIDI take {} (retired: {})", unmuted, take_id, retired);
 					}
 				}
 				Event::Timestamp(song_position, transport_position) =>
 				{
-					state2.update_list.push(UpdateRoot {
-						synths: None,
-						song: Some(UpdateSong {
-							song_position: Some(song_position as f32 / sample_rate as f32),
-							transport_position: Some(transport_position as f32 / sample_rate as f32),
-							loop_length: None,
-						})
-					}).await;
+					state2.update_list.push(make_update_song(UpdateSong {
+						song_position: Some(song_position as f32 / sample_rate as f32),
+						transport_position: Some(transport_position as f32 / sample_rate as f32),
+						..Default::default()
+					})).await;
+				}
+				Event::TransportChanged(playing) =>
+				{
+					state2.update_list.push(make_update_song(UpdateSong {
+						playing: Some(playing),
+						..Default::default()
+					})).await;
+				}
+				Event::SuggestedLoopLength(loop_length) =>
+				{
+					let mut guard_ = state2.mutex.lock().await;
+					let guard = &mut *guard_;
+					// adapting the loop length would rescale the takes and forget the removed ones that
+					// could be restored, so this is left to the user unless there is nothing to lose
+					let has_takes = guard.synths.iter().flat_map(|s| s.chains.iter()).any(|c| !c.takes.is_empty());
+					if !has_takes && guard.history.is_empty() {
+						let beats = guard.engine.n_beats();
+						if let Err(status) = set_loop_length_(&state2, guard, loop_length, beats).await {
+							println!("could not adapt the loop length to the external tempo: {}", status);
+						}
+					}
+					else {
+						let sample_rate = guard.engine.sample_rate() as f64;
+						state2.update_list.push(make_update_song(UpdateSong {
+							suggested_loop_length: Some((loop_length as f64 / sample_rate) as f32),
+							..Default::default()
+						})).await;
+					}
+				}
+				Event::TakeNotFound(take_id, operation) =>
+				{
+					// the frontend's and the audio thread's takes have diverged; the take keeps playing
+					// or stays silent until it is deleted
+					println!("the audio thread could not find take {} ({:?})", take_id, operation);
 				}
 				Event::Kill =>
 				{
@@ -143,14 +235,20 @@ pub async fn launch_server(engine: Box<dyn FrontendTrait>, event_channel_: realt
 		.manage(state)
 		.mount("/api", routes![
 			cors::options,
-			song_get, song_patch,
+			song_get, song_patch, post_song_tap,
+			mixer_get, mixer_patch,
+			midiclock_get, midiclock_patch,
+			metronome_get, metronome_patch, post_metronome_accent_sample, post_metronome_click_sample, delete_metronome_accent_sample, delete_metronome_click_sample,
 			get_updates,
 			synths_get, synths_get_one,
 			chains_get, chains_get_one,
-			takes_get, takes_get_one,
-			patch_synths, patch_synth, post_synth,
-			patch_chains, patch_chain, post_chain,
-			patch_takes, patch_take, post_take, post_take_finish_recording, post_restart_transport
+			takes_get, takes_get_one, takes_get_wav, takes_get_smf,
+			patch_synths, patch_synth, post_synth, post_import_midi,
+			patch_chains, patch_chain, post_chain, post_import_wav,
+			patch_takes, patch_take, post_take, post_take_finish_recording, post_take_cancel, post_restart_transport,
+			delete_synth, delete_chain, delete_take,
+			post_session_save, post_session_load,
+			post_undo, post_redo
 		])
 		.register(catchers![not_found])
 		.attach(cors::CORS())
@@ -2,47 +2,364 @@ use rocket_contrib::json::Json;
 use super::gui_state::*;
 use rocket::State;
 use rocket::http::Status;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use super::updates::*;
-use crate::engine::FrontendTrait;
+use super::history::Operation;
+use super::util::{tempo, loop_length_for_tempo, TEMPO_RANGE};
+use crate::engine::{FrontendTrait, JackTransportMode, MetronomeSound};
+use std::sync::Arc;
 
 #[derive(Deserialize,Clone)]
 pub struct SongPatch {
+	/// In seconds. Requires `beats`, and the resulting tempo must be between 20 and 400 bpm.
 	loop_length: Option<f32>,
-	beats: Option<u32>
+	beats: Option<u32>,
+	/// Between 20 and 400. Sets the loop length for `beats` beats, or the current number of
+	/// beats if not given. Cannot be combined with `loop_length`.
+	bpm: Option<f64>,
+	/// `true` starts on beat one, unless `resume` is set or takes are being recorded.
+	playing: Option<bool>,
+	/// Only valid together with `"playing": false`. Moves the song position back to the loop start.
+	rewind: Option<bool>,
+	/// Only valid together with `"playing": true`. Continues from the current song position
+	/// instead of starting on beat one.
+	resume: Option<bool>,
+	/// Id of the synth whose MIDI clock the song follows, or `null` to stop following.
+	#[serde(default, deserialize_with = "deserialize_some")]
+	clock_source: Option<Option<u32>>,
+	/// Cannot be `"follow"` while there is a MIDI clock source.
+	jack_transport: Option<JackTransport>,
+	/// Lets the first take define the loop length, or `null` to cancel. Only possible while
+	/// there are no takes, no MIDI clock source and the JACK transport is not followed.
+	#[serde(default, deserialize_with = "deserialize_some")]
+	free_running: Option<Option<FreeRunning>>,
+	/// Default rounding of takes whose recording is finished
+	finish_rounding: Option<FinishRounding>
+}
+
+/// Distinguishes fields that are `null` from missing ones.
+fn deserialize_some<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+	T::deserialize(deserializer).map(Some)
 }
 
 #[patch("/song", data="<patch>")]
 pub async fn song_patch(state: State<'_, std::sync::Arc<GuiState>>, patch: Json<SongPatch>) -> Result<(), Status> {
-	let mut guard = state.mutex.lock().await;
-	let e = guard.engine.as_mut();
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+
+	let rewind = patch.rewind.unwrap_or(false);
+	let resume = patch.resume.unwrap_or(false);
+	if (rewind && patch.playing != Some(false)) || (resume && patch.playing != Some(true)) {
+		return Err(Status::UnprocessableEntity);
+	}
+	// takes that are being recorded would lose their alignment with the song
+	let recording = guard.synths.iter().flat_map(|s| s.chains.iter()).flat_map(|c| c.takes.iter()).any(|t| matches!(t.state, RecordingState::Recording(_)));
+	if rewind && recording {
+		return Err(Status::Conflict);
+	}
+	// starting a stopped transport rewinds it as well, unless that would break recordings
+	let rewind = rewind || (patch.playing == Some(true) && !resume && !recording && !guard.engine.playing());
+
+	let clock_source = match patch.clock_source {
+		Some(Some(synthid)) => Some(Some(guard.synths.iter().find(|s| s.id == synthid).ok_or(Status::UnprocessableEntity)?.engine_mididevice_id)),
+		Some(None) => Some(None),
+		None => None
+	};
+	let jack_transport = patch.jack_transport.map(JackTransportMode::from);
+	if clock_source.unwrap_or(guard.engine.clock_source()).is_some() && jack_transport.unwrap_or(guard.engine.jack_transport_mode()) == JackTransportMode::Follow {
+		return Err(Status::Conflict);
+	}
+	if let Some(Some(free_running)) = &patch.free_running {
+		let has_takes = guard.synths.iter().flat_map(|s| s.chains.iter()).any(|c| !c.takes.is_empty());
+		if has_takes || clock_source.unwrap_or(guard.engine.clock_source()).is_some() || jack_transport.unwrap_or(guard.engine.jack_transport_mode()) == JackTransportMode::Follow {
+			return Err(Status::Conflict);
+		}
+		if free_running.beats == Some(0) || free_running.bpm_range.map_or(false, |(min, max)| !(TEMPO_RANGE.contains(&min) && TEMPO_RANGE.contains(&max) && min.ceil() <= max.floor())) {
+			return Err(Status::UnprocessableEntity);
+		}
+	}
 
-	if let Some(loop_length) = patch.loop_length {
-		if let Some(beats) = patch.beats {
-			e.set_loop_length( (e.sample_rate() as f32 * loop_length) as u32, beats)
-				.map_err(|_| Status::UnprocessableEntity)?;
-			state.update_list.push( UpdateRoot {
-				synths: None,
-				song: Some(UpdateSong {
-					song_position: None,
-					transport_position: None,
-					loop_length: Some(loop_length) // FIXME use the looplength retrieved from the engine, after fixing the problems there.
-				})
-			}).await;
+	let sample_rate = guard.engine.sample_rate();
+	let new_loop_length = match (patch.loop_length, patch.bpm) {
+		(Some(_), Some(_)) => return Err(Status::UnprocessableEntity),
+		(Some(loop_length), None) => {
+			let beats = patch.beats.ok_or(Status::UnprocessableEntity)?;
+			let loop_length = (sample_rate as f32 * loop_length) as u32;
+			if !TEMPO_RANGE.contains(&tempo(loop_length, beats, sample_rate)) {
+				return Err(Status::UnprocessableEntity);
+			}
+			Some((loop_length, beats))
 		}
+		(None, Some(bpm)) => {
+			let beats = patch.beats.unwrap_or_else(|| guard.engine.n_beats());
+			if !TEMPO_RANGE.contains(&bpm) {
+				return Err(Status::UnprocessableEntity);
+			}
+			Some((loop_length_for_tempo(bpm, beats, sample_rate).ok_or(Status::UnprocessableEntity)?, beats))
+		}
+		(None, None) => None
+	};
+
+	// the request is valid, so the changes are applied from here on. The loop length goes first
+	// since it may still be refused, which leaves everything else unchanged.
+	if let Some((loop_length, beats)) = new_loop_length {
+		set_loop_length_(state.inner(), guard, loop_length, beats).await?;
+	}
+
+	let e = guard.engine.as_mut();
+
+	// the MIDI clock source must be unset before following the JACK transport and vice versa
+	if let Some(mode) = jack_transport.filter(|mode| *mode != JackTransportMode::Follow) {
+		e.set_jack_transport_mode(mode).map_err(|_| Status::InternalServerError)?;
+	}
+	if let Some(mididevice_id) = clock_source {
+		e.set_clock_source(mididevice_id).map_err(|_| Status::InternalServerError)?;
+	}
+	if let Some(mode) = jack_transport.filter(|mode| *mode == JackTransportMode::Follow) {
+		e.set_jack_transport_mode(mode).map_err(|_| Status::InternalServerError)?;
+	}
+	if let Some(free_running) = &patch.free_running {
+		e.set_free_running(free_running.is_some()).map_err(|_| Status::Conflict)?;
+		guard.free_running = free_running.clone();
+	}
+	if let Some(finish_rounding) = patch.finish_rounding {
+		guard.finish_rounding = finish_rounding;
+	}
+	if clock_source.is_some() || jack_transport.is_some() || patch.free_running.is_some() || patch.finish_rounding.is_some() {
+		state.update_list.push( make_update_song(UpdateSong {
+			free_running: patch.free_running.clone(),
+			clock_source: patch.clock_source,
+			jack_transport: patch.jack_transport,
+			finish_rounding: patch.finish_rounding,
+			..Default::default()
+		})).await;
+	}
+
+	if let Some(playing) = patch.playing {
+		let result = match (playing, resume) {
+			(true, false) => e.start_transport(),
+			(true, true) => e.resume_transport(),
+			(false, _) => e.stop_transport(rewind)
+		};
+		result.map_err(|_| Status::InternalServerError)?;
+		state.update_list.push( make_update_song(UpdateSong {
+			song_position: if rewind { Some(0.0) } else { None },
+			playing: Some(playing),
+			..Default::default()
+		})).await;
+	}
+
+	Ok(())
+}
+
+/// Changes the loop length and rescales all takes, which must be finished. The takes are
+/// rescaled on a worker thread without locking the GUI state; the engine reports a pending
+/// loop length change until they are swapped in.
+pub async fn set_loop_length_(state: &Arc<GuiState>, guard: &mut GuiMutexedState, loop_length: u32, beats: u32) -> Result<(), Status> {
+	if loop_length == 0 || beats == 0 {
+		return Err(Status::UnprocessableEntity);
+	}
+	// takes that are still being recorded cannot be rescaled
+	if guard.synths.iter().flat_map(|s| s.chains.iter()).flat_map(|c| c.takes.iter()).any(|t| t.state != RecordingState::Finished) {
+		return Err(Status::UnprocessableEntity);
+	}
+
+	let e = guard.engine.as_mut();
+	if !e.can_change_loop_length() {
+		return Err(Status::UnprocessableEntity);
+	}
+	// retired takes cannot be rescaled, so they are forgotten
+	guard.history.forget_removed_takes(e);
+	let rescaling = e.prepare_loop_length_change(loop_length, beats)
+		.map_err(|_| Status::InternalServerError)?;
+	push_loop_length(&state.update_list, e.sample_rate(), loop_length, beats).await;
+
+	if let Some(rescaling) = rescaling {
+		let state = state.clone();
+		tokio::task::spawn(async move {
+			let change = tokio::task::spawn_blocking(move || rescaling.rescale()).await;
+			let mut guard_ = state.mutex.lock().await;
+			let guard = &mut *guard_;
+			let e = guard.engine.as_mut();
+			let result = match change {
+				Ok(change) => e.finish_loop_length_change(change),
+				Err(_) => {
+					e.cancel_loop_length_change();
+					Err(())
+				}
+			};
+			if result.is_err() {
+				println!("failed to rescale the takes to a loop length of {}", loop_length);
+				push_loop_length(&state.update_list, e.sample_rate(), e.loop_length(), e.n_beats()).await;
+				return;
+			}
+
+			// existing takes have been rescaled to the new loop length
+			let sample_rate = e.sample_rate() as f64;
+			for synth in guard.synths.iter_mut() {
+				for chain in synth.chains.iter_mut() {
+					for take in chain.takes.iter_mut() {
+						let length = match take.engine_take_id {
+							EngineTakeRef::Audio(id) => e.devices()[&chain.engine_audiodevice_id].takes()[&id].length,
+							EngineTakeRef::Midi(id) => e.mididevices()[&synth.engine_mididevice_id].takes()[&id].length
+						};
+						take.duration = length.map(|l| l as f64 / sample_rate);
+						state.update_list.push(make_update_take(take, synth.id, chain.id)).await;
+					}
+				}
+			}
+		});
+	}
+	Ok(())
+}
+
+async fn push_loop_length(update_list: &UpdateList, sample_rate: u32, loop_length: u32, beats: u32) {
+	update_list.push( make_update_song(UpdateSong {
+		loop_length: Some((loop_length as f64 / sample_rate as f64) as f32),
+		bpm: Some(tempo(loop_length, beats, sample_rate) as f32),
+		..Default::default()
+	})).await;
+}
+
+
+#[derive(Deserialize,Clone)]
+pub struct MixerPatch {
+	master_gain: Option<f32>,
+	monitor_gain: Option<f32>,
+	metronome_gain: Option<f32>,
+	/// In seconds
+	fade_length: Option<f32>,
+	/// In seconds
+	crossfade_length: Option<f32>
+}
+
+#[patch("/mixer", data="<patch>")]
+pub async fn mixer_patch(state: State<'_, std::sync::Arc<GuiState>>, patch: Json<MixerPatch>) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+
+	let master_gain = patch.master_gain.unwrap_or(guard.mixer.master_gain);
+	let monitor_gain = patch.monitor_gain.unwrap_or(guard.mixer.monitor_gain);
+	let metronome_gain = patch.metronome_gain.unwrap_or(guard.mixer.metronome_gain);
+	let fade_length = patch.fade_length.unwrap_or(guard.mixer.fade_length);
+	let crossfade_length = patch.crossfade_length.unwrap_or(guard.mixer.crossfade_length);
+	if ![fade_length, crossfade_length].iter().all(|length| length.is_finite() && (0.0..=1.0).contains(length)) {
+		return Err(Status::UnprocessableEntity);
 	}
+	guard.engine.set_bus_gains(master_gain, monitor_gain, metronome_gain)
+		.map_err(|_| Status::UnprocessableEntity)?;
+	let sample_rate = guard.engine.sample_rate() as f32;
+	guard.engine.set_audiotake_fade_lengths((fade_length * sample_rate) as u32, (crossfade_length * sample_rate) as u32);
+	guard.mixer = Mixer { master_gain, monitor_gain, metronome_gain, fade_length, crossfade_length };
+
+	state.update_list.push( UpdateRoot {
+		synths: None,
+		song: None,
+		mixer: Some(guard.mixer.clone()),
+		midiclock: None,
+		metronome: None
+	}).await;
+	Ok(())
+}
+
+#[derive(Deserialize,Clone)]
+pub struct MidiClockPatch {
+	enabled: Option<bool>,
+	offset: Option<i32>
+}
+
+#[patch("/midiclock", data="<patch>")]
+pub async fn midiclock_patch(state: State<'_, std::sync::Arc<GuiState>>, patch: Json<MidiClockPatch>) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+
+	let enabled = patch.enabled.unwrap_or(guard.midiclock.enabled);
+	let offset = patch.offset.unwrap_or(guard.midiclock.offset);
+	guard.engine.set_midiclock(enabled, offset)
+		.map_err(|_| Status::UnprocessableEntity)?;
+	guard.midiclock = MidiClock { enabled, offset };
 
+	state.update_list.push( UpdateRoot {
+		synths: None,
+		song: None,
+		mixer: None,
+		midiclock: Some(guard.midiclock.clone()),
+		metronome: None
+	}).await;
 	Ok(())
 }
 
+#[derive(Deserialize,Clone)]
+pub struct MetronomePatch {
+	muted: Option<bool>,
+	mode: Option<MetronomeClickMode>,
+	volume: Option<f32>,
+	accent: Option<f32>,
+	pitch: Option<f32>,
+	subdivision: Option<u32>,
+	accents: Option<Vec<u32>>
+}
+
+#[patch("/metronome", data="<patch>")]
+pub async fn metronome_patch(state: State<'_, std::sync::Arc<GuiState>>, patch: Json<MetronomePatch>) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+
+	let metronome = Metronome {
+		muted: patch.muted.unwrap_or(guard.metronome.muted),
+		mode: patch.mode.unwrap_or(guard.metronome.mode),
+		volume: patch.volume.unwrap_or(guard.metronome.volume),
+		accent: patch.accent.unwrap_or(guard.metronome.accent),
+		pitch: patch.pitch.unwrap_or(guard.metronome.pitch),
+		subdivision: patch.subdivision.unwrap_or(guard.metronome.subdivision),
+		accents: patch.accents.clone().unwrap_or_else(|| guard.metronome.accents.clone()),
+		..guard.metronome.clone()
+	};
+	if metronome.accents.iter().any(|beat| *beat >= 32) {
+		return Err(Status::UnprocessableEntity);
+	}
+	guard.engine.set_metronome((&metronome).into())
+		.map_err(|_| Status::UnprocessableEntity)?;
+	guard.metronome = metronome;
 
+	state.update_list.push( UpdateRoot {
+		synths: None,
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: Some(guard.metronome.clone())
+	}).await;
+	Ok(())
+}
 
+/// Replaces the accent or click sample of the metronome, or restores the synthesized click
+/// if `sample` is None.
+pub async fn set_metronome_sample_(guard: &mut GuiMutexedState, update_list: &UpdateList, which: MetronomeSound, sample: Option<Vec<f32>>) -> Result<(), Status> {
+	let loaded = sample.is_some();
+	guard.engine.set_metronome_sample(which, sample)
+		.map_err(|_| Status::InternalServerError)?;
+	match which {
+		MetronomeSound::Accent => guard.metronome.accent_sample = loaded,
+		MetronomeSound::Click => guard.metronome.click_sample = loaded
+	}
+
+	update_list.push( UpdateRoot {
+		synths: None,
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: Some(guard.metronome.clone())
+	}).await;
+	Ok(())
+}
 
 #[derive(Deserialize,Clone)]
 pub struct SynthPatch {
 	id: u32,
 	name: Option<String>,
-	chains: Option<Vec<ChainPatch>>
+	chains: Option<Vec<ChainPatch>>,
+	clock: Option<bool>,
+	clock_offset: Option<i32>
 }
 
 #[derive(Deserialize,Clone)]
@@ -50,7 +367,8 @@ pub struct ChainPatch {
 	id: u32,
 	name: Option<String>,
 	takes: Option<Vec<TakePatch>>,
-	echo: Option<bool>
+	echo: Option<bool>,
+	gain: Option<f32>
 }
 
 #[derive(Deserialize,Clone)]
@@ -59,6 +377,11 @@ pub struct TakePatch {
 	name: Option<String>,
 	muted: Option<bool>,
 	muted_scheduled: Option<bool>,
+	gain: Option<f32>,
+	pan: Option<f32>,
+	cued: Option<bool>,
+	overdub: Option<bool>,
+	overdub_feedback: Option<f32>,
 	associated_midi_takes: Option<Vec<u32>>,
 }
 
@@ -67,8 +390,10 @@ pub struct TakePatch {
 pub async fn patch_synths(state: State<'_, std::sync::Arc<GuiState>>, patch: Json<Vec<SynthPatch>>) -> Result<(), Status> {
 	let mut guard_ = state.mutex.lock().await;
 	let guard = &mut *guard_;
-	patch_synths_(guard.engine.as_mut(), &mut guard.synths, &*patch, true)?;
-	patch_synths_(guard.engine.as_mut(), &mut guard.synths, &*patch, false).unwrap();
+	let mut inverse = Vec::new();
+	patch_synths_(guard.engine.as_mut(), &mut guard.synths, &*patch, true, &mut inverse)?;
+	patch_synths_(guard.engine.as_mut(), &mut guard.synths, &*patch, false, &mut inverse).unwrap();
+	guard.history.push(guard.engine.as_mut(), inverse);
 	for p in patch.iter() {
 		state.update_list.push(make_update_synth(guard.synths.iter().find(|s| s.id == p.id).unwrap())).await;
 	}
@@ -82,8 +407,10 @@ pub async fn patch_synth(state: State<'_, std::sync::Arc<GuiState>>, id: u32, pa
 	}
 	let mut guard_ = state.mutex.lock().await;
 	let guard = &mut *guard_;
-	patch_synth_(guard.engine.as_mut(), &mut guard.synths, &*patch, true)?;
-	patch_synth_(guard.engine.as_mut(), &mut guard.synths, &*patch, false).unwrap();
+	let mut inverse = Vec::new();
+	patch_synth_(guard.engine.as_mut(), &mut guard.synths, &*patch, true, &mut inverse)?;
+	patch_synth_(guard.engine.as_mut(), &mut guard.synths, &*patch, false, &mut inverse).unwrap();
+	guard.history.push(guard.engine.as_mut(), inverse);
 	state.update_list.push(make_update_synth(guard.synths.iter().find(|s| s.id == patch.id).unwrap())).await;
 	Ok(())
 }
@@ -93,8 +420,10 @@ pub async fn patch_chains(state: State<'_, std::sync::Arc<GuiState>>, synthid: u
 	let mut guard_ = state.mutex.lock().await;
 	let guard = &mut *guard_;
 	if let Some(synth) = guard.synths.iter_mut().find(|s| s.id == synthid) {
-		patch_chains_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, true)?;
-		patch_chains_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, false).unwrap();
+		let mut inverse = Vec::new();
+		patch_chains_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, true, &mut inverse)?;
+		patch_chains_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, false, &mut inverse).unwrap();
+		guard.history.push(guard.engine.as_mut(), inverse);
 		for p in patch.iter() {
 			state.update_list.push(make_update_chain(synth.chains.iter().find(|s| s.id == p.id).unwrap(), synthid)).await;
 		}
@@ -111,8 +440,10 @@ pub async fn patch_chain(state: State<'_, std::sync::Arc<GuiState>>, synthid: u3
 		if chainid != patch.id {
 			return Err(Status::UnprocessableEntity);
 		}
-		patch_chain_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, true)?;
-		patch_chain_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, false).unwrap();
+		let mut inverse = Vec::new();
+		patch_chain_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, true, &mut inverse)?;
+		patch_chain_(guard.engine.as_mut(), synth.engine_mididevice_id, &mut synth.chains, &*patch, false, &mut inverse).unwrap();
+		guard.history.push(guard.engine.as_mut(), inverse);
 		state.update_list.push(make_update_chain(synth.chains.iter().find(|s| s.id == patch.id).unwrap(), synthid)).await;
 		return Ok(());
 	}
@@ -125,12 +456,15 @@ pub async fn patch_takes(state: State<'_, std::sync::Arc<GuiState>>, synthid: u3
 	let guard = &mut *guard_;
 	if let Some(synth) = guard.synths.iter_mut().find(|s| s.id == synthid) {
 		if let Some(chain) = synth.chains.iter_mut().find(|c| c.id == chainid) {
-			patch_takes_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, true)?;
-			patch_takes_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, false).unwrap();
+			let mut inverse = Vec::new();
+			patch_takes_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, true, &mut inverse)?;
+			// the engine may still refuse a change, but what has been applied up to then is kept
+			let result = patch_takes_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, false, &mut inverse);
+			guard.history.push(guard.engine.as_mut(), inverse);
 			for p in patch.iter() {
 				state.update_list.push(make_update_take(chain.takes.iter().find(|s| s.id == p.id).unwrap(), synthid, chainid)).await;
 			}
-			return Ok(());
+			return result;
 		}
 	}
 	Err(Status::NotFound)
@@ -145,31 +479,41 @@ pub async fn patch_take(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32
 			if takeid != patch.id {
 				return Err(Status::UnprocessableEntity);
 			}
-			patch_take_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, true)?;
-			patch_take_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, false).unwrap();
+			let mut inverse = Vec::new();
+			patch_take_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, true, &mut inverse)?;
+			// the engine may still refuse a change, but what has been applied up to then is kept
+			let result = patch_take_(guard.engine.as_mut(), synth.engine_mididevice_id, chain.engine_audiodevice_id, &mut chain.takes, &*patch, false, &mut inverse);
+			guard.history.push(guard.engine.as_mut(), inverse);
 			state.update_list.push(make_update_take(chain.takes.iter().find(|s| s.id == patch.id).unwrap(), synthid, chainid)).await;
-			return Ok(());
+			return result;
 		}
 	}
 	Err(Status::NotFound)
 }
 
-fn patch_synths_(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, patch: &Vec<SynthPatch>, check: bool) -> Result<(), Status> {
+fn patch_synths_(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, patch: &Vec<SynthPatch>, check: bool, inverse: &mut Vec<Operation>) -> Result<(), Status> {
 	for synth in patch.iter() {
-		patch_synth_(engine, synths, synth, check)?;
+		patch_synth_(engine, synths, synth, check, inverse)?;
 	}
 	Ok(())
 }
 
-fn patch_synth_(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, patch: &SynthPatch, check: bool) -> Result<(), Status> {
+fn patch_synth_(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, patch: &SynthPatch, check: bool, inverse: &mut Vec<Operation>) -> Result<(), Status> {
 	if let Some(synth_to_patch) = synths.iter_mut().find(|s| s.id == patch.id) {
 		if let Some(chains) = &patch.chains {
-			patch_chains_(engine, synth_to_patch.engine_mididevice_id, &mut synth_to_patch.chains, chains, check)?;
+			patch_chains_(engine, synth_to_patch.engine_mididevice_id, &mut synth_to_patch.chains, chains, check, inverse)?;
 		}
 		if !check {
 			if let Some(name) = &patch.name {
 				synth_to_patch.name = name.clone();
 			}
+			if patch.clock.is_some() || patch.clock_offset.is_some() {
+				synth_to_patch.clock = patch.clock.unwrap_or(synth_to_patch.clock);
+				synth_to_patch.clock_offset = patch.clock_offset.unwrap_or(synth_to_patch.clock_offset);
+				let offset = if synth_to_patch.clock { Some(synth_to_patch.clock_offset) } else { None };
+				engine.set_mididevice_clock(synth_to_patch.engine_mididevice_id, offset)
+					.map_err(|_| Status::InternalServerError)?;
+			}
 		}
 
 		Ok(())
@@ -179,19 +523,26 @@ fn patch_synth_(engine: &mut dyn FrontendTrait, synths: &mut Vec<Synth>, patch:
 	}
 }
 
-fn patch_chains_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chains: &mut Vec<Chain>, patch: &Vec<ChainPatch>, check: bool) -> Result<(), Status> {
+fn patch_chains_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chains: &mut Vec<Chain>, patch: &Vec<ChainPatch>, check: bool, inverse: &mut Vec<Operation>) -> Result<(), Status> {
 	for chain in patch.iter() {
-		patch_chain_(engine, mididevice_id, chains, chain, check)?;
+		patch_chain_(engine, mididevice_id, chains, chain, check, inverse)?;
 	}
 	Ok(())
 }
 
-fn patch_chain_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chains: &mut Vec<Chain>, patch: &ChainPatch, check: bool) -> Result<(), Status> {
+fn patch_chain_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chains: &mut Vec<Chain>, patch: &ChainPatch, check: bool, inverse: &mut Vec<Operation>) -> Result<(), Status> {
 	if let Some(chain_to_patch) = chains.iter_mut().find(|s| s.id == patch.id) {
 		if let Some(takes) = &patch.takes {
-			patch_takes_(engine, mididevice_id, chain_to_patch.engine_audiodevice_id, &mut chain_to_patch.takes, takes, check)?;
+			patch_takes_(engine, mididevice_id, chain_to_patch.engine_audiodevice_id, &mut chain_to_patch.takes, takes, check, inverse)?;
 		}
-		if !check {
+		if check {
+			if let Some(gain) = patch.gain {
+				if !(gain.is_finite() && gain >= 0.0) {
+					return Err(Status::UnprocessableEntity);
+				}
+			}
+		}
+		else {
 			if let Some(name) = &patch.name {
 				chain_to_patch.name = name.clone();
 			}
@@ -200,6 +551,11 @@ fn patch_chain_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chains: &m
 				engine.set_audiodevice_echo(chain_to_patch.engine_audiodevice_id, echo)
 					.map_err(|_| Status::InternalServerError)?;
 			}
+			if let Some(gain) = patch.gain {
+				chain_to_patch.gain = gain;
+				engine.set_audiodevice_gain(chain_to_patch.engine_audiodevice_id, gain)
+					.map_err(|_| Status::InternalServerError)?;
+			}
 		}
 
 		Ok(())
@@ -209,19 +565,56 @@ fn patch_chain_(engine: &mut dyn FrontendTrait, mididevice_id: usize, chains: &m
 	}
 }
 
-fn patch_takes_(engine: &mut dyn FrontendTrait, mididevice_id: usize, audiodevice_id: usize, takes: &mut Vec<Take>, patch: &Vec<TakePatch>, check: bool) -> Result<(), Status> {
+fn patch_takes_(engine: &mut dyn FrontendTrait, mididevice_id: usize, audiodevice_id: usize, takes: &mut Vec<Take>, patch: &Vec<TakePatch>, check: bool, inverse: &mut Vec<Operation>) -> Result<(), Status> {
 	for take in patch.iter() {
-		patch_take_(engine, mididevice_id, audiodevice_id, takes, take, check)?;
+		patch_take_(engine, mididevice_id, audiodevice_id, takes, take, check, inverse)?;
 	}
 	Ok(())
 }
 
-fn patch_take_(engine: &mut dyn FrontendTrait, mididevice_id: usize, audiodevice_id: usize, takes: &mut Vec<Take>, patch: &TakePatch, check: bool) -> Result<(), Status> {
+fn patch_take_(engine: &mut dyn FrontendTrait, mididevice_id: usize, audiodevice_id: usize, takes: &mut Vec<Take>, patch: &TakePatch, check: bool, inverse: &mut Vec<Operation>) -> Result<(), Status> {
 	if let Some(take_to_patch) = takes.iter_mut().find(|s| s.id == patch.id) {
-		if !check {
+		if check {
+			if patch.gain.is_some() || patch.pan.is_some() {
+				// only audio takes have a gain
+				if take_to_patch.is_midi() {
+					return Err(Status::UnprocessableEntity);
+				}
+				let gain = patch.gain.unwrap_or(take_to_patch.gain);
+				let pan = patch.pan.unwrap_or(take_to_patch.pan);
+				if !(gain.is_finite() && gain >= 0.0 && (-1.0..=1.0).contains(&pan)) {
+					return Err(Status::UnprocessableEntity);
+				}
+			}
+			if patch.cued == Some(true) && take_to_patch.is_midi() {
+				// MIDI takes play through their synth, which has no monitor output
+				return Err(Status::UnprocessableEntity);
+			}
+			if patch.overdub.is_some() || patch.overdub_feedback.is_some() {
+				// only finished audio takes can be overdubbed
+				if take_to_patch.is_midi() || take_to_patch.state != RecordingState::Finished {
+					return Err(Status::UnprocessableEntity);
+				}
+				let feedback = patch.overdub_feedback.unwrap_or(take_to_patch.overdub_feedback);
+				if !(0.0..=1.0).contains(&feedback) {
+					return Err(Status::UnprocessableEntity);
+				}
+				// the rescaled copy of the take would lose what is overdubbed
+				if patch.overdub.unwrap_or(take_to_patch.overdub) && engine.loop_length_change_pending() {
+					return Err(Status::Conflict);
+				}
+			}
+		}
+		else {
 			if let Some(name) = &patch.name {
+				inverse.push(Operation::Rename(take_to_patch.id, take_to_patch.name.clone()));
 				take_to_patch.name = name.clone();
 			}
+			// the mute state the take has once the patch has been applied, which a scheduled mute toggles
+			let target_muted = patch.muted.unwrap_or(take_to_patch.muted);
+			if patch.muted.is_some() || patch.muted_scheduled.is_some() {
+				inverse.push(Operation::SetMuted(take_to_patch.id, take_to_patch.muted, take_to_patch.muted_scheduled));
+			}
 			if let Some(muted) = patch.muted {
 				println!("patching take {} ({}) muted {}", take_to_patch.id, take_to_patch.name, muted);
 				match take_to_patch.engine_take_id {
@@ -229,11 +622,42 @@ fn patch_take_(engine: &mut dyn FrontendTrait, mididevice_id: usize, audiodevice
 					EngineTakeRef::Midi(id) => { engine.set_miditake_unmuted(mididevice_id, id, !muted).map_err(|_| Status::InternalServerError)?; }
 				}
 				take_to_patch.muted = muted;
+				take_to_patch.muted_scheduled = false;
 			}
 			if let Some(muted_scheduled) = patch.muted_scheduled {
-				// TODO: schedule mute, communicate with the engine.
+				// a scheduled mute toggles the take's mute state at the next loop boundary
+				let scheduled_unmute = if muted_scheduled { Some(target_muted) } else { None };
+				match take_to_patch.engine_take_id {
+					EngineTakeRef::Audio(id) => { engine.schedule_audiotake_unmuted(audiodevice_id, id, scheduled_unmute).map_err(|_| Status::InternalServerError)?; }
+					EngineTakeRef::Midi(id) => { engine.schedule_miditake_unmuted(mididevice_id, id, scheduled_unmute).map_err(|_| Status::InternalServerError)?; }
+				}
 				take_to_patch.muted_scheduled = muted_scheduled;
 			}
+			if patch.gain.is_some() || patch.pan.is_some() {
+				let gain = patch.gain.unwrap_or(take_to_patch.gain);
+				let pan = patch.pan.unwrap_or(take_to_patch.pan);
+				if let EngineTakeRef::Audio(id) = take_to_patch.engine_take_id {
+					engine.set_audiotake_gain(audiodevice_id, id, gain, pan).map_err(|_| Status::InternalServerError)?;
+				}
+				take_to_patch.gain = gain;
+				take_to_patch.pan = pan;
+			}
+			if let Some(cued) = patch.cued {
+				if let EngineTakeRef::Audio(id) = take_to_patch.engine_take_id {
+					engine.set_audiotake_cued(audiodevice_id, id, cued).map_err(|_| Status::InternalServerError)?;
+				}
+				take_to_patch.cued = cued;
+			}
+			if patch.overdub.is_some() || patch.overdub_feedback.is_some() {
+				let overdub = patch.overdub.unwrap_or(take_to_patch.overdub);
+				let feedback = patch.overdub_feedback.unwrap_or(take_to_patch.overdub_feedback);
+				if let EngineTakeRef::Audio(id) = take_to_patch.engine_take_id {
+					engine.set_audiotake_overdub(audiodevice_id, id, if overdub { Some(feedback) } else { None })
+						.map_err(|_| Status::InternalServerError)?;
+				}
+				take_to_patch.overdub = overdub;
+				take_to_patch.overdub_feedback = feedback;
+			}
 		}
 
 		Ok(())
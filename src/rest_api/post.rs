@@ -2,10 +2,24 @@ use rocket_contrib::json::Json;
 use super::gui_state::*;
 use rocket::State;
 use rocket::http::Status;
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
 use super::updates::*;
-use super::util::gen_unique_name;
+use super::history::Operation;
+use super::patch::{set_metronome_sample_, set_loop_length_};
+use super::util::{gen_unique_name, round_take_length_with, tempo, loop_length_for_tempo, tapped_tempo_to_apply};
+use crate::wav::{read_wav, resample, resampling_ratio_is_sane};
+use crate::smf::read_smf;
+use crate::midi_message::MidiMessage;
+use crate::engine::MetronomeSound;
+use rocket::data::{Data, ToByteUnit};
+use std::convert::TryFrom;
 
+/// Largest WAV file that is accepted by `post_import_wav`
+const MAX_WAV_SIZE_MIB: usize = 256;
+/// Largest WAV file that is accepted by `post_metronome_sample`
+const MAX_METRONOME_SAMPLE_SIZE_MIB: usize = 4;
+/// Largest Standard MIDI File that is accepted by `post_import_midi`
+const MAX_SMF_SIZE_MIB: usize = 16;
 
 #[derive(Deserialize,Clone,PartialEq)]
 pub enum TakeType {
@@ -17,6 +31,8 @@ pub enum TakeType {
 pub struct TakePost {
 	name: Option<String>,
 	r#type: TakeType,
+	/// If given, the take records for this many loops and then finishes by itself
+	loops: Option<u32>,
 }
 
 #[derive(Deserialize,Clone)]
@@ -42,6 +58,8 @@ pub async fn post_synth(state: State<'_, std::sync::Arc<GuiState>>, data: Json<S
 			id,
 			chains: Vec::new(),
 			name,
+			clock: false,
+			clock_offset: 0,
 			engine_mididevice_id
 		};
 		state.update_list.push(make_update_synth(&new_synth)).await;
@@ -81,6 +99,7 @@ pub async fn post_chain(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32
 				name,
 				midi: true, // FIXME this should not be hard-coded,
 				echo: false,
+				gain: 1.0,
 				engine_audiodevice_id
 			};
 			state.update_list.push(make_update_chain(&new_chain, synthid)).await;
@@ -101,13 +120,24 @@ pub async fn post_take(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32,
 	let guard = &mut *guard_;
 	if let Some(synth) = guard.synths.iter_mut().find(|s| s.id == synthid) {
 		if let Some(chain) = synth.chains.iter_mut().find(|c| c.id == chainid) {
+			if guard.engine.loop_length_change_pending() {
+				return Err(Status::Conflict);
+			}
+			let length = match data.loops {
+				// while free-running, the loop length is not known yet
+				Some(_) if guard.free_running.is_some() => return Err(Status::Conflict),
+				Some(loops) => Some(guard.engine.loop_length().checked_mul(loops).filter(|l| *l > 0).ok_or(Status::UnprocessableEntity)?),
+				None => None
+			};
+			let duration = length.map(|l| l as f64 / guard.engine.sample_rate() as f64);
+
 			// FIXME this is racy! there should be an atomic function for adding multiple takes at once!
 			// FIXME and the unwrap... there is so much wrong with this.
 			let name = gen_unique_name(data.name.as_deref().unwrap_or("Take"), chain.takes.iter().map(|c|&c.name[..]));
 
 			// set up the MIDI take
 			let midi_id = guard.take_id.gen();
-			let engine_miditake_id = guard.engine.add_miditake(synth.engine_mididevice_id, true).unwrap();
+			let engine_miditake_id = guard.engine.add_miditake(synth.engine_mididevice_id, true, length).unwrap();
 
 			chain.takes.push( Take {
 				id: midi_id,
@@ -115,18 +145,24 @@ pub async fn post_take(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32,
 				name: name.clone(),
 				muted: false,
 				muted_scheduled: false,
+				gain: 1.0,
+				pan: 0.0,
+				cued: false,
+				overdub: false,
+				overdub_feedback: 1.0,
 				state: RecordingState::Waiting,
 				playing_since: None,
-				duration: None,
+				duration,
 				associated_midi_takes: Vec::new(),
 			});
 			state.update_list.push(make_update_take(chain.takes.last().unwrap(), synthid, chainid)).await;
+			let mut inverse = vec![Operation::Remove(midi_id)];
 
 			let result_take_id;
 			// set up the audio take, if requested.
 			if data.r#type == TakeType::Audio {
 				let audio_id = guard.take_id.gen();
-				let engine_audiotake_id = guard.engine.add_audiotake(chain.engine_audiodevice_id, false).unwrap();
+				let engine_audiotake_id = guard.engine.add_audiotake(chain.engine_audiodevice_id, false, length).unwrap();
 
 				let mut associated_midi_takes: Vec<u32> =
 					chain.takes.iter()
@@ -141,17 +177,24 @@ pub async fn post_take(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32,
 					name,
 					muted: true,
 					muted_scheduled: false,
+					gain: 1.0,
+					pan: 0.0,
+					cued: false,
+					overdub: false,
+					overdub_feedback: 1.0,
 					playing_since: None,
-					duration: None,
+					duration,
 					state: RecordingState::Waiting,
 					associated_midi_takes
 				});
 				state.update_list.push(make_update_take(chain.takes.last().unwrap(), synthid, chainid)).await;
+				inverse.push(Operation::Remove(audio_id));
 				result_take_id = audio_id;
 			}
 			else {
 				result_take_id = midi_id;
 			}
+			guard.history.push(guard.engine.as_mut(), inverse);
 
 			return Ok(rocket::response::status::Created::new(format!("/api/synths/{}/chains/{}/takes/{}", synthid, chainid, result_take_id)));
 		}
@@ -159,33 +202,401 @@ pub async fn post_take(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32,
 	Err(Status::NotFound)
 }
 
-fn div_ceil(a: u32, b: u32) -> u32 { (a+b-1)/b }
+#[derive(Serialize)]
+pub struct FinishRecording {
+	/// Length the take will have, in seconds
+	duration: f64,
+	loops: u32
+}
 
-#[post("/synths/<synthid>/chains/<chainid>/takes/<takeid>/finish_recording")]
-pub async fn post_take_finish_recording(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, chainid: u32, takeid: u32) -> Result<rocket::response::status::Accepted::<()>, Status> {
+/// Finishes recording a take, rounding its length with `rounding` (e.g. `?rounding=nearest`)
+/// or the song's `finish_rounding`. A query parameter is used instead of a request body,
+/// so that MIDI or OSC triggers can easily be mapped to this request. While free-running,
+/// the take defines the loop length and all takes that are recording are finished with it.
+#[post("/synths/<synthid>/chains/<chainid>/takes/<takeid>/finish_recording?<rounding>")]
+pub async fn post_take_finish_recording(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, chainid: u32, takeid: u32, rounding: Option<String>) -> Result<rocket::response::status::Accepted::<Json<FinishRecording>>, Status> {
+	let rounding = match rounding {
+		Some(rounding) => Some(rounding.parse::<FinishRounding>().map_err(|_| Status::UnprocessableEntity)?),
+		None => None
+	};
 	let mut guard_ = state.mutex.lock().await;
 	let guard = &mut *guard_;
-	if let Some(synth) = guard.synths.iter_mut().find(|s| s.id == synthid) {
-		if let Some(chain) = synth.chains.iter_mut().find(|c| c.id == chainid) {
-			if let Some(take) = chain.takes.iter_mut().find(|s| s.id == takeid) {
-				if let RecordingState::Recording(started) = take.state {
-					// TODO: check whether the take has already been finished
-					let now = guard.engine.transport_position();
-					let current_duration = now - started;
-					let loop_length = guard.engine.loop_length();
-					let target_duration = div_ceil(current_duration - std::cmp::min(loop_length/4, current_duration-1), loop_length) * loop_length;
-					println!("rounding take duration {} to {} (base loop length is {})", current_duration, target_duration, loop_length);
-
-					match take.engine_take_id {
-						EngineTakeRef::Audio(id) => guard.engine.finish_audiotake(chain.engine_audiodevice_id, id, target_duration).map_err(|_| Status::InternalServerError)?,
-						EngineTakeRef::Midi(id) => guard.engine.finish_miditake(synth.engine_mididevice_id, id, target_duration).map_err(|_| Status::InternalServerError)?
-					};
-
-					// TODO set the take as finished
-					return Ok(rocket::response::status::Accepted(None));
+	let recording_state = guard.synths.iter().find(|s| s.id == synthid)
+		.and_then(|s| s.chains.iter().find(|c| c.id == chainid))
+		.and_then(|c| c.takes.iter().find(|t| t.id == takeid))
+		.map(|t| (t.state.clone(), t.engine_take_id.clone()));
+	let (started, engine_take_id) = match recording_state {
+		Some((RecordingState::Recording(started), engine_take_id)) => (started, engine_take_id),
+		_ => return Err(Status::NotFound)
+	};
+
+	// TODO: check whether the take has already been finished
+	let now = guard.engine.transport_position();
+	let current_duration = now - started;
+	let sample_rate = guard.engine.sample_rate();
+	let was_free_running = guard.free_running.is_some();
+	let (target_duration, loops, to_finish) = match guard.free_running.clone() {
+		Some(free_running) => {
+			// the first take defines the loop length
+			let beats = free_running.beats.unwrap_or(guard.engine.n_beats());
+			let loop_length = match free_running.bpm_range {
+				Some((min, max)) => {
+					let bpm = tempo(current_duration, beats, sample_rate).round().max(min.ceil()).min(max.floor());
+					loop_length_for_tempo(bpm, beats, sample_rate).ok_or(Status::UnprocessableEntity)?
+				}
+				None => current_duration
+			};
+
+			// all takes recording right now have been armed while free-running, e.g. the MIDI take
+			// along with an audio take, and are finished together
+			let mut to_finish = Vec::new();
+			for synth in guard.synths.iter() {
+				for chain in synth.chains.iter() {
+					for take in chain.takes.iter().filter(|t| matches!(t.state, RecordingState::Recording(_))) {
+						let length = match take.engine_take_id {
+							EngineTakeRef::Audio(id) => guard.engine.devices().get(&chain.engine_audiodevice_id).and_then(|d| d.takes().get(&id)).map(|t| t.length),
+							EngineTakeRef::Midi(id) => guard.engine.mididevices().get(&synth.engine_mididevice_id).and_then(|d| d.takes().get(&id)).map(|t| t.length)
+						}.ok_or(Status::Conflict)?;
+						if length.is_some() {
+							return Err(Status::Conflict);
+						}
+						to_finish.push((synth.id, chain.id, take.id));
+					}
 				}
 			}
+
+			println!("setting loop length to {} from the first take's duration {}", loop_length, current_duration);
+			let engine_id = match engine_take_id {
+				EngineTakeRef::Audio(id) => id,
+				EngineTakeRef::Midi(id) => id
+			};
+			guard.engine.end_free_running(loop_length, beats, engine_id).map_err(|_| Status::InternalServerError)?;
+			guard.free_running = None;
+			state.update_list.push( make_update_song(UpdateSong {
+				loop_length: Some((loop_length as f64 / sample_rate as f64) as f32),
+				bpm: Some(tempo(loop_length, beats, sample_rate) as f32),
+				free_running: Some(None),
+				..Default::default()
+			})).await;
+			(loop_length, 1, to_finish)
+		}
+		None => {
+			let loop_length = guard.engine.loop_length();
+			let rounding = rounding.unwrap_or(guard.finish_rounding);
+			let target_duration = round_take_length_with(current_duration, loop_length, rounding).ok_or(Status::UnprocessableEntity)?;
+			println!("rounding take duration {} to {} ({:?}, base loop length is {})", current_duration, target_duration, rounding, loop_length);
+			(target_duration, target_duration / loop_length, vec![(synthid, chainid, takeid)])
 		}
+	};
+
+	for &(synthid, chainid, takeid) in to_finish.iter() {
+		let synth = guard.synths.iter_mut().find(|s| s.id == synthid).unwrap();
+		let chain = synth.chains.iter_mut().find(|c| c.id == chainid).unwrap();
+		let take = chain.takes.iter_mut().find(|t| t.id == takeid).unwrap();
+		match take.engine_take_id {
+			// fails if the take has a fixed length and finishes by itself
+			EngineTakeRef::Audio(id) => guard.engine.finish_audiotake(chain.engine_audiodevice_id, id, target_duration).map_err(|_| Status::Conflict)?,
+			EngineTakeRef::Midi(id) => guard.engine.finish_miditake(synth.engine_mididevice_id, id, target_duration).map_err(|_| Status::Conflict)?
+		};
+		take.duration = Some(target_duration as f64 / sample_rate as f64);
+		state.update_list.push(make_update_take(take, synthid, chainid)).await;
 	}
-	Err(Status::NotFound)
+	// the loop length cannot be undone, hence neither can finishing the first takes
+	if !was_free_running {
+		guard.history.push(guard.engine.as_mut(), vec![Operation::Unfinish(takeid)]);
+	}
+
+	Ok(rocket::response::status::Accepted(Some(Json(FinishRecording {
+		duration: target_duration as f64 / sample_rate as f64,
+		loops
+	}))))
+}
+
+/// Aborts a take that is waiting or recording, together with the MIDI take that was armed
+/// along with an audio take. Cancelled takes are deleted and cannot be restored by undoing.
+#[post("/synths/<synthid>/chains/<chainid>/takes/<takeid>/cancel")]
+pub async fn post_take_cancel(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, chainid: u32, takeid: u32) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	let chain = guard.synths.iter().find(|s| s.id == synthid)
+		.and_then(|s| s.chains.iter().find(|c| c.id == chainid))
+		.ok_or(Status::NotFound)?;
+	let take = chain.takes.iter().find(|t| t.id == takeid).ok_or(Status::NotFound)?;
+	if take.state == RecordingState::Finished {
+		return Err(Status::Conflict);
+	}
+
+	let mut takeids = vec![takeid];
+	for t in chain.takes.iter().filter(|t| take.associated_midi_takes.contains(&t.id) && t.state != RecordingState::Finished) {
+		takeids.push(t.id);
+	}
+	let operations = takeids.iter().map(|id| Operation::Remove(*id)).collect();
+
+	// unfinished takes are deleted right away, hence there is nothing to undo. Undoing the
+	// creation of the takes or anything else done to them is not possible anymore either.
+	let mut updates = Vec::new();
+	let result = guard.history.perform(guard.engine.as_mut(), &mut guard.synths, operations, &mut updates);
+	guard.history.forget_takes(guard.engine.as_mut(), &takeids);
+	for update in updates {
+		state.update_list.push(update).await;
+	}
+	result
+}
+
+#[derive(Serialize)]
+pub struct Tap {
+	/// Tempo averaged over the recent taps, or `null` if this tap started a new series
+	bpm: Option<f64>
+}
+
+/// Registers a tap of the tap tempo. As long as there are no takes, the song's tempo is set to
+/// the tapped tempo, keeping the number of beats. Otherwise the takes would have to be
+/// rescaled on every tap, so the tempo is only reported.
+#[post("/song/tap")]
+pub async fn post_song_tap(state: State<'_, std::sync::Arc<GuiState>>) -> Result<Json<Tap>, Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	let bpm = guard.tap_tempo.tap(std::time::Instant::now());
+	let has_takes = guard.synths.iter().flat_map(|s| s.chains.iter()).any(|c| !c.takes.is_empty());
+	if let Some(bpm) = tapped_tempo_to_apply(bpm, has_takes) {
+		let beats = guard.engine.n_beats();
+		let loop_length = loop_length_for_tempo(bpm, beats, guard.engine.sample_rate()).ok_or(Status::UnprocessableEntity)?;
+		set_loop_length_(state.inner(), guard, loop_length, beats).await?;
+	}
+	Ok(Json(Tap { bpm }))
+}
+
+/// Parses the `rounding` query parameter of imports. Unlike recordings, imported files are
+/// not cut short by the song's `finish_rounding`, as they usually have exactly the intended length.
+fn import_rounding(rounding: Option<String>) -> Result<FinishRounding, Status> {
+	match rounding {
+		Some(rounding) => rounding.parse::<FinishRounding>().map_err(|_| Status::UnprocessableEntity),
+		None => Ok(FinishRounding::Lenient)
+	}
+}
+
+/// Loads a WAV file as the metronome's `accent_sample`. Multichannel files are mixed down
+/// to mono.
+#[post("/metronome/accent_sample", data="<data>")]
+pub async fn post_metronome_accent_sample(state: State<'_, std::sync::Arc<GuiState>>, data: Data) -> Result<(), Status> {
+	post_metronome_sample(&state, MetronomeSound::Accent, data).await
+}
+
+/// Loads a WAV file as the metronome's `click_sample`. Multichannel files are mixed down
+/// to mono.
+#[post("/metronome/click_sample", data="<data>")]
+pub async fn post_metronome_click_sample(state: State<'_, std::sync::Arc<GuiState>>, data: Data) -> Result<(), Status> {
+	post_metronome_sample(&state, MetronomeSound::Click, data).await
+}
+
+async fn post_metronome_sample(state: &GuiState, which: MetronomeSound, data: Data) -> Result<(), Status> {
+	let bytes = data.open(MAX_METRONOME_SAMPLE_SIZE_MIB.mebibytes()).into_bytes().await.map_err(|_| Status::BadRequest)?;
+	let (file_sample_rate, file_channels) = read_wav(&mut &bytes[..]).map_err(|e| {
+		println!("failed to load metronome sample: {}", e);
+		Status::UnprocessableEntity
+	})?;
+	if file_channels.is_empty() {
+		return Err(Status::UnprocessableEntity);
+	}
+
+	let sample_rate = state.mutex.lock().await.engine.sample_rate();
+	if !resampling_ratio_is_sane(file_sample_rate, sample_rate) {
+		println!("cannot load metronome sample with sample rate {} (running at {})", file_sample_rate, sample_rate);
+		return Err(Status::UnprocessableEntity);
+	}
+	// mixing down and resampling may take a while, so do it without holding the lock
+	let sample = tokio::task::spawn_blocking(move || {
+		let n_channels = file_channels.len() as f32;
+		let mono: Vec<f32> = (0..file_channels[0].len())
+			.map(|i| file_channels.iter().map(|channel| channel[i]).sum::<f32>() / n_channels)
+			.collect();
+		resample(&mono, file_sample_rate, sample_rate)
+	}).await.map_err(|_| Status::InternalServerError)?;
+
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	set_metronome_sample_(guard, &state.update_list, which, Some(sample)).await
+}
+
+/// Imports a WAV file into the chain. Its duration is rounded with `rounding` (e.g.
+/// `?rounding=down`), or extended to whole loops unless it only slightly exceeds them.
+#[post("/synths/<synthid>/chains/<chainid>/import_wav?<name>&<rounding>", data="<data>")]
+pub async fn post_import_wav(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, chainid: u32, name: Option<String>, rounding: Option<String>, data: Data) -> Result<rocket::response::status::Created<()>, Status> {
+	let rounding = import_rounding(rounding)?;
+	let bytes = data.open(MAX_WAV_SIZE_MIB.mebibytes()).into_bytes().await.map_err(|_| Status::BadRequest)?;
+	let (file_sample_rate, file_channels) = read_wav(&mut &bytes[..]).map_err(|e| {
+		println!("failed to import wav file: {}", e);
+		Status::UnprocessableEntity
+	})?;
+
+	// resampling may take a while, so it is done without holding the lock
+	let sample_rate = state.mutex.lock().await.engine.sample_rate();
+	if !resampling_ratio_is_sane(file_sample_rate, sample_rate) {
+		println!("cannot import wav file with sample rate {} (running at {})", file_sample_rate, sample_rate);
+		return Err(Status::UnprocessableEntity);
+	}
+	let file_channels: Vec<Vec<f32>> = tokio::task::spawn_blocking(move || {
+		file_channels.iter().map(|channel| resample(channel, file_sample_rate, sample_rate)).collect()
+	}).await.map_err(|_| Status::InternalServerError)?;
+	let n_frames = file_channels[0].len() as u32;
+	if n_frames == 0 {
+		return Err(Status::UnprocessableEntity);
+	}
+
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	let synth = guard.synths.iter_mut().find(|s| s.id == synthid).ok_or(Status::NotFound)?;
+	let chain = synth.chains.iter_mut().find(|c| c.id == chainid).ok_or(Status::NotFound)?;
+	// the loop length is either not known yet or about to change
+	if guard.free_running.is_some() || guard.engine.loop_length_change_pending() {
+		return Err(Status::Conflict);
+	}
+
+	let n_channels = guard.engine.devices()[&chain.engine_audiodevice_id].info().n_channels;
+	let length = round_take_length_with(n_frames, guard.engine.loop_length(), rounding).ok_or(Status::UnprocessableEntity)?;
+	println!("rounding imported take duration {} to {} ({:?}, base loop length is {})", n_frames, length, rounding, guard.engine.loop_length());
+
+	// mono files are played on every channel, otherwise the file's channels are used round-robin
+	let samples: Vec<Vec<f32>> = (0..n_channels)
+		.map(|i| {
+			let mut channel = file_channels[i % file_channels.len()].clone();
+			if channel.len() < length as usize {
+				channel.resize(length as usize, 0.0);
+			}
+			channel
+		})
+		.collect();
+
+	let engine_take_id = guard.engine.add_finished_audiotake(chain.engine_audiodevice_id, true, &samples, length)
+		.map_err(|_| Status::InternalServerError)?;
+
+	let id = guard.take_id.gen();
+	let name = gen_unique_name(name.as_deref().unwrap_or("Imported"), chain.takes.iter().map(|c|&c.name[..]));
+	let loop_start = guard.engine.transport_position().saturating_sub(guard.engine.song_position());
+	chain.takes.push( Take {
+		id,
+		name,
+		engine_take_id: EngineTakeRef::Audio(engine_take_id),
+		state: RecordingState::Finished,
+		muted: false,
+		muted_scheduled: false,
+		gain: 1.0,
+		pan: 0.0,
+		cued: false,
+		overdub: false,
+		overdub_feedback: 1.0,
+		associated_midi_takes: Vec::new(),
+		playing_since: Some(loop_start as f64 / sample_rate as f64),
+		duration: Some(length as f64 / sample_rate as f64)
+	});
+	state.update_list.push(make_update_take(chain.takes.last().unwrap(), synthid, chainid)).await;
+	guard.history.push(guard.engine.as_mut(), vec![Operation::Remove(id)]);
+
+	Ok(rocket::response::status::Created::new(format!("/api/synths/{}/chains/{}/takes/{}", synthid, chainid, id)))
+}
+
+/// Imports a Standard MIDI File into the chain `chain` (or the synth's first chain).
+/// One beat of the song corresponds to one quarter note; the file's tempo is ignored.
+/// The duration is rounded like for `post_import_wav`.
+#[post("/synths/<synthid>/import_midi?<name>&<chain>&<rounding>", data="<data>")]
+pub async fn post_import_midi(state: State<'_, std::sync::Arc<GuiState>>, synthid: u32, name: Option<String>, chain: Option<u32>, rounding: Option<String>, data: Data) -> Result<rocket::response::status::Created<()>, Status> {
+	let rounding = import_rounding(rounding)?;
+	let bytes = data.open(MAX_SMF_SIZE_MIB.mebibytes()).into_bytes().await.map_err(|_| Status::BadRequest)?;
+	let smf = read_smf(&mut &bytes[..]).map_err(|e| {
+		println!("failed to import midi file: {}", e);
+		Status::UnprocessableEntity
+	})?;
+
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	let synth = guard.synths.iter_mut().find(|s| s.id == synthid).ok_or(Status::NotFound)?;
+	let chainid = chain;
+	let chain = match chainid {
+		Some(chainid) => synth.chains.iter_mut().find(|c| c.id == chainid),
+		None => synth.chains.first_mut()
+	}.ok_or(Status::NotFound)?;
+	// the loop length is either not known yet or about to change
+	if guard.free_running.is_some() || guard.engine.loop_length_change_pending() {
+		return Err(Status::Conflict);
+	}
+
+	let loop_length = guard.engine.loop_length() as u64;
+	let ticks_per_loop = guard.engine.n_beats() as u64 * smf.ticks_per_quarter as u64;
+	// files whose timestamps do not fit into the song are rejected
+	let to_samples = |tick: u32| u32::try_from(tick as u64 * loop_length / ticks_per_loop).ok();
+
+	let last_event_end = match smf.events.last() {
+		Some(event) => event.timestamp.checked_add(1).ok_or(Status::UnprocessableEntity)?,
+		None => 0
+	};
+	let end_tick = std::cmp::max(smf.end_tick, last_event_end);
+	let duration = to_samples(end_tick).ok_or(Status::UnprocessableEntity)?;
+	if duration == 0 {
+		// an empty file, or one that is shorter than a sample
+		return Err(Status::BadRequest);
+	}
+	let length = round_take_length_with(duration, loop_length as u32, rounding).ok_or(Status::UnprocessableEntity)?;
+	println!("rounding imported take duration {} to {} ({:?}, base loop length is {})", duration, length, rounding, loop_length);
+
+	// Events beyond the take length are dropped. Notes that are still held at the end of
+	// the take are released by the take's note registry when it loops.
+	let events: Vec<MidiMessage> = smf.events.iter()
+		// no event lies beyond `end_tick`, so all of them are representable
+		.filter_map(|event| Some(MidiMessage { timestamp: to_samples(event.timestamp)?, data: event.data, datalen: event.datalen }))
+		.filter(|event| event.timestamp < length)
+		.collect();
+
+	let engine_take_id = guard.engine.add_finished_miditake(synth.engine_mididevice_id, true, &events, length)
+		.map_err(|_| Status::InternalServerError)?;
+
+	let id = guard.take_id.gen();
+	let name = gen_unique_name(name.as_deref().unwrap_or("Imported"), chain.takes.iter().map(|c|&c.name[..]));
+	let sample_rate = guard.engine.sample_rate() as f64;
+	let loop_start = guard.engine.transport_position().saturating_sub(guard.engine.song_position());
+	chain.takes.push( Take {
+		id,
+		name,
+		engine_take_id: EngineTakeRef::Midi(engine_take_id),
+		state: RecordingState::Finished,
+		muted: false,
+		muted_scheduled: false,
+		gain: 1.0,
+		pan: 0.0,
+		cued: false,
+		overdub: false,
+		overdub_feedback: 1.0,
+		associated_midi_takes: Vec::new(),
+		playing_since: Some(loop_start as f64 / sample_rate),
+		duration: Some(length as f64 / sample_rate)
+	});
+	let chainid = chain.id;
+	state.update_list.push(make_update_take(chain.takes.last().unwrap(), synthid, chainid)).await;
+	guard.history.push(guard.engine.as_mut(), vec![Operation::Remove(id)]);
+
+	Ok(rocket::response::status::Created::new(format!("/api/synths/{}/chains/{}/takes/{}", synthid, chainid, id)))
+}
+
+/// Reverts the most recent take creation, finish, mute, renaming or deletion.
+#[post("/undo")]
+pub async fn post_undo(state: State<'_, std::sync::Arc<GuiState>>) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	let mut updates = Vec::new();
+	let result = guard.history.undo(guard.engine.as_mut(), &mut guard.synths, &mut updates);
+	for update in updates {
+		state.update_list.push(update).await;
+	}
+	result
+}
+
+#[post("/redo")]
+pub async fn post_redo(state: State<'_, std::sync::Arc<GuiState>>) -> Result<(), Status> {
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	let mut updates = Vec::new();
+	let result = guard.history.redo(guard.engine.as_mut(), &mut guard.synths, &mut updates);
+	for update in updates {
+		state.update_list.push(update).await;
+	}
+	result
 }
@@ -0,0 +1,496 @@
+use rocket_contrib::json::Json;
+use super::gui_state::*;
+use rocket::State;
+use rocket::http::Status;
+use serde::{Serialize, Deserialize};
+use super::updates::*;
+use super::delete::{delete_synth_, check_synth_deletable};
+use super::util::tempo;
+use crate::engine::FrontendTrait;
+use crate::midi_message::MidiMessage;
+use crate::wav::{read_wav, write_wav};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SESSION_VERSION: u32 = 1;
+const MANIFEST_FILENAME: &str = "session.json";
+/// How long loading a session waits for the devices of the previous session to be torn down
+const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize,Clone)]
+pub struct SessionPost {
+	path: String
+}
+
+/// Contents of the session.json file. Sample data is stored in separate files
+/// next to it, which are referred to by `SessionTake::files`.
+#[derive(Serialize,Deserialize)]
+struct SessionManifest {
+	version: u32,
+	sample_rate: u32,
+	loop_length: u32, // in samples
+	beats: u32,
+	/// Mute fade and loop seam crossfade lengths in samples for audio takes created afterwards
+	#[serde(default)]
+	fade_lengths: Option<(u32, u32)>,
+	/// Settings of the "clock" MIDI port
+	#[serde(default)]
+	midiclock: Option<SessionMidiClock>,
+	synths: Vec<SessionSynth>
+}
+
+#[derive(Serialize,Deserialize)]
+struct SessionMidiClock {
+	enabled: bool,
+	offset: i32
+}
+
+#[derive(Serialize,Deserialize)]
+struct SessionSynth {
+	name: String,
+	#[serde(default)]
+	clock: bool,
+	#[serde(default)]
+	clock_offset: i32,
+	chains: Vec<SessionChain>
+}
+
+#[derive(Serialize,Deserialize)]
+struct SessionChain {
+	name: String,
+	midi: bool,
+	echo: bool,
+	#[serde(default = "unity_gain")]
+	gain: f32,
+	channels: usize,
+	takes: Vec<SessionTake>
+}
+
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq)]
+enum SessionTakeType {
+	Audio,
+	Midi
+}
+
+#[derive(Serialize,Deserialize)]
+struct SessionTake {
+	/// only used for resolving `associated_midi_takes` within the session
+	id: u32,
+	name: String,
+	r#type: SessionTakeType,
+	muted: bool,
+	#[serde(default = "unity_gain")]
+	gain: f32,
+	#[serde(default)]
+	pan: f32,
+	associated_midi_takes: Vec<u32>,
+	length: u32, // in samples
+	/// one mono WAV file per channel for audio takes, one event dump for MIDI takes
+	files: Vec<String>
+}
+
+#[derive(Serialize,Deserialize)]
+struct SessionMidiEvent {
+	timestamp: u32,
+	data: Vec<u8>
+}
+
+/// Sample data of a take, read from the files referred to by `SessionTake::files`
+enum TakeData {
+	Audio(Vec<Vec<f32>>),
+	Midi(Vec<MidiMessage>)
+}
+
+/// Contents of a file next to the manifest
+enum SessionFile {
+	Wav(Vec<f32>),
+	Midi(Vec<SessionMidiEvent>)
+}
+
+/// A copy of the current session, which can be written without holding the GUI state's lock
+struct SavedSession {
+	manifest: SessionManifest,
+	files: Vec<(String, SessionFile)>
+}
+
+/// A session whose files have all been read, with the take data nested like the manifest's
+/// synths, chains and takes.
+struct LoadedSession {
+	manifest: SessionManifest,
+	data: Vec<Vec<Vec<TakeData>>>
+}
+
+fn unity_gain() -> f32 { 1.0 }
+
+fn invalid(message: &str) -> Error {
+	Error::new(ErrorKind::InvalidData, message)
+}
+
+#[post("/session/save", data="<data>")]
+pub async fn post_session_save(state: State<'_, std::sync::Arc<GuiState>>, data: Json<SessionPost>) -> Result<(), Status> {
+	let session = {
+		let guard = state.mutex.lock().await;
+		copy_session(&*guard)
+	}.map_err(|e| {
+		println!("failed to save session to {}: {}", data.path, e);
+		Status::InternalServerError
+	})?;
+
+	let path = PathBuf::from(&data.path);
+	tokio::task::spawn_blocking(move || write_session(&path, session)).await
+		.map_err(|_| Status::InternalServerError)?
+		.map_err(|e| {
+			println!("failed to save session to {}: {}", data.path, e);
+			Status::InternalServerError
+		})
+}
+
+/// Replaces the current session with the one saved in `path`. The file is read and checked
+/// against the running engine before anything is changed. Since the new devices may have the
+/// same names as the current ones, the current session is torn down before the new one is
+/// created; should creating it fail, the part that has been loaded up to then is kept.
+#[post("/session/load", data="<data>")]
+pub async fn post_session_load(state: State<'_, std::sync::Arc<GuiState>>, data: Json<SessionPost>) -> Result<(), Status> {
+	let session = read_session(Path::new(&data.path)).map_err(|e| {
+		println!("failed to read session from {}: {}", data.path, e);
+		Status::UnprocessableEntity
+	})?;
+	let manifest = &session.manifest;
+
+	let mut guard_ = state.mutex.lock().await;
+	let guard = &mut *guard_;
+	if manifest.sample_rate != guard.engine.sample_rate() {
+		println!("cannot load session with sample rate {} (running at {})", manifest.sample_rate, guard.engine.sample_rate());
+		return Err(Status::UnprocessableEntity);
+	}
+	if guard.free_running.is_some() || guard.engine.loop_length_change_pending() {
+		return Err(Status::Conflict);
+	}
+
+	for synth in guard.synths.iter() {
+		check_synth_deletable(guard.engine.as_ref(), synth)?;
+	}
+
+	// remove the current session
+	guard.history.clear(guard.engine.as_mut());
+	while let Some(synth) = guard.synths.last_mut() {
+		delete_synth_(guard.engine.as_mut(), synth)?;
+		let synthid = synth.id;
+		guard.synths.pop();
+		state.update_list.push(make_update_synth_deleted(synthid)).await;
+	}
+	// the new devices may have the same names as the removed ones
+	if !guard.engine.wait_for_removed_devices(TEARDOWN_TIMEOUT) {
+		println!("devices of the previous session have not been torn down in time");
+		return Err(Status::InternalServerError);
+	}
+
+	guard.engine.set_loop_length(manifest.loop_length, manifest.beats).map_err(|_| Status::InternalServerError)?;
+	state.update_list.push( make_update_song(UpdateSong {
+		loop_length: Some(manifest.loop_length as f32 / manifest.sample_rate as f32),
+		bpm: Some(tempo(manifest.loop_length, manifest.beats, manifest.sample_rate) as f32),
+		// the clock source has been removed along with its synth
+		clock_source: Some(None),
+		..Default::default()
+	})).await;
+	// the fades apply to the takes created below
+	if let Some((fade_length, crossfade_length)) = manifest.fade_lengths {
+		guard.engine.set_audiotake_fade_lengths(fade_length, crossfade_length);
+		guard.mixer.fade_length = fade_length as f32 / manifest.sample_rate as f32;
+		guard.mixer.crossfade_length = crossfade_length as f32 / manifest.sample_rate as f32;
+		state.update_list.push( UpdateRoot {
+			synths: None,
+			song: None,
+			mixer: Some(guard.mixer.clone()),
+			midiclock: None,
+			metronome: None
+		}).await;
+	}
+	if let Some(midiclock) = manifest.midiclock.as_ref() {
+		guard.engine.set_midiclock(midiclock.enabled, midiclock.offset).map_err(|_| Status::InternalServerError)?;
+		guard.midiclock = MidiClock { enabled: midiclock.enabled, offset: midiclock.offset };
+		state.update_list.push( UpdateRoot {
+			synths: None,
+			song: None,
+			mixer: None,
+			midiclock: Some(guard.midiclock.clone()),
+			metronome: None
+		}).await;
+	}
+
+	for (session_synth, synth_data) in manifest.synths.iter().zip(session.data.into_iter()) {
+		let engine = guard.engine.as_mut();
+		let engine_mididevice_id = engine.add_mididevice(&session_synth.name).map_err(|_| Status::InternalServerError)?;
+		if session_synth.clock {
+			engine.set_mididevice_clock(engine_mididevice_id, Some(session_synth.clock_offset)).map_err(|_| Status::InternalServerError)?;
+		}
+		let synth = Synth {
+			id: guard.synth_id.gen(),
+			name: session_synth.name.clone(),
+			chains: Vec::new(),
+			clock: session_synth.clock,
+			clock_offset: session_synth.clock_offset,
+			engine_mididevice_id
+		};
+		state.update_list.push(make_update_synth(&synth)).await;
+		guard.synths.push(synth);
+		let synth = guard.synths.last_mut().unwrap();
+
+		for (session_chain, chain_data) in session_synth.chains.iter().zip(synth_data.into_iter()) {
+			let engine = guard.engine.as_mut();
+			let engine_audiodevice_id = engine.add_device(&session_chain.name, session_chain.channels as u32)
+				.map_err(|_| Status::InternalServerError)?;
+			if session_chain.echo {
+				engine.set_audiodevice_echo(engine_audiodevice_id, true).map_err(|_| Status::InternalServerError)?;
+			}
+			engine.set_audiodevice_gain(engine_audiodevice_id, session_chain.gain).map_err(|_| Status::InternalServerError)?;
+			let mut chain = Chain {
+				id: guard.chain_id.gen(),
+				name: session_chain.name.clone(),
+				takes: Vec::new(),
+				midi: session_chain.midi,
+				echo: session_chain.echo,
+				gain: session_chain.gain,
+				engine_audiodevice_id
+			};
+			state.update_list.push(make_update_chain(&chain, synth.id)).await;
+
+			let mut take_ids = HashMap::new();
+			for (session_take, take_data) in session_chain.takes.iter().zip(chain_data.into_iter()) {
+				let engine_take_id = add_take(guard.engine.as_mut(), session_take, take_data, synth.engine_mididevice_id, engine_audiodevice_id)
+					.map_err(|_| {
+						println!("failed to add take {}", session_take.name);
+						Status::InternalServerError
+					})?;
+				let id = guard.take_id.gen();
+				take_ids.insert(session_take.id, id);
+
+				let sample_rate = guard.engine.sample_rate() as f64;
+				let loop_start = guard.engine.transport_position().saturating_sub(guard.engine.song_position());
+				chain.takes.push( Take {
+					id,
+					name: session_take.name.clone(),
+					engine_take_id,
+					state: RecordingState::Finished,
+					muted: session_take.muted,
+					muted_scheduled: false,
+					gain: session_take.gain,
+					pan: session_take.pan,
+					cued: false,
+					overdub: false,
+					overdub_feedback: 1.0,
+					associated_midi_takes: Vec::new(),
+					playing_since: Some(loop_start as f64 / sample_rate),
+					duration: Some(session_take.length as f64 / sample_rate)
+				});
+			}
+
+			for (take, session_take) in chain.takes.iter_mut().zip(session_chain.takes.iter()) {
+				take.associated_midi_takes = session_take.associated_midi_takes.iter()
+					.filter_map(|id| take_ids.get(id).cloned())
+					.collect();
+				state.update_list.push(make_update_take(take, synth.id, chain.id)).await;
+			}
+
+			synth.chains.push(chain);
+		}
+	}
+
+	Ok(())
+}
+
+/// Resolves `filename` within the canonical session directory `dir`. Fails if the file lies
+/// outside of it, e.g. because of `..`, an absolute path or a symbolic link.
+fn session_file(dir: &Path, filename: &str) -> std::io::Result<PathBuf> {
+	let path = dir.join(filename).canonicalize()?;
+	if !path.starts_with(dir) {
+		return Err(invalid("file outside of the session directory"));
+	}
+	Ok(path)
+}
+
+/// Reads the manifest in `dir` and all files it refers to, and checks that they fit together.
+fn read_session(dir: &Path) -> std::io::Result<LoadedSession> {
+	let dir = dir.canonicalize()?;
+	let manifest: SessionManifest = serde_json::from_reader(BufReader::new(File::open(dir.join(MANIFEST_FILENAME))?))?;
+	if manifest.version != SESSION_VERSION {
+		return Err(invalid("unsupported session version"));
+	}
+	if manifest.loop_length == 0 || manifest.beats == 0 {
+		return Err(invalid("invalid loop length"));
+	}
+	if manifest.fade_lengths.map_or(false, |(fade_length, crossfade_length)| fade_length.max(crossfade_length) > manifest.sample_rate) {
+		return Err(invalid("invalid fade lengths"));
+	}
+
+	let mut data = Vec::new();
+	for session_synth in manifest.synths.iter() {
+		let mut synth_data = Vec::new();
+		for session_chain in session_synth.chains.iter() {
+			if session_chain.channels == 0 || !(session_chain.gain.is_finite() && session_chain.gain >= 0.0) {
+				return Err(invalid("invalid chain"));
+			}
+			let mut chain_data = Vec::new();
+			for session_take in session_chain.takes.iter() {
+				let take_data = read_take(&dir, session_take, session_chain.channels).map_err(|e| {
+					Error::new(e.kind(), format!("take {}: {}", session_take.name, e))
+				})?;
+				chain_data.push(take_data);
+			}
+			synth_data.push(chain_data);
+		}
+		data.push(synth_data);
+	}
+	Ok(LoadedSession { manifest, data })
+}
+
+fn read_take(dir: &Path, session_take: &SessionTake, channels: usize) -> std::io::Result<TakeData> {
+	if session_take.length == 0 {
+		return Err(invalid("empty take"));
+	}
+	match session_take.r#type {
+		SessionTakeType::Audio => {
+			if session_take.files.len() != channels {
+				return Err(invalid("expected a file per channel"));
+			}
+			if !(session_take.gain.is_finite() && session_take.gain >= 0.0 && (-1.0..=1.0).contains(&session_take.pan)) {
+				return Err(invalid("invalid gain or pan"));
+			}
+			let mut samples = Vec::new();
+			for filename in session_take.files.iter() {
+				let (_, mut channels) = read_wav(&mut BufReader::new(File::open(session_file(dir, filename)?)?))?;
+				if channels.len() != 1 {
+					return Err(invalid("expected a mono file per channel"));
+				}
+				let channel = channels.pop().unwrap();
+				if channel.len() < session_take.length as usize {
+					return Err(invalid("audio data is shorter than the take"));
+				}
+				samples.push(channel);
+			}
+			Ok(TakeData::Audio(samples))
+		}
+		SessionTakeType::Midi => {
+			let filename = session_take.files.first().ok_or_else(|| invalid("no event file given"))?;
+			let dump: Vec<SessionMidiEvent> = serde_json::from_reader(BufReader::new(File::open(session_file(dir, filename)?)?))?;
+			let mut events = Vec::with_capacity(dump.len());
+			for event in dump {
+				if event.data.is_empty() || event.data.len() > 3 {
+					return Err(invalid("invalid MIDI event"));
+				}
+				if event.timestamp >= session_take.length {
+					return Err(invalid("MIDI events exceed the take length"));
+				}
+				let mut data = [0u8; 3];
+				data[0..event.data.len()].copy_from_slice(&event.data);
+				events.push(MidiMessage { timestamp: event.timestamp, data, datalen: event.data.len() as u8 });
+			}
+			events.sort_by_key(|event| event.timestamp);
+			Ok(TakeData::Midi(events))
+		}
+	}
+}
+
+fn add_take(engine: &mut dyn FrontendTrait, session_take: &SessionTake, data: TakeData, mididevice_id: usize, audiodevice_id: usize) -> Result<EngineTakeRef, ()> {
+	match data {
+		TakeData::Audio(samples) => {
+			let id = engine.add_finished_audiotake(audiodevice_id, !session_take.muted, &samples, session_take.length)?;
+			engine.set_audiotake_gain(audiodevice_id, id, session_take.gain, session_take.pan)?;
+			Ok(EngineTakeRef::Audio(id))
+		}
+		TakeData::Midi(events) => {
+			let id = engine.add_finished_miditake(mididevice_id, !session_take.muted, &events, session_take.length)?;
+			Ok(EngineTakeRef::Midi(id))
+		}
+	}
+}
+
+/// Copies the manifest and the take data of the current session
+fn copy_session(state: &GuiMutexedState) -> std::io::Result<SavedSession> {
+	let engine = state.engine.as_ref();
+	let mut files = Vec::new();
+
+	let mut manifest = SessionManifest {
+		version: SESSION_VERSION,
+		sample_rate: engine.sample_rate(),
+		loop_length: engine.loop_length(),
+		beats: engine.n_beats(),
+		fade_lengths: Some(engine.audiotake_fade_lengths()),
+		midiclock: Some(SessionMidiClock { enabled: state.midiclock.enabled, offset: state.midiclock.offset }),
+		synths: Vec::new()
+	};
+
+	for synth in state.synths.iter() {
+		let mut session_synth = SessionSynth { name: synth.name.clone(), clock: synth.clock, clock_offset: synth.clock_offset, chains: Vec::new() };
+		for chain in synth.chains.iter() {
+			let channels = engine.devices().get(&chain.engine_audiodevice_id).map(|d| d.info().n_channels).unwrap_or(2);
+			let mut session_chain = SessionChain { name: chain.name.clone(), midi: chain.midi, echo: chain.echo, gain: chain.gain, channels, takes: Vec::new() };
+
+			// takes that are still being recorded are not saved
+			let finished_takes: Vec<&Take> = chain.takes.iter().filter(|t| t.state == RecordingState::Finished).collect();
+			for take in finished_takes.iter() {
+				let (r#type, length, take_files) = match take.engine_take_id {
+					EngineTakeRef::Audio(id) => {
+						let length = engine.devices()[&chain.engine_audiodevice_id].takes()[&id].length.unwrap();
+						let samples = engine.audiotake_samples(chain.engine_audiodevice_id, id).ok_or_else(|| invalid("audio take vanished"))?;
+						let mut take_files = Vec::new();
+						for (i, channel) in samples.into_iter().enumerate() {
+							let filename = format!("take{}_{}.wav", take.id, i+1);
+							take_files.push(filename.clone());
+							files.push((filename, SessionFile::Wav(channel)));
+						}
+						(SessionTakeType::Audio, length, take_files)
+					}
+					EngineTakeRef::Midi(id) => {
+						let length = engine.mididevices()[&synth.engine_mididevice_id].takes()[&id].length.unwrap();
+						let events = engine.miditake_events(synth.engine_mididevice_id, id).ok_or_else(|| invalid("MIDI take vanished"))?;
+						let dump: Vec<SessionMidiEvent> = events.iter().map(|event| SessionMidiEvent {
+							timestamp: event.timestamp,
+							data: event.data[0..event.datalen as usize].to_vec()
+						}).collect();
+						let filename = format!("take{}.json", take.id);
+						files.push((filename.clone(), SessionFile::Midi(dump)));
+						(SessionTakeType::Midi, length, vec![filename])
+					}
+				};
+
+				session_chain.takes.push(SessionTake {
+					id: take.id,
+					name: take.name.clone(),
+					r#type,
+					muted: take.muted,
+					gain: take.gain,
+					pan: take.pan,
+					associated_midi_takes: take.associated_midi_takes.iter()
+						.filter(|id| finished_takes.iter().any(|t| t.id == **id))
+						.cloned().collect(),
+					length,
+					files: take_files
+				});
+			}
+			session_synth.chains.push(session_chain);
+		}
+		manifest.synths.push(session_synth);
+	}
+
+	Ok(SavedSession { manifest, files })
+}
+
+/// Writes a copied session to `dir`, creating it if necessary
+fn write_session(dir: &Path, session: SavedSession) -> std::io::Result<()> {
+	std::fs::create_dir_all(dir)?;
+	let dir = &dir.canonicalize()?;
+
+	for (filename, file) in session.files.iter() {
+		let mut writer = BufWriter::new(File::create(dir.join(filename))?);
+		match file {
+			SessionFile::Wav(channel) => write_wav(&mut writer, session.manifest.sample_rate, std::slice::from_ref(channel))?,
+			SessionFile::Midi(dump) => serde_json::to_writer(&mut writer, dump)?
+		}
+	}
+	serde_json::to_writer_pretty(BufWriter::new(File::create(dir.join(MANIFEST_FILENAME))?), &session.manifest)?;
+	Ok(())
+}
@@ -3,7 +3,7 @@ use std::time::Duration;
 use rocket::State;
 use rocket_contrib::json::Json;
 use super::gui_state::GuiState;
-use super::data::{Synth,Chain,Take,RecordingState,EngineTakeRef};
+use super::data::{Synth,Chain,Take,RecordingState,EngineTakeRef,Mixer,MidiClock,Metronome,JackTransport,FreeRunning,FinishRounding};
 
 #[derive(Serialize, Clone)]
 pub struct Update {
@@ -11,14 +11,30 @@ pub struct Update {
 	pub action: UpdateRoot
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, Default)]
 pub struct UpdateSong {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub song_position: Option<f32>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub transport_position: Option<f32>,
 	#[serde(skip_serializing_if = "Option::is_none")]
-	pub loop_length: Option<f32>
+	pub loop_length: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub bpm: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub playing: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub clock_source: Option<Option<u32>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub free_running: Option<Option<FreeRunning>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub jack_transport: Option<JackTransport>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub finish_rounding: Option<FinishRounding>,
+	/// Loop length in seconds matching the tempo of the clock source or the JACK transport,
+	/// which has not been applied because there are takes
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub suggested_loop_length: Option<f32>
 }
 
 #[derive(Serialize, Clone)]
@@ -26,7 +42,13 @@ pub struct UpdateRoot {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub synths: Option<Vec<UpdateSynth>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
-	pub song: Option<UpdateSong>
+	pub song: Option<UpdateSong>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mixer: Option<Mixer>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub midiclock: Option<MidiClock>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub metronome: Option<Metronome>
 }
 
 #[derive(Serialize, Clone, Default)]
@@ -37,6 +59,10 @@ pub struct UpdateSynth {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub chains: Option<Vec<UpdateChain>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
+	pub clock: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub clock_offset: Option<i32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub deleted: Option<bool>
 }
 
@@ -50,6 +76,8 @@ pub struct UpdateChain {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub echo: Option<bool>,
 	#[serde(skip_serializing_if = "Option::is_none")]
+	pub gain: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub takes: Option<Vec<UpdateTake>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub deleted: Option<bool>
@@ -69,6 +97,16 @@ pub struct UpdateTake {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub muted_scheduled: Option<bool>,
 	#[serde(skip_serializing_if = "Option::is_none")]
+	pub gain: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub pan: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub cued: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub overdub: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub overdub_feedback: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub associated_midi_takes: Option<Vec<u32>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub playing_since: Option<Option<f64>>,
@@ -78,14 +116,29 @@ pub struct UpdateTake {
 	pub deleted: Option<bool>
 }
 
+pub fn make_update_song(song: UpdateSong) -> UpdateRoot {
+	UpdateRoot {
+		synths: None,
+		song: Some(song),
+		mixer: None,
+		midiclock: None,
+		metronome: None
+	}
+}
+
 pub fn make_update_synth(synth: &Synth) -> UpdateRoot {
 	UpdateRoot {
 		synths: Some(vec![UpdateSynth {
 			id: synth.id,
 			name: Some(synth.name.clone()),
+			clock: Some(synth.clock),
+			clock_offset: Some(synth.clock_offset),
 			..Default::default()
 		}]),
-		song: None
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: None
 	}
 }
 
@@ -98,11 +151,15 @@ pub fn make_update_chain(chain: &Chain, synthid: u32) -> UpdateRoot {
 				name: Some(chain.name.clone()),
 				midi: Some(chain.midi),
 				echo: Some(chain.echo),
+				gain: Some(chain.gain),
 				..Default::default()
 			}]),
 			..Default::default()
 		}]),
-		song: None
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: None
 	}
 }
 
@@ -119,6 +176,11 @@ pub fn make_update_take(take: &Take, synthid: u32, chainid: u32) -> UpdateRoot {
 					state: Some(take.state.clone()),
 					muted: Some(take.muted),
 					muted_scheduled: Some(take.muted_scheduled),
+					gain: Some(take.gain),
+					pan: Some(take.pan),
+					cued: Some(take.cued),
+					overdub: Some(take.overdub),
+					overdub_feedback: Some(take.overdub_feedback),
 					associated_midi_takes: Some(take.associated_midi_takes.clone()),
 					playing_since: Some(take.playing_since),
 					duration: Some(take.duration),
@@ -128,7 +190,64 @@ pub fn make_update_take(take: &Take, synthid: u32, chainid: u32) -> UpdateRoot {
 			}]),
 			..Default::default()
 		}]),
-		song: None
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: None
+	}
+}
+
+pub fn make_update_synth_deleted(synthid: u32) -> UpdateRoot {
+	UpdateRoot {
+		synths: Some(vec![UpdateSynth {
+			id: synthid,
+			deleted: Some(true),
+			..Default::default()
+		}]),
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: None
+	}
+}
+
+pub fn make_update_chain_deleted(chainid: u32, synthid: u32) -> UpdateRoot {
+	UpdateRoot {
+		synths: Some(vec![UpdateSynth {
+			id: synthid,
+			chains: Some(vec![UpdateChain {
+				id: chainid,
+				deleted: Some(true),
+				..Default::default()
+			}]),
+			..Default::default()
+		}]),
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: None
+	}
+}
+
+pub fn make_update_take_deleted(takeid: u32, synthid: u32, chainid: u32) -> UpdateRoot {
+	UpdateRoot {
+		synths: Some(vec![UpdateSynth {
+			id: synthid,
+			chains: Some(vec![UpdateChain {
+				id: chainid,
+				takes: Some(vec![UpdateTake {
+					id: takeid,
+					deleted: Some(true),
+					..Default::default()
+				}]),
+				..Default::default()
+			}]),
+			..Default::default()
+		}]),
+		song: None,
+		mixer: None,
+		midiclock: None,
+		metronome: None
 	}
 }
 
@@ -1,4 +1,15 @@
 
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use super::data::FinishRounding;
+
+/// Number of taps the tap tempo is averaged over
+const TAP_HISTORY: usize = 8;
+/// Pause after which a tap starts a new series
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Tempos (in BPM) that can be set. Tapped tempos outside this range are reported, but not applied.
+pub const TEMPO_RANGE: std::ops::RangeInclusive<f64> = 20.0..=400.0;
+
 pub fn gen_unique_name<'a,T: Iterator<Item=&'a str> + Clone>(desired_name: &str, iter: T) -> String {
 	if iter.clone().find(|s| *s == desired_name).is_some() {
 		let mut i = 2;
@@ -15,3 +26,210 @@ pub fn gen_unique_name<'a,T: Iterator<Item=&'a str> + Clone>(desired_name: &str,
 	}
 }
 
+
+/// Returns the tempo in beats per minute of a loop of `loop_length` samples with `beats` beats.
+pub fn tempo(loop_length: u32, beats: u32, sample_rate: u32) -> f64 {
+	60.0 * beats as f64 * sample_rate as f64 / loop_length as f64
+}
+
+/// Returns the length in samples of a loop of `beats` beats at `bpm`, rounded to the nearest
+/// sample, or None if it is too long to be represented.
+pub fn loop_length_for_tempo(bpm: f64, beats: u32, sample_rate: u32) -> Option<u32> {
+	let loop_length = (60.0 * beats as f64 * sample_rate as f64 / bpm).round();
+	if loop_length <= u32::MAX as f64 { Some(loop_length as u32) } else { None }
+}
+
+/// Returns the tapped tempo if it should be applied to the song. While there are takes, it
+/// is only reported, because the takes would have to be rescaled on every tap.
+pub fn tapped_tempo_to_apply(bpm: Option<f64>, has_takes: bool) -> Option<f64> {
+	bpm.filter(|bpm| !has_takes && TEMPO_RANGE.contains(bpm))
+}
+
+/// Derives a tempo from the times at which the user has tapped.
+pub struct TapTempo {
+	taps: VecDeque<Instant>
+}
+
+impl TapTempo {
+	pub fn new() -> TapTempo {
+		TapTempo { taps: VecDeque::with_capacity(TAP_HISTORY) }
+	}
+
+	/// Registers a tap at `now`. Returns the tempo in beats per minute averaged over the recent
+	/// taps, or None if this tap starts a new series.
+	pub fn tap(&mut self, now: Instant) -> Option<f64> {
+		if let Some(last) = self.taps.back() {
+			if now.duration_since(*last) > TAP_TIMEOUT {
+				self.taps.clear();
+			}
+		}
+		if self.taps.len() == TAP_HISTORY {
+			self.taps.pop_front();
+		}
+		self.taps.push_back(now);
+
+		let span = now.duration_since(*self.taps.front().unwrap()).as_secs_f64();
+		if self.taps.len() < 2 || span <= 0.0 {
+			return None;
+		}
+		Some(60.0 * (self.taps.len() - 1) as f64 / span)
+	}
+}
+
+/// Divides `a > 0` by `b`, rounding up
+fn div_ceil(a: u32, b: u32) -> u32 { (a-1)/b + 1 }
+
+/// Rounds a take duration to a multiple of the loop length. Takes that exceed a multiple of
+/// the loop length by up to a quarter loop are shortened, everything else is extended.
+/// Returns `None` if the rounded length does not fit into a `u32`.
+pub fn round_take_length(duration: u32, loop_length: u32) -> Option<u32> {
+	// even an empty take lasts one loop
+	let duration = std::cmp::max(duration, 1);
+	div_ceil(duration - std::cmp::min(loop_length/4, duration-1), loop_length).checked_mul(loop_length)
+}
+
+/// Rounds a take duration to a multiple of the loop length, as determined by `rounding`.
+/// Returns `None` if the rounded length does not fit into a `u32`.
+pub fn round_take_length_with(duration: u32, loop_length: u32, rounding: FinishRounding) -> Option<u32> {
+	if duration == 0 {
+		return Some(loop_length);
+	}
+	let loops = duration as f64 / loop_length as f64;
+	let n_loops = match rounding {
+		FinishRounding::Lenient => return round_take_length(duration, loop_length),
+		FinishRounding::Nearest => loops.round(),
+		FinishRounding::Up => loops.ceil(),
+		FinishRounding::Down => loops.floor(),
+		FinishRounding::PowerOfTwo => {
+			let lower = loops.max(1.0).log2().floor().exp2();
+			if loops - lower < 2.0 * lower - loops { lower } else { 2.0 * lower }
+		}
+	};
+	// the cast saturates, so the multiplication catches too many loops
+	(n_loops.max(1.0) as u32).checked_mul(loop_length)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LOOP: u32 = 1000;
+
+	fn tap_at(tap_tempo: &mut TapTempo, start: Instant, millis: u64) -> Option<f64> {
+		tap_tempo.tap(start + Duration::from_millis(millis))
+	}
+
+	#[test]
+	pub fn tap_tempo_averages_the_tap_intervals() {
+		let start = Instant::now();
+		let mut tap_tempo = TapTempo::new();
+		assert_eq!(tap_at(&mut tap_tempo, start, 0), None);
+		assert_eq!(tap_at(&mut tap_tempo, start, 500), Some(120.0));
+		assert_eq!(tap_at(&mut tap_tempo, start, 1500), Some(80.0));
+		assert_eq!(tap_at(&mut tap_tempo, start, 2000), Some(90.0));
+	}
+
+	#[test]
+	pub fn tap_tempo_only_averages_over_the_recent_taps() {
+		let start = Instant::now();
+		let mut tap_tempo = TapTempo::new();
+		for i in 0..TAP_HISTORY as u64 {
+			tap_at(&mut tap_tempo, start, i * 1000);
+		}
+		let mut bpm = None;
+		for i in 0..TAP_HISTORY as u64 {
+			bpm = tap_at(&mut tap_tempo, start, (TAP_HISTORY as u64 - 1) * 1000 + (i+1) * 500);
+		}
+		assert_eq!(bpm, Some(120.0));
+	}
+
+	#[test]
+	pub fn tap_tempo_starts_a_new_series_after_a_pause() {
+		let start = Instant::now();
+		let mut tap_tempo = TapTempo::new();
+		tap_at(&mut tap_tempo, start, 0);
+		assert_eq!(tap_at(&mut tap_tempo, start, 1000), Some(60.0));
+		assert_eq!(tap_at(&mut tap_tempo, start, 3000), Some(40.0));
+		assert_eq!(tap_at(&mut tap_tempo, start, 5001), None);
+		assert_eq!(tap_at(&mut tap_tempo, start, 5501), Some(120.0));
+	}
+
+	#[test]
+	pub fn tapped_tempos_are_only_applied_within_the_range_and_without_takes() {
+		assert_eq!(tapped_tempo_to_apply(Some(120.0), false), Some(120.0));
+		assert_eq!(tapped_tempo_to_apply(Some(20.0), false), Some(20.0));
+		assert_eq!(tapped_tempo_to_apply(Some(400.0), false), Some(400.0));
+		assert_eq!(tapped_tempo_to_apply(Some(19.9), false), None);
+		assert_eq!(tapped_tempo_to_apply(Some(600.0), false), None);
+		assert_eq!(tapped_tempo_to_apply(Some(120.0), true), None);
+		assert_eq!(tapped_tempo_to_apply(None, false), None);
+	}
+
+	#[test]
+	pub fn loop_length_for_tempo_inverts_tempo() {
+		for sample_rate in [44100, 48000, 96000].iter().cloned() {
+			assert_eq!(tempo(2 * sample_rate, 4, sample_rate), 120.0);
+			assert_eq!(loop_length_for_tempo(120.0, 4, sample_rate), Some(2 * sample_rate));
+			for loop_length in [1, 12345, sample_rate, 3 * sample_rate + 1, 60 * sample_rate].iter().cloned() {
+				for beats in [1, 3, 4, 7].iter().cloned() {
+					assert_eq!(loop_length_for_tempo(tempo(loop_length, beats, sample_rate), beats, sample_rate), Some(loop_length));
+				}
+			}
+		}
+	}
+
+	#[test]
+	pub fn loop_length_for_tempo_rejects_unrepresentable_lengths() {
+		assert_eq!(loop_length_for_tempo(20.0, u32::MAX, 44100), None);
+		assert_eq!(loop_length_for_tempo(0.0, 4, 44100), None);
+	}
+
+	fn round_all(rounding: FinishRounding) -> Vec<u32> {
+		// exact multiples, just below and above a loop boundary, shorter than a loop, empty
+		[2000, 4000, 1999, 2001, 500, 0].iter().map(|d| round_take_length_with(*d, LOOP, rounding).unwrap()).collect()
+	}
+
+	#[test]
+	pub fn lenient_rounding_shortens_takes_only_slightly_exceeding_the_loop() {
+		assert_eq!(round_all(FinishRounding::Lenient), vec![2000, 4000, 2000, 2000, 1000, 1000]);
+		assert_eq!(round_take_length_with(2250, LOOP, FinishRounding::Lenient), Some(2000));
+		assert_eq!(round_take_length_with(2251, LOOP, FinishRounding::Lenient), Some(3000));
+	}
+
+	#[test]
+	pub fn nearest_rounding_picks_the_nearest_multiple() {
+		assert_eq!(round_all(FinishRounding::Nearest), vec![2000, 4000, 2000, 2000, 1000, 1000]);
+		assert_eq!(round_take_length_with(2499, LOOP, FinishRounding::Nearest), Some(2000));
+		assert_eq!(round_take_length_with(2501, LOOP, FinishRounding::Nearest), Some(3000));
+		assert_eq!(round_take_length_with(300, LOOP, FinishRounding::Nearest), Some(1000));
+	}
+
+	#[test]
+	pub fn up_rounding_extends_takes() {
+		assert_eq!(round_all(FinishRounding::Up), vec![2000, 4000, 2000, 3000, 1000, 1000]);
+	}
+
+	#[test]
+	pub fn down_rounding_shortens_takes_to_at_least_one_loop() {
+		assert_eq!(round_all(FinishRounding::Down), vec![2000, 4000, 1000, 2000, 1000, 1000]);
+	}
+
+	#[test]
+	pub fn power_of_two_rounding_picks_the_nearest_power_of_two() {
+		assert_eq!(round_all(FinishRounding::PowerOfTwo), vec![2000, 4000, 2000, 2000, 1000, 1000]);
+		assert_eq!(round_take_length_with(2999, LOOP, FinishRounding::PowerOfTwo), Some(2000));
+		assert_eq!(round_take_length_with(3001, LOOP, FinishRounding::PowerOfTwo), Some(4000));
+		assert_eq!(round_take_length_with(5000, LOOP, FinishRounding::PowerOfTwo), Some(4000));
+		assert_eq!(round_take_length_with(7000, LOOP, FinishRounding::PowerOfTwo), Some(8000));
+	}
+
+	#[test]
+	pub fn rounding_rejects_lengths_beyond_u32() {
+		let long_loop = u32::MAX / 2 + 1;
+		assert_eq!(round_take_length_with(u32::MAX, long_loop, FinishRounding::Up), None);
+		assert_eq!(round_take_length_with(u32::MAX, long_loop, FinishRounding::Lenient), None);
+		assert_eq!(round_take_length_with(u32::MAX, long_loop, FinishRounding::PowerOfTwo), None);
+		assert_eq!(round_take_length_with(u32::MAX, long_loop, FinishRounding::Down), Some(long_loop));
+		assert_eq!(round_take_length_with(u32::MAX, 1, FinishRounding::Nearest), Some(u32::MAX));
+	}
+}
@@ -0,0 +1,252 @@
+//! Minimal Standard MIDI File support.
+
+use std::io::{Read, Write, Error, ErrorKind};
+use std::convert::TryInto;
+use crate::midi_message::MidiMessage;
+
+fn write_variable_length(buf: &mut Vec<u8>, value: u32) {
+	let mut bytes = [0u8; 5];
+	let mut n = 0;
+	let mut v = value;
+	loop {
+		bytes[n] = (v & 0x7F) as u8;
+		n += 1;
+		v >>= 7;
+		if v == 0 { break; }
+	}
+	for i in (0..n).rev() {
+		buf.push(if i > 0 { bytes[i] | 0x80 } else { bytes[i] });
+	}
+}
+
+/// Writes a format 0 Standard MIDI File. The `timestamp`s of `events` are given in ticks
+/// and must be sorted. The track ends at `end_tick`. `microseconds_per_quarter` must fit into
+/// 24 bits.
+pub fn write_smf(writer: &mut impl Write, ticks_per_quarter: u16, microseconds_per_quarter: u32, beats_per_bar: u8, events: &[MidiMessage], end_tick: u32) -> std::io::Result<()> {
+	debug_assert!(microseconds_per_quarter <= 0xFFFFFF);
+	let mut track = Vec::new();
+
+	// tempo
+	write_variable_length(&mut track, 0);
+	track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+	track.extend_from_slice(&microseconds_per_quarter.to_be_bytes()[1..4]);
+
+	// time signature: beats_per_bar / 4, 24 clocks per click, 8 32nds per quarter
+	write_variable_length(&mut track, 0);
+	track.extend_from_slice(&[0xFF, 0x58, 0x04, beats_per_bar, 2, 24, 8]);
+
+	let mut last_tick = 0;
+	for event in events {
+		assert!(event.timestamp >= last_tick);
+		write_variable_length(&mut track, event.timestamp - last_tick);
+		track.extend_from_slice(&event.data[0..event.datalen as usize]);
+		last_tick = event.timestamp;
+	}
+
+	write_variable_length(&mut track, end_tick.saturating_sub(last_tick));
+	track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+	let mut header = Vec::with_capacity(22);
+	header.extend_from_slice(b"MThd");
+	header.extend_from_slice(&6u32.to_be_bytes());
+	header.extend_from_slice(&0u16.to_be_bytes()); // format 0
+	header.extend_from_slice(&1u16.to_be_bytes()); // one track
+	header.extend_from_slice(&ticks_per_quarter.to_be_bytes());
+	header.extend_from_slice(b"MTrk");
+	header.extend_from_slice(&(track.len() as u32).to_be_bytes());
+
+	writer.write_all(&header)?;
+	writer.write_all(&track)
+}
+
+fn invalid(message: &str) -> Error {
+	Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_variable_length(data: &[u8], pos: &mut usize) -> std::io::Result<u32> {
+	let mut value = 0u32;
+	for _ in 0..4 {
+		let byte = *data.get(*pos).ok_or_else(|| invalid("truncated variable length quantity"))?;
+		*pos += 1;
+		value = (value << 7) | (byte & 0x7F) as u32;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+	Err(invalid("variable length quantity too long"))
+}
+
+/// Contents of a Standard MIDI File, with all tracks merged into one.
+pub struct Smf {
+	pub ticks_per_quarter: u16,
+	/// Channel messages with their `timestamp` in ticks, sorted by time.
+	pub events: Vec<MidiMessage>,
+	/// Position of the last End of Track event.
+	pub end_tick: u32
+}
+
+/// Reads a format 0 or 1 Standard MIDI File. Meta and system exclusive events are skipped.
+pub fn read_smf(reader: &mut impl Read) -> std::io::Result<Smf> {
+	let mut bytes = Vec::new();
+	reader.read_to_end(&mut bytes)?;
+
+	if bytes.len() < 14 || &bytes[0..4] != b"MThd" {
+		return Err(invalid("not a Standard MIDI File"));
+	}
+	let header_length = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+	let format = u16::from_be_bytes(bytes[8..10].try_into().unwrap());
+	let division = u16::from_be_bytes(bytes[12..14].try_into().unwrap());
+	if format > 1 {
+		return Err(invalid("only format 0 and 1 files are supported"));
+	}
+	if division & 0x8000 != 0 || division == 0 {
+		return Err(invalid("SMPTE time division is not supported"));
+	}
+
+	let mut events = Vec::new();
+	let mut end_tick = 0;
+	let mut pos = 8 + header_length;
+	while pos + 8 <= bytes.len() {
+		let chunk_length = u32::from_be_bytes(bytes[pos+4..pos+8].try_into().unwrap()) as usize;
+		let chunk = &bytes[pos+8 .. std::cmp::min(pos + 8 + chunk_length, bytes.len())];
+		if &bytes[pos..pos+4] == b"MTrk" {
+			end_tick = std::cmp::max(end_tick, read_track(chunk, &mut events)?);
+		}
+		pos += 8 + chunk_length;
+	}
+
+	// stable, so simultaneous events keep their order within the track
+	events.sort_by_key(|event| event.timestamp);
+	Ok(Smf { ticks_per_quarter: division, events, end_tick })
+}
+
+/// Appends the channel messages of `track` to `events` and returns the track's end tick.
+fn read_track(track: &[u8], events: &mut Vec<MidiMessage>) -> std::io::Result<u32> {
+	let mut pos = 0;
+	let mut tick = 0u32;
+	let mut running_status = None;
+	while pos < track.len() {
+		tick = tick.checked_add(read_variable_length(track, &mut pos)?).ok_or_else(|| invalid("track too long"))?;
+		let mut status = *track.get(pos).ok_or_else(|| invalid("truncated event"))?;
+		if status & 0x80 != 0 {
+			pos += 1;
+		}
+		else {
+			status = running_status.ok_or_else(|| invalid("running status without previous status"))?;
+		}
+
+		match status {
+			0xFF => {
+				let kind = *track.get(pos).ok_or_else(|| invalid("truncated meta event"))?;
+				pos += 1;
+				let length = read_variable_length(track, &mut pos)? as usize;
+				pos += length;
+				running_status = None;
+				if kind == 0x2F {
+					return Ok(tick);
+				}
+			}
+			0xF0 | 0xF7 => {
+				let length = read_variable_length(track, &mut pos)? as usize;
+				pos += length;
+				running_status = None;
+			}
+			0x80..=0xEF => {
+				let n_data = if status & 0xE0 == 0xC0 { 1 } else { 2 };
+				let data_bytes = track.get(pos..pos+n_data).ok_or_else(|| invalid("truncated channel message"))?;
+				let mut data = [status, 0, 0];
+				data[1..1+n_data].copy_from_slice(data_bytes);
+				events.push(MidiMessage { timestamp: tick, data, datalen: 1 + n_data as u8 });
+				pos += n_data;
+				running_status = Some(status);
+			}
+			_ => return Err(invalid("unexpected status byte"))
+		}
+	}
+	Ok(tick)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	pub fn variable_length_quantities_are_encoded_correctly() {
+		for (value, expected) in vec![
+			(0, vec![0x00]),
+			(0x40, vec![0x40]),
+			(0x7F, vec![0x7F]),
+			(0x80, vec![0x81, 0x00]),
+			(0x2000, vec![0xC0, 0x00]),
+			(0x3FFF, vec![0xFF, 0x7F]),
+			(0x100000, vec![0xC0, 0x80, 0x00]),
+			(0x0FFFFFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+		] {
+			let mut buf = Vec::new();
+			write_variable_length(&mut buf, value);
+			assert!(buf == expected, "encoding {:#x} gave {:x?}", value, buf);
+		}
+	}
+
+	#[test]
+	pub fn smf_is_written_correctly() {
+		let events = vec![
+			MidiMessage { timestamp: 0, data: [0x90, 60, 100], datalen: 3 },
+			MidiMessage { timestamp: 480, data: [0x80, 60, 0], datalen: 3 },
+		];
+		let mut file = Vec::new();
+		write_smf(&mut file, 480, 500000, 4, &events, 1920).unwrap();
+
+		let mut expected = vec![];
+		expected.extend_from_slice(b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x01\xE0");
+		expected.extend_from_slice(b"MTrk\x00\x00\x00\x1D");
+		expected.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]);
+		expected.extend_from_slice(&[0x00, 0xFF, 0x58, 0x04, 0x04, 0x02, 0x18, 0x08]);
+		expected.extend_from_slice(&[0x00, 0x90, 60, 100]);
+		expected.extend_from_slice(&[0x83, 0x60, 0x80, 60, 0]);
+		expected.extend_from_slice(&[0x8B, 0x20, 0xFF, 0x2F, 0x00]);
+		assert!(file == expected, "got {:x?}", file);
+	}
+
+	#[test]
+	pub fn written_files_can_be_read_back() {
+		let events = vec![
+			MidiMessage { timestamp: 0, data: [0x90, 60, 100], datalen: 3 },
+			MidiMessage { timestamp: 100, data: [0xC3, 5, 0], datalen: 2 },
+			MidiMessage { timestamp: 480, data: [0x80, 60, 0], datalen: 3 },
+		];
+		let mut file = Vec::new();
+		write_smf(&mut file, 480, 500000, 4, &events, 1920).unwrap();
+
+		let smf = read_smf(&mut &file[..]).unwrap();
+		assert!(smf.ticks_per_quarter == 480);
+		assert!(smf.events == events);
+		assert!(smf.end_tick == 1920);
+	}
+
+	#[test]
+	pub fn tracks_are_merged_and_running_status_is_understood() {
+		let mut file = vec![];
+		file.extend_from_slice(b"MThd\x00\x00\x00\x06\x00\x01\x00\x02\x00\x60");
+		file.extend_from_slice(b"MTrk\x00\x00\x00\x0B");
+		file.extend_from_slice(&[0x00, 0x90, 60, 100, 0x10, 62, 100, 0x10, 0xFF, 0x2F, 0x00]);
+		file.extend_from_slice(b"MTrk\x00\x00\x00\x11");
+		file.extend_from_slice(&[0x08, 0xF0, 0x02, 0x01, 0xF7, 0x08, 0x80, 60, 0, 0x00, 0xB0, 7, 127, 0x00, 0xFF, 0x2F, 0x00]);
+
+		let smf = read_smf(&mut &file[..]).unwrap();
+		assert!(smf.ticks_per_quarter == 96);
+		assert!(smf.events == vec![
+			MidiMessage { timestamp: 0, data: [0x90, 60, 100], datalen: 3 },
+			MidiMessage { timestamp: 16, data: [0x90, 62, 100], datalen: 3 },
+			MidiMessage { timestamp: 16, data: [0x80, 60, 0], datalen: 3 },
+			MidiMessage { timestamp: 16, data: [0xB0, 7, 127], datalen: 3 },
+		]);
+		assert!(smf.end_tick == 32);
+	}
+
+	#[test]
+	pub fn garbage_is_rejected() {
+		assert!(read_smf(&mut &b"this is not a midi file"[..]).is_err());
+		assert!(read_smf(&mut &b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x01\xE0MTrk\x00\x00\x00\x02\x00\x42"[..]).is_err());
+	}
+}
@@ -0,0 +1,215 @@
+//! Minimal reading and writing of RIFF WAVE files.
+
+use std::io::{Read, Write, Error, ErrorKind};
+use std::convert::TryInto;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Largest factor by which `resample` may change the number of samples
+pub const MAX_RESAMPLING_RATIO: u32 = 32;
+
+fn invalid(message: &str) -> Error {
+	Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Writes `channels` as a 32-bit float WAVE file. All channels must have the same length.
+pub fn write_wav(writer: &mut impl Write, sample_rate: u32, channels: &[Vec<f32>]) -> std::io::Result<()> {
+	let n_channels = channels.len() as u16;
+	let n_frames = channels.first().map(|c| c.len()).unwrap_or(0);
+	assert!(channels.iter().all(|c| c.len() == n_frames));
+
+	let block_align = n_channels as u32 * 4;
+	let data_size = n_frames as u32 * block_align;
+
+	let mut header = Vec::with_capacity(44);
+	header.extend_from_slice(b"RIFF");
+	header.extend_from_slice(&(36 + data_size).to_le_bytes());
+	header.extend_from_slice(b"WAVE");
+	header.extend_from_slice(b"fmt ");
+	header.extend_from_slice(&16u32.to_le_bytes());
+	header.extend_from_slice(&FORMAT_IEEE_FLOAT.to_le_bytes());
+	header.extend_from_slice(&n_channels.to_le_bytes());
+	header.extend_from_slice(&sample_rate.to_le_bytes());
+	header.extend_from_slice(&(sample_rate * block_align).to_le_bytes());
+	header.extend_from_slice(&(block_align as u16).to_le_bytes());
+	header.extend_from_slice(&32u16.to_le_bytes());
+	header.extend_from_slice(b"data");
+	header.extend_from_slice(&data_size.to_le_bytes());
+	writer.write_all(&header)?;
+
+	let mut data = Vec::with_capacity(data_size as usize);
+	for i in 0..n_frames {
+		for channel in channels {
+			data.extend_from_slice(&channel[i].to_le_bytes());
+		}
+	}
+	writer.write_all(&data)
+}
+
+/// Reads a WAVE file with 16, 24 or 32 bit integer or 32 bit float samples.
+/// Returns the sample rate and the deinterleaved channels.
+pub fn read_wav(reader: &mut impl Read) -> std::io::Result<(u32, Vec<Vec<f32>>)> {
+	let mut bytes = Vec::new();
+	reader.read_to_end(&mut bytes)?;
+
+	if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+		return Err(invalid("not a RIFF WAVE file"));
+	}
+
+	let mut format = None;
+	let mut pos = 12;
+	while pos + 8 <= bytes.len() {
+		let chunk_id = &bytes[pos..pos+4];
+		let chunk_size = u32::from_le_bytes(bytes[pos+4..pos+8].try_into().unwrap()) as usize;
+		let chunk = &bytes[pos+8 .. std::cmp::min(pos + 8 + chunk_size, bytes.len())];
+
+		if chunk_id == b"fmt " {
+			if chunk.len() < 16 {
+				return Err(invalid("fmt chunk too short"));
+			}
+			let mut format_tag = u16::from_le_bytes(chunk[0..2].try_into().unwrap());
+			let n_channels = u16::from_le_bytes(chunk[2..4].try_into().unwrap()) as usize;
+			let sample_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+			let bits_per_sample = u16::from_le_bytes(chunk[14..16].try_into().unwrap());
+			if format_tag == FORMAT_EXTENSIBLE {
+				if chunk.len() < 26 {
+					return Err(invalid("extensible fmt chunk too short"));
+				}
+				// the first two bytes of the subformat GUID are the actual format tag
+				format_tag = u16::from_le_bytes(chunk[24..26].try_into().unwrap());
+			}
+			if n_channels == 0 {
+				return Err(invalid("file has no channels"));
+			}
+			if sample_rate == 0 {
+				return Err(invalid("invalid sample rate"));
+			}
+			format = Some((format_tag, n_channels, sample_rate, bits_per_sample));
+		}
+		else if chunk_id == b"data" {
+			let (format_tag, n_channels, sample_rate, bits_per_sample) = format.ok_or_else(|| invalid("data chunk before fmt chunk"))?;
+			let decode: fn(&[u8]) -> f32 = match (format_tag, bits_per_sample) {
+				(FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+				(FORMAT_PCM, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+				(FORMAT_PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+				(FORMAT_IEEE_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+				_ => return Err(invalid("unsupported sample format"))
+			};
+
+			let bytes_per_sample = bits_per_sample as usize / 8;
+			let n_frames = chunk.len() / (bytes_per_sample * n_channels);
+			let mut channels: Vec<Vec<f32>> = (0..n_channels).map(|_| Vec::with_capacity(n_frames)).collect();
+			for frame in chunk.chunks_exact(bytes_per_sample * n_channels) {
+				for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(bytes_per_sample)) {
+					channel.push(decode(sample));
+				}
+			}
+			return Ok((sample_rate, channels));
+		}
+
+		// chunks are padded to an even size
+		pos += 8 + chunk_size + (chunk_size & 1);
+	}
+
+	Err(invalid("no data chunk found"))
+}
+
+/// Whether `resample` can convert between the two rates without producing absurd amounts of data
+pub fn resampling_ratio_is_sane(from_rate: u32, to_rate: u32) -> bool {
+	let (from_rate, to_rate) = (from_rate as u64, to_rate as u64);
+	from_rate > 0 && to_rate > 0
+		&& to_rate <= from_rate * MAX_RESAMPLING_RATIO as u64
+		&& from_rate <= to_rate * MAX_RESAMPLING_RATIO as u64
+}
+
+/// Converts `samples` from `from_rate` to `to_rate` using linear interpolation. This is not
+/// band-limited, but good enough for importing loops that were recorded at a different rate.
+/// Check the rates with `resampling_ratio_is_sane` first.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+	if from_rate == to_rate || samples.is_empty() {
+		return samples.to_vec();
+	}
+	let n_out = (samples.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+	(0..n_out).map(|i| {
+		let pos = i as f64 * from_rate as f64 / to_rate as f64;
+		let index = pos as usize;
+		let frac = (pos - index as f64) as f32;
+		let a = samples[index];
+		let b = samples.get(index + 1).cloned().unwrap_or(a);
+		a + (b - a) * frac
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	pub fn written_files_can_be_read_back() {
+		let channels = vec![
+			vec![0.0, 0.5, -0.25, 1.0],
+			vec![-1.0, 0.125, 0.0, 0.75],
+		];
+		let mut file = Vec::new();
+		write_wav(&mut file, 48000, &channels).unwrap();
+		assert!(file.len() == 44 + 4 * 2 * 4);
+
+		let (sample_rate, result) = read_wav(&mut &file[..]).unwrap();
+		assert!(sample_rate == 48000);
+		assert!(result == channels);
+	}
+
+	#[test]
+	pub fn pcm16_files_can_be_read() {
+		let mut file = Vec::new();
+		file.extend_from_slice(b"RIFF");
+		file.extend_from_slice(&(36u32 + 8).to_le_bytes());
+		file.extend_from_slice(b"WAVEfmt ");
+		file.extend_from_slice(&16u32.to_le_bytes());
+		file.extend_from_slice(&1u16.to_le_bytes()); // PCM
+		file.extend_from_slice(&1u16.to_le_bytes()); // mono
+		file.extend_from_slice(&44100u32.to_le_bytes());
+		file.extend_from_slice(&88200u32.to_le_bytes());
+		file.extend_from_slice(&2u16.to_le_bytes());
+		file.extend_from_slice(&16u16.to_le_bytes());
+		file.extend_from_slice(b"data");
+		file.extend_from_slice(&8u32.to_le_bytes());
+		for sample in [0i16, 16384, -32768, 8192].iter() {
+			file.extend_from_slice(&sample.to_le_bytes());
+		}
+
+		let (sample_rate, result) = read_wav(&mut &file[..]).unwrap();
+		assert!(sample_rate == 44100);
+		assert!(result == vec![vec![0.0, 0.5, -1.0, 0.25]]);
+	}
+
+	#[test]
+	pub fn garbage_is_rejected() {
+		assert!(read_wav(&mut &b"this is not a wave file"[..]).is_err());
+	}
+
+	#[test]
+	pub fn zero_sample_rates_are_rejected() {
+		let mut file = Vec::new();
+		write_wav(&mut file, 0, &[vec![0.0, 0.5]]).unwrap();
+		assert!(read_wav(&mut &file[..]).is_err());
+	}
+
+	#[test]
+	pub fn absurd_resampling_ratios_are_recognized() {
+		assert!(resampling_ratio_is_sane(44100, 48000));
+		assert!(resampling_ratio_is_sane(8000, 192000));
+		assert!(!resampling_ratio_is_sane(1, 48000));
+		assert!(!resampling_ratio_is_sane(48000, 1));
+		assert!(!resampling_ratio_is_sane(0, 48000));
+	}
+
+	#[test]
+	pub fn resampling_interpolates_linearly() {
+		assert!(resample(&[0.0, 1.0, 0.0, -1.0], 1000, 2000) == vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]);
+		assert!(resample(&[0.0, 1.0, 0.0, -1.0], 2000, 1000) == vec![0.0, 0.0]);
+		assert!(resample(&[0.25, 0.5], 44100, 44100) == vec![0.25, 0.5]);
+	}
+}
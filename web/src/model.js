@@ -338,7 +338,7 @@ export function patch_array(array_to_patch, patches, clazz, parent_object) {
 		return;
 	}
 	for (let patch of patches) {
-		if (patch.delete === true) {
+		if (patch.deleted === true) {
 			let index = array_to_patch.findIndex( x => x.id === patch.id );
 			if (index != -1) {
 				array_to_patch.splice(index, 1);